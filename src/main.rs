@@ -1,34 +1,376 @@
 extern crate twister_core;
 #[macro_use] extern crate log;
 extern crate env_logger;
+extern crate mio;
 
-use std::time::Duration;
-use std::thread;
-use std::net::{TcpListener, TcpStream};
-use twister_core::connection::Connection;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::mem;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::task::{Wake, Waker};
+use std::time::{Duration, Instant};
+use std::vec;
+use mio::{Events, Interest, Poll, Registry, Token};
+use mio::net::{TcpListener, TcpStream};
+use twister_core::acl::Acl;
+use twister_core::auth::{Htpasswd, ProxyAuth};
+use twister_core::connection::{Connection, KeepAlive};
+use twister_core::parent::ParentProxy;
+use twister_core::pool::Pool;
+use twister_core::resolve::{self, CachingResolver, Resolver, SystemResolver, ThreadedResolver};
+use twister_core::rewrite::Rewrite;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The number of host names resolved at once
+const RESOLVER_THREADS: usize = 4;
+
+const LISTENER: Token = Token(0);
+
+/// Wakes the loop to poll the connections in its [`Woken`] list
+const WAKER: Token = Token(usize::MAX);
+
+/// Each connection has two tokens - One for the client's socket and
+/// one for the upstream socket
+fn client_token(id: usize) -> Token {
+    Token(id * 2 + 1)
+}
+
+fn upstream_token(id: usize) -> Token {
+    Token(id * 2 + 2)
+}
+
+fn connection_id(token: Token) -> usize {
+    (token.0 - 1) / 2
+}
+
+type SharedResolver = Arc<dyn Resolver + Send + Sync>;
+
+/// The connections woken by their resolvers, to be polled by the loop
+type Woken = Arc<Mutex<Vec<usize>>>;
+
+/// Wakes the loop to poll connection `id`
+struct ConnectionWaker {
+    id: usize,
+    woken: Woken,
+    waker: Arc<mio::Waker>,
+}
+
+impl Wake for ConnectionWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.lock().unwrap().push(self.id);
+        if let Err(e) = self.waker.wake() {
+            debug!("Couldn't wake the loop for connection {}: {}", self.id, e);
+        }
+    }
+}
+
+/// A connection to `dest` being made without blocking. Its addresses are
+/// tried in turn until one accepts.
+struct Dial {
+    dest: String,
+    addrs: vec::IntoIter<SocketAddr>,
+    stream: Option<TcpStream>,
+    error: io::Error,
+}
+
+impl Dial {
+    fn new(dest: &str, addrs: Vec<SocketAddr>) -> Dial {
+        Dial {
+            dest: dest.to_string(),
+            addrs: addrs.into_iter(),
+            stream: None,
+            error: io::Error::new(io::ErrorKind::NotFound, format!("{} has no addresses", dest)),
+        }
+    }
+
+    /// Fails with `WouldBlock` until the socket registered with `token`
+    /// is connected, which it's writable once it is
+    fn poll(&mut self, registry: &Registry, token: Token) -> Result<TcpStream, io::Error> {
+        loop {
+            if let Some(mut s) = self.stream.take() {
+                // The connect is still in progress while there's no error
+                // and no peer
+                let result = match s.take_error() {
+                    Ok(None) => s.peer_addr(),
+                    Ok(Some(e)) | Err(e) => Err(e),
+                };
+
+                match result {
+                    Ok(_) => return Ok(s),
+                    Err(ref e) if e.kind() == io::ErrorKind::NotConnected => {
+                        self.stream = Some(s);
+                        return Err(io::ErrorKind::WouldBlock.into());
+                    },
+                    Err(e) => {
+                        debug!("Couldn't connect to {}: {}", self.dest, e);
+                        registry.deregister(&mut s).ok();
+                        self.error = e;
+                    },
+                }
+            }
+
+            let addr = match self.addrs.next() {
+                Some(addr) => addr,
+                None => return Err(mem::replace(&mut self.error, io::ErrorKind::NotConnected.into())),
+            };
+
+            debug!("Connecting to {} at {}", self.dest, addr);
+            match TcpStream::connect(addr) {
+                Ok(mut s) => {
+                    registry.register(&mut s, token, Interest::READABLE | Interest::WRITABLE)?;
+                    self.stream = Some(s);
+                },
+                Err(e) => self.error = e,
+            }
+        }
+    }
+}
+
+type Connector = Box<dyn FnMut(&str, Option<&[SocketAddr]>) -> Result<TcpStream, io::Error>>;
+
+type Connections = HashMap<usize, Connection<TcpStream, Connector, TcpStream>>;
+
+/// Creates the connector for a connection. Upstream sockets are
+/// registered with the connection's upstream token, and go through
+/// `parent` if there is one. Destinations the ACL didn't resolve are
+/// resolved with `resolver`, which wakes `waker` once it's answered.
+fn connector(registry: Registry, token: Token, parent: Option<ParentProxy>, resolver: SharedResolver, waker: Waker) -> Connector {
+    let mut pending: Option<Dial> = None;
+
+    let direct = move |dest: &str, addrs: Option<&[SocketAddr]>| {
+        let mut dial = match pending.take() {
+            Some(dial) if dial.dest == dest => dial,
+            _ => {
+                let addrs = match addrs {
+                    Some(addrs) => addrs.to_vec(),
+                    None => resolve::socket_addrs(&*resolver, dest, &waker)?,
+                };
+                Dial::new(dest, addrs)
+            },
+        };
+
+        let result = dial.poll(&registry, token);
+        if let Err(ref e) = result {
+            if e.kind() == io::ErrorKind::WouldBlock {
+                pending = Some(dial);
+            }
+        }
+        result
+    };
+
+    match parent {
+        Some(parent) => Box::new(parent.connector(direct)),
+        None => Box::new(direct),
+    }
+}
+
+/// Polls connection `id`, removing it once it's finished
+fn drive(connections: &mut Connections, registry: &Registry, id: usize) {
+    let finished = match connections.get_mut(&id) {
+        Some(conn) => match conn.poll() {
+            Ok(None) => false,
+            Ok(Some(mut s)) => {
+                registry.deregister(&mut s).ok();
+                true
+            },
+            Err(e) => {
+                debug!("Connection {} failed: {}", id, e);
+                true
+            },
+        },
+        // Events can still arrive for a connection that's finished
+        None => false,
+    };
+
+    if finished {
+        debug!("Connection {} finished", id);
+        connections.remove(&id);
+    }
+}
 
 fn main() {
     env_logger::init().ok();
 
-    let listener = TcpListener::bind("127.0.0.1:8083").unwrap();
+    // Clients must authenticate when a htpasswd file is given
+    let auth = env::args().nth(1).map(|path| {
+        let htpasswd = Htpasswd::open(&path).unwrap_or_else(|e| panic!("Couldn't read {}: {}", path, e));
+        Arc::new(ProxyAuth::new("twister", htpasswd))
+    });
 
-    for stream in listener.incoming() {
-        let mut s = stream.unwrap();
-        s.set_nonblocking(true).unwrap();
-        debug!("Accepted connection");
-        let mut conn = Connection::new(s, |dest| {
-            println!("Connecting to {}", dest);
-            let mut s = TcpStream::connect(dest).unwrap();
-            s.set_nonblocking(true).unwrap();
-            s
-        });
+    let read_acl = |path: String| {
+        let rules = fs::read_to_string(&path).unwrap_or_else(|e| panic!("Couldn't read {}: {}", path, e));
+        rules.parse::<Acl>().unwrap_or_else(|_| panic!("Invalid rule in {}", path))
+    };
 
-        loop {
-            if let Some(_) = conn.poll().unwrap() {
-                break;
+    // Tunnels are restricted by the rules in $TWISTER_ACL, or to port
+    // 443 without it
+    let acl = Arc::new(env::var("TWISTER_ACL").map(read_acl).unwrap_or_default());
+
+    // Forwarded requests are restricted by the rules in
+    // $TWISTER_FORWARD_ACL, or to port 80 without it
+    let forward_acl = Arc::new(env::var("TWISTER_FORWARD_ACL").map(read_acl).unwrap_or_else(|_| Acl::default_forward()));
+
+    // Upstream connections go through $TWISTER_PARENT when it's set,
+    // authenticating with $TWISTER_PARENT_CREDENTIALS as `user:password`
+    let parent = env::var("TWISTER_PARENT").ok().map(|addr| {
+        let parent = ParentProxy::new(&addr);
+        match env::var("TWISTER_PARENT_CREDENTIALS") {
+            Ok(credentials) => {
+                let split = credentials.find(':').expect("TWISTER_PARENT_CREDENTIALS must be user:password");
+                parent.with_credentials(&credentials[..split], &credentials[split + 1..])
+            },
+            Err(_) => parent,
+        }
+    });
+
+    // Forwarded requests name the proxy as $TWISTER_PSEUDONYM in `Via`,
+    // and carry the client's address in the comma-separated headers
+    // listed in $TWISTER_FORWARDED
+    let rewrite = env::var("TWISTER_PSEUDONYM").map(|name| Rewrite::new(&name)).unwrap_or_default();
+    let rewrite = Arc::new(env::var("TWISTER_FORWARDED").unwrap_or_default().split(',').fold(rewrite, |rewrite, name| {
+        match &*name.trim().to_ascii_lowercase() {
+            "" => rewrite,
+            "x-forwarded-for" => rewrite.with_x_forwarded_for(),
+            "x-forwarded-proto" => rewrite.with_x_forwarded_proto(),
+            "forwarded" => rewrite.with_forwarded(),
+            _ => panic!("TWISTER_FORWARDED can't contain {}", name),
+        }
+    }));
+
+    // Client connections are kept open between requests for
+    // $TWISTER_IDLE_TIMEOUT seconds, for up to $TWISTER_MAX_REQUESTS
+    // requests
+    let mut keep_alive = KeepAlive::default();
+    if let Ok(secs) = env::var("TWISTER_IDLE_TIMEOUT") {
+        let secs = secs.parse().unwrap_or_else(|_| panic!("TWISTER_IDLE_TIMEOUT must be a number of seconds"));
+        keep_alive.idle_timeout = Duration::from_secs(secs);
+    }
+    if let Ok(max) = env::var("TWISTER_MAX_REQUESTS") {
+        keep_alive.max_requests = max.parse().unwrap_or_else(|_| panic!("TWISTER_MAX_REQUESTS must be a number"));
+    }
+
+    // Idle upstream connections are shared by every client, up to
+    // $TWISTER_MAX_IDLE_PER_HOST for each origin server
+    let mut pool = Pool::new();
+    if let Ok(max) = env::var("TWISTER_MAX_IDLE_PER_HOST") {
+        pool = pool.with_max_idle_per_host(max.parse().unwrap_or_else(|_| panic!("TWISTER_MAX_IDLE_PER_HOST must be a number")));
+    }
+    let pool = Arc::new(pool);
+
+    // Host names are resolved by the system on other threads, so the loop
+    // isn't held up, and cached for a minute
+    let resolver = Arc::new(CachingResolver::new(ThreadedResolver::new(SystemResolver, RESOLVER_THREADS)));
+
+    let mut poll = Poll::new().unwrap();
+    let mut listener = TcpListener::bind("127.0.0.1:8083".parse().unwrap()).unwrap();
+    poll.registry().register(&mut listener, LISTENER, Interest::READABLE).unwrap();
+
+    let waker = Arc::new(mio::Waker::new(poll.registry(), WAKER).unwrap());
+    let woken = Woken::default();
+
+    let mut events = Events::with_capacity(1024);
+    let mut connections = Connections::new();
+    let mut next_id = 0;
+
+    loop {
+        // Wake up in time to close the next idle connection, or time out
+        // the next upstream connection
+        let timeout = connections.values()
+            .filter_map(|conn| conn.deadline())
+            .min()
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+
+        if let Err(e) = poll.poll(&mut events, timeout) {
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
             }
+            panic!("Polling failed: {}", e);
+        }
+
+        for event in &events {
+            let id = match event.token() {
+                LISTENER => {
+                    loop {
+                        let (mut s, addr) = match listener.accept() {
+                            Ok(accepted) => accepted,
+                            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                            Err(e) => {
+                                debug!("Accept failed: {}", e);
+                                break;
+                            },
+                        };
+
+                        let id = next_id;
+                        next_id += 1;
+
+                        debug!("Accepted connection {}", id);
+                        if let Err(e) = poll.registry().register(&mut s, client_token(id), Interest::READABLE | Interest::WRITABLE) {
+                            debug!("Couldn't register connection {}: {}", id, e);
+                            continue;
+                        }
+
+                        let registry = poll.registry().try_clone().unwrap();
+                        let reregistry = poll.registry().try_clone().unwrap();
+                        let waker = Waker::from(Arc::new(ConnectionWaker {
+                            id,
+                            woken: woken.clone(),
+                            waker: waker.clone(),
+                        }));
+                        let connector = connector(registry, upstream_token(id), parent.clone(), resolver.clone(), waker.clone());
+                        let conn = Connection::new(s, connector)
+                            .with_acl(acl.clone())
+                            .with_forward_acl(forward_acl.clone())
+                            .with_resolver(resolver.clone())
+                            .with_waker(waker)
+                            .with_connect_timeout(CONNECT_TIMEOUT)
+                            .with_rewrite(rewrite.clone())
+                            .with_client_addr(addr.ip())
+                            .with_keep_alive(keep_alive)
+                            // Pooled sockets are still registered for the
+                            // connection that last used them
+                            .with_pool(pool.clone(), move |s: &mut TcpStream| {
+                                reregistry.reregister(s, upstream_token(id), Interest::READABLE | Interest::WRITABLE)
+                            });
+                        let conn = match auth {
+                            Some(ref auth) => conn.with_auth(auth.clone()),
+                            None => conn,
+                        };
+                        connections.insert(id, conn);
+                    }
+                    continue;
+                },
+                WAKER => {
+                    let ids = mem::take(&mut *woken.lock().unwrap());
+                    for id in ids {
+                        drive(&mut connections, poll.registry(), id);
+                    }
+                    continue;
+                },
+                token => connection_id(token),
+            };
 
-            thread::sleep(Duration::from_millis(5));
+            drive(&mut connections, poll.registry(), id);
         }
+
+        let now = Instant::now();
+        let expired: Vec<usize> = connections.iter()
+            .filter(|&(_, conn)| conn.deadline().is_some_and(|deadline| deadline <= now))
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in expired {
+            drive(&mut connections, poll.registry(), id);
+        }
+
+        pool.expire();
+        resolver.expire();
     }
 }
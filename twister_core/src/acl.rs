@@ -0,0 +1,426 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::task::Waker;
+
+use resolve::{split_dest, Resolver, SystemResolver};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Action {
+    Allow,
+    Deny,
+}
+
+/// The destination hosts a [`Rule`] applies to
+///
+/// [`Rule`]: struct.Rule.html
+#[derive(Debug, PartialEq, Clone)]
+pub enum HostPattern {
+    /// A case-insensitive hostname glob, where `*` matches any run of
+    /// characters and `?` matches one - E.g. `*.example.com`
+    Glob(String),
+    /// An address range - E.g. `10.0.0.0/8`. Hostnames are resolved to
+    /// match against it.
+    Cidr(IpAddr, u8),
+}
+
+/// Only the last `*` is backtracked to on a mismatch - Whatever an
+/// earlier one matched, the later one can match instead - so this takes
+/// at most `pattern.len() * text.len()` steps.
+fn glob_matches(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // The position after the last `*`, and where its match ends
+    let mut star = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(&b'*') => {
+                p += 1;
+                star = Some((p, t));
+            },
+            Some(&c) if c == b'?' || c.eq_ignore_ascii_case(&text[t]) => {
+                p += 1;
+                t += 1;
+            },
+            _ => match star {
+                Some((after, end)) => {
+                    p = after;
+                    t = end + 1;
+                    star = Some((after, t));
+                },
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+fn in_cidr(addr: &IpAddr, network: &IpAddr, prefix: u8) -> bool {
+    fn masked(bytes: &[u8], prefix: u8) -> Vec<u8> {
+        bytes.iter().enumerate().map(|(i, byte)| {
+            let bits = (prefix as usize).saturating_sub(i * 8).min(8);
+            if bits == 0 { 0 } else { byte & (0xff_u8 << (8 - bits)) }
+        }).collect()
+    }
+
+    match (addr, network) {
+        (&IpAddr::V4(a), &IpAddr::V4(n)) => masked(&a.octets(), prefix) == masked(&n.octets(), prefix),
+        (&IpAddr::V6(a), &IpAddr::V6(n)) => masked(&a.octets(), prefix) == masked(&n.octets(), prefix),
+        (&IpAddr::V6(a), &IpAddr::V4(_)) => a.to_ipv4_mapped().is_some_and(|a| in_cidr(&IpAddr::V4(a), network, prefix)),
+        _ => false,
+    }
+}
+
+/// A rule deciding whether connections to matching destinations are
+/// allowed
+#[derive(Debug, PartialEq, Clone)]
+pub struct Rule {
+    pub action: Action,
+    pub host: HostPattern,
+    /// The inclusive range of ports the rule applies to
+    pub ports: (u16, u16),
+}
+
+impl Rule {
+    pub fn new(action: Action, host: HostPattern, ports: (u16, u16)) -> Rule {
+        Rule {
+            action: action,
+            host: host,
+            ports: ports,
+        }
+    }
+
+    /// `addrs` resolves `host`, and is only called for an address
+    /// range. A `Deny` rule matches if any of the addresses are in its
+    /// range, but an `Allow` rule only matches if all of them are.
+    fn matches(&self, host: &str, port: u16, addrs: &mut dyn FnMut() -> Result<Vec<IpAddr>, io::Error>) -> Result<bool, io::Error> {
+        if port < self.ports.0 || port > self.ports.1 {
+            return Ok(false);
+        }
+
+        match self.host {
+            HostPattern::Glob(ref pattern) => Ok(glob_matches(pattern.as_bytes(), host.as_bytes())),
+            HostPattern::Cidr(ref network, prefix) => {
+                let addrs = addrs()?;
+                let mut in_range = addrs.iter().map(|addr| in_cidr(addr, network, prefix));
+                Ok(match self.action {
+                    Action::Deny => in_range.any(|x| x),
+                    Action::Allow => !addrs.is_empty() && in_range.all(|x| x),
+                })
+            },
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct InvalidRule;
+
+/// Parses rules in the form `<allow|deny> <host> <ports>`. The host is a
+/// glob or a CIDR range, and the ports are a port, an inclusive range or
+/// `*` - E.g. `deny 10.0.0.0/8 *` or `allow *.example.com 8000-8999`.
+impl FromStr for Rule {
+    type Err = InvalidRule;
+
+    fn from_str(s: &str) -> Result<Rule, InvalidRule> {
+        let parts = s.split_whitespace().collect::<Vec<_>>();
+        if parts.len() != 3 {
+            return Err(InvalidRule);
+        }
+
+        let action = match &*parts[0].to_ascii_lowercase() {
+            "allow" => Action::Allow,
+            "deny" => Action::Deny,
+            _ => return Err(InvalidRule),
+        };
+
+        let host = match parts[1].find('/') {
+            Some(split) => {
+                let network = parts[1][..split].parse::<IpAddr>().map_err(|_| InvalidRule)?;
+                let prefix = parts[1][split + 1..].parse::<u8>().map_err(|_| InvalidRule)?;
+                let max = if network.is_ipv4() { 32 } else { 128 };
+                if prefix > max {
+                    return Err(InvalidRule);
+                }
+                HostPattern::Cidr(network, prefix)
+            },
+            None => HostPattern::Glob(parts[1].to_string()),
+        };
+
+        let ports = match parts[2].find('-') {
+            _ if parts[2] == "*" => (0, 65535),
+            Some(split) => (
+                parts[2][..split].parse().map_err(|_| InvalidRule)?,
+                parts[2][split + 1..].parse().map_err(|_| InvalidRule)?,
+            ),
+            None => {
+                let port = parts[2].parse().map_err(|_| InvalidRule)?;
+                (port, port)
+            },
+        };
+
+        Ok(Rule::new(action, host, ports))
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let action = match self.action {
+            Action::Allow => "allow",
+            Action::Deny => "deny",
+        };
+
+        match self.host {
+            HostPattern::Glob(ref pattern) => write!(f, "{} {} ", action, pattern)?,
+            HostPattern::Cidr(ref network, prefix) => write!(f, "{} {}/{} ", action, network, prefix)?,
+        }
+
+        match self.ports {
+            (0, 65535) => write!(f, "*"),
+            (from, to) if from == to => write!(f, "{}", from),
+            (from, to) => write!(f, "{}-{}", from, to),
+        }
+    }
+}
+
+/// An ordered list of rules for the destinations of tunnels and
+/// forwarded requests. The first rule to match decides, and destinations
+/// matching no rule are denied.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Acl(Vec<Rule>);
+
+impl Acl {
+    pub fn new(rules: Vec<Rule>) -> Acl {
+        Acl(rules)
+    }
+
+    /// Allows port 80 only. The default for forwarded requests, whose
+    /// targets are `http` URLs. See [`Acl::default`] for tunnels.
+    ///
+    /// [`Acl::default`]: #impl-Default-for-Acl
+    pub fn default_forward() -> Acl {
+        Acl(vec![Rule::new(Action::Allow, HostPattern::Glob("*".to_string()), (80, 80))])
+    }
+
+    /// Decides whether a connection to `dest`, a `host:port`, is allowed.
+    /// Host names are resolved with the system's resolver, if a rule
+    /// needs their addresses, and are denied if they can't be.
+    ///
+    /// # Return Value
+    /// The action, along with the rule that decided it. There's no rule
+    /// when no rule matched, `dest` isn't a valid `host:port` or its host
+    /// couldn't be resolved.
+    pub fn check(&self, dest: &str) -> (Action, Option<&Rule>) {
+        match self.check_with(dest, &SystemResolver, Waker::noop()) {
+            Ok(decision) => (decision.action, decision.rule),
+            Err(_) => (Action::Deny, None),
+        }
+    }
+
+    /// Decides whether a connection to `dest` is allowed, like [`check`],
+    /// resolving host names with `resolver`.
+    ///
+    /// # Return Value
+    /// The [`Decision`], or the error resolving the host if a rule
+    /// needed its addresses. That's `WouldBlock` if `resolver` can't
+    /// answer yet, in which case `waker` is woken once it can and the
+    /// check should be made again.
+    ///
+    /// [`check`]: #method.check
+    /// [`Decision`]: struct.Decision.html
+    pub fn check_with<R: Resolver + ?Sized>(&self, dest: &str, resolver: &R, waker: &Waker) -> Result<Decision<'_>, io::Error> {
+        let (host, port) = match split_dest(dest) {
+            Some(parts) => parts,
+            None => return Ok(Decision { action: Action::Deny, rule: None, addrs: None }),
+        };
+
+        let mut resolved = None;
+        for rule in &self.0 {
+            let mut addrs = || {
+                if resolved.is_none() {
+                    resolved = Some(match host.parse::<IpAddr>() {
+                        Ok(addr) => vec![addr],
+                        Err(_) => resolver.resolve(host, waker)?.addrs,
+                    });
+                }
+                Ok(resolved.clone().unwrap_or_default())
+            };
+
+            if rule.matches(host, port, &mut addrs)? {
+                return Ok(Decision::new(rule.action, Some(rule), resolved, port));
+            }
+        }
+
+        Ok(Decision::new(Action::Deny, None, resolved, port))
+    }
+}
+
+/// The outcome of checking a destination against an [`Acl`]
+///
+/// [`Acl`]: struct.Acl.html
+#[derive(Debug, PartialEq, Clone)]
+pub struct Decision<'a> {
+    pub action: Action,
+    /// The rule that decided, or `None` if no rule matched
+    pub rule: Option<&'a Rule>,
+    /// The destination's addresses, if a rule needed them. An allowed
+    /// connection should be made to one of these, rather than resolving
+    /// the host again, so it goes where the rules were checked.
+    pub addrs: Option<Vec<SocketAddr>>,
+}
+
+impl<'a> Decision<'a> {
+    fn new(action: Action, rule: Option<&'a Rule>, addrs: Option<Vec<IpAddr>>, port: u16) -> Decision<'a> {
+        Decision {
+            action: action,
+            rule: rule,
+            addrs: addrs.map(|addrs| addrs.into_iter().map(|addr| SocketAddr::new(addr, port)).collect()),
+        }
+    }
+}
+
+/// Parses a rule per line. Blank lines and `#` comments are ignored.
+impl FromStr for Acl {
+    type Err = InvalidRule;
+
+    fn from_str(s: &str) -> Result<Acl, InvalidRule> {
+        s.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::parse)
+            .collect::<Result<Vec<_>, _>>()
+            .map(Acl::new)
+    }
+}
+
+/// Allows port 443 only
+impl Default for Acl {
+    fn default() -> Acl {
+        Acl(vec![Rule::new(Action::Allow, HostPattern::Glob("*".to_string()), (443, 443))])
+    }
+}
+
+#[cfg(test)]
+mod acl_should {
+    use super::*;
+
+    fn acl(rules: &[&str]) -> Acl {
+        Acl::new(rules.iter().map(|rule| rule.parse().unwrap()).collect())
+    }
+
+    #[test]
+    fn allow_only_port_443_by_default() {
+        assert_eq!(Action::Allow, Acl::default().check("example.com:443").0);
+        assert_eq!(Action::Allow, Acl::default().check("[::1]:443").0);
+        assert_eq!((Action::Deny, None), Acl::default().check("example.com:22"));
+        assert_eq!((Action::Deny, None), Acl::default().check("example.com"));
+        assert_eq!((Action::Deny, None), Acl::default().check("::1:443"));
+    }
+
+    #[test]
+    fn allow_only_port_80_for_forwarded_requests_by_default() {
+        assert_eq!(Action::Allow, Acl::default_forward().check("example.com:80").0);
+        assert_eq!((Action::Deny, None), Acl::default_forward().check("example.com:443"));
+        assert_eq!((Action::Deny, None), Acl::default_forward().check("example.com:8080"));
+    }
+
+    #[test]
+    fn match_the_first_rule() {
+        let acl = acl(&["deny admin.example.com *", "allow *.EXAMPLE.com 8000-8999", "allow * 443"]);
+
+        let (action, rule) = acl.check("admin.example.com:443");
+        assert_eq!(Action::Deny, action);
+        assert_eq!("deny admin.example.com *", rule.unwrap().to_string());
+
+        let (action, rule) = acl.check("www.example.com:8080");
+        assert_eq!(Action::Allow, action);
+        assert_eq!("allow *.EXAMPLE.com 8000-8999", rule.unwrap().to_string());
+
+        assert_eq!(Action::Deny, acl.check("example.com:8080").0);
+        assert_eq!(Action::Allow, acl.check("example.com:443").0);
+    }
+
+    #[test]
+    fn match_globs() {
+        for &(pattern, text) in &[("*", ""), ("a*b?d", "aXXbcd"), ("*.example.com", "a.b.example.com"), ("**a", "ba")] {
+            assert!(glob_matches(pattern.as_bytes(), text.as_bytes()), "{} {}", pattern, text);
+        }
+
+        for &(pattern, text) in &[("?", ""), ("a*b", "ab_"), ("*.example.com", "example.com"), ("a?", "a")] {
+            assert!(!glob_matches(pattern.as_bytes(), text.as_bytes()), "{} {}", pattern, text);
+        }
+
+        // Backtracking to every `*` would take far too long
+        let (pattern, text) = ("*a".repeat(20) + "b", "a".repeat(10000));
+        assert!(!glob_matches(pattern.as_bytes(), text.as_bytes()));
+    }
+
+    #[test]
+    fn match_address_ranges() {
+        let acl = acl(&["deny 10.0.0.0/8 *", "deny fc00::/7 *", "allow 192.168.1.0/24 22", "allow * 443"]);
+
+        assert_eq!(Action::Deny, acl.check("10.1.2.3:443").0);
+        assert_eq!(Action::Deny, acl.check("[fd00::1]:443").0);
+        assert_eq!(Action::Deny, acl.check("[::ffff:10.0.0.1]:443").0);
+        assert_eq!(Action::Allow, acl.check("11.1.2.3:443").0);
+        assert_eq!(Action::Allow, acl.check("192.168.1.200:22").0);
+        assert_eq!(Action::Deny, acl.check("192.168.2.1:22").0);
+    }
+
+    #[test]
+    fn match_resolved_hostnames() {
+        let acl = acl(&["deny 127.0.0.0/8 *", "allow * *"]);
+        assert_eq!(Action::Deny, acl.check("localhost:443").0);
+    }
+
+    #[test]
+    fn match_hostnames_resolved_by_a_resolver() {
+        struct Pending;
+
+        impl Resolver for Pending {
+            fn resolve(&self, _: &str, _: &Waker) -> Result<::resolve::Resolved, io::Error> {
+                Err(io::ErrorKind::WouldBlock.into())
+            }
+        }
+
+        let acl = acl(&["allow www.example.com 443", "deny 192.0.2.0/24 *", "allow * *"]);
+        let hosts = "192.0.2.1 internal.example.com\n192.0.2.100 www.example.com\n198.51.100.1 public.example.com"
+            .parse::<::resolve::StaticHosts>()
+            .unwrap();
+
+        let decision = acl.check_with("internal.example.com:443", &hosts, Waker::noop()).unwrap();
+        assert_eq!(Action::Deny, decision.action);
+        let decision = acl.check_with("public.example.com:443", &hosts, Waker::noop()).unwrap();
+        assert_eq!(Action::Allow, decision.action);
+        assert_eq!(Some(vec!["198.51.100.1:443".parse().unwrap()]), decision.addrs);
+
+        // Hosts are only resolved for the rules that need them
+        let decision = acl.check_with("www.example.com:443", &Pending, Waker::noop()).unwrap();
+        assert_eq!((Action::Allow, None), (decision.action, decision.addrs));
+        assert_eq!(io::ErrorKind::WouldBlock, acl.check_with("internal.example.com:443", &Pending, Waker::noop()).unwrap_err().kind());
+    }
+
+    #[test]
+    fn fail_when_an_address_range_needs_an_unresolvable_host() {
+        let acl = acl(&["deny 192.0.2.0/24 *", "allow * *"]);
+        let hosts = ::resolve::StaticHosts::new();
+
+        assert_eq!(io::ErrorKind::NotFound, acl.check_with("unknown.example.com:443", &hosts, Waker::noop()).unwrap_err().kind());
+        assert_eq!((Action::Deny, None), acl.check("unknown.invalid:443"));
+    }
+
+    #[test]
+    fn parse_a_rule_per_line() {
+        let acl = "# Rules\n\ndeny 10.0.0.0/8 *\n  allow * 443\n".parse::<Acl>().unwrap();
+        assert_eq!(acl, self::acl(&["deny 10.0.0.0/8 *", "allow * 443"]));
+        assert_eq!(Err(InvalidRule), "allow * 443\nallow *\n".parse::<Acl>());
+    }
+
+    #[test]
+    fn reject_invalid_rules() {
+        for rule in &["allow", "permit * 443", "allow * 443 80", "allow 10.0.0.0/33 *", "allow * 70000", "allow * a-b"] {
+            assert_eq!(Err(InvalidRule), rule.parse::<Rule>(), "{}", rule);
+        }
+    }
+}
@@ -0,0 +1,477 @@
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{self, Instant, Sleep};
+
+use acl::Acl;
+use auth::ProxyAuth;
+use connection::{Connection, KeepAlive};
+use pool::Pool;
+use resolve::{Resolver, SystemResolver, ThreadedResolver};
+use rewrite::Rewrite;
+use stream::HalfClose;
+
+/// The waker of the task driving a connection, shared with the
+/// connection's streams
+type SharedWaker = Arc<Mutex<Option<Waker>>>;
+
+type Connector<U> = Box<dyn FnMut(&str, Option<&[SocketAddr]>) -> Result<Compat<U>, io::Error> + Send>;
+
+/// The number of host names resolved at once for connections without
+/// their own resolver
+const RESOLVER_THREADS: usize = 4;
+
+/// The resolver connections share unless they're given one. It resolves
+/// on its own threads, so lookups don't block the runtime's.
+fn default_resolver() -> Arc<dyn Resolver + Send + Sync> {
+    static RESOLVER: OnceLock<Arc<dyn Resolver + Send + Sync>> = OnceLock::new();
+    RESOLVER.get_or_init(|| Arc::new(ThreadedResolver::new(SystemResolver, RESOLVER_THREADS))).clone()
+}
+
+/// Wakes whichever task last polled the connection
+struct TaskWaker(SharedWaker);
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if let Some(ref waker) = *self.0.lock().unwrap() {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+fn with_waker<R, F>(waker: &SharedWaker, f: F) -> Result<R, io::Error>
+    where F: FnOnce(&mut Context) -> Poll<Result<R, io::Error>>
+{
+    let waker = waker.lock().unwrap().clone().expect("polled outside of a task");
+    match f(&mut Context::from_waker(&waker)) {
+        Poll::Ready(result) => result,
+        Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+    }
+}
+
+/// Adapts an `AsyncRead + AsyncWrite` stream to `Read + Write`. An
+/// operation that would be pending fails with `WouldBlock` instead, and
+/// the task driving the connection is woken once it can progress.
+pub struct Compat<T> {
+    inner: T,
+    waker: SharedWaker,
+}
+
+impl<T> Compat<T> {
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: AsyncRead + Unpin> Read for Compat<T> {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, io::Error> {
+        let inner = &mut self.inner;
+        with_waker(&self.waker, |cx| {
+            let mut buffer = ReadBuf::new(buffer);
+            Pin::new(inner).poll_read(cx, &mut buffer).map_ok(|()| buffer.filled().len())
+        })
+    }
+}
+
+impl<T: AsyncWrite + Unpin> Write for Compat<T> {
+    fn write(&mut self, buffer: &[u8]) -> Result<usize, io::Error> {
+        let inner = &mut self.inner;
+        with_waker(&self.waker, |cx| Pin::new(inner).poll_write(cx, buffer))
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        let inner = &mut self.inner;
+        with_waker(&self.waker, |cx| Pin::new(inner).poll_flush(cx))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> HalfClose for Compat<T> {
+    fn close_write(&mut self) -> Result<(), io::Error> {
+        let inner = &mut self.inner;
+        with_waker(&self.waker, |cx| Pin::new(inner).poll_shutdown(cx))
+    }
+}
+
+/// Adapts an async connector to the one a [`Connection`] expects. While
+/// the connection is still being made, the connector fails with
+/// `WouldBlock` and the connection calls it again later.
+///
+/// [`Connection`]: ../connection/struct.Connection.html
+fn connector<C, Fut, U>(mut connect: C, waker: SharedWaker) -> Connector<U>
+    where C: FnMut(&str, Option<&[SocketAddr]>) -> Fut + Send + 'static,
+          Fut: Future<Output = Result<U, io::Error>> + Send + 'static,
+          U: 'static,
+{
+    let mut pending: Option<Pin<Box<Fut>>> = None;
+
+    Box::new(move |dest, addrs| {
+        let mut future = pending.take().unwrap_or_else(|| Box::pin(connect(dest, addrs)));
+        let result = with_waker(&waker, |cx| future.as_mut().poll(cx));
+
+        match result {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => pending = Some(future),
+            _ => {},
+        }
+
+        result.map(|upstream| Compat {
+            inner: upstream,
+            waker: waker.clone(),
+        })
+    })
+}
+
+/// A [`Connection`] driven by an async runtime, such as tokio. The
+/// future completes with the client's stream once the connection is
+/// finished with.
+///
+/// # Examples
+/// ```no_compile
+/// let conn = AsyncConnection::new(client, |dest: &str, addrs: Option<&[SocketAddr]>| {
+///     let addrs = addrs.map(<[_]>::to_vec);
+///     let dest = dest.to_string();
+///     async move {
+///         match addrs {
+///             Some(addrs) => TcpStream::connect(&*addrs).await,
+///             None => TcpStream::connect(dest).await,
+///         }
+///     }
+/// });
+/// tokio::spawn(conn);
+/// ```
+///
+/// [`Connection`]: ../connection/struct.Connection.html
+pub struct AsyncConnection<S, U>
+    where S: AsyncRead + AsyncWrite + Unpin,
+          U: AsyncRead + AsyncWrite + Unpin,
+{
+    connection: Connection<Compat<S>, Connector<U>, Compat<U>>,
+    waker: SharedWaker,
+    /// Wakes the task at the connection's deadline
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S, U> AsyncConnection<S, U>
+    where S: AsyncRead + AsyncWrite + Unpin,
+          U: AsyncRead + AsyncWrite + Unpin + 'static,
+{
+    /// Creates a connection for the client `stream`. `connect` is called
+    /// with the `host:port` the client wants, and the addresses the
+    /// [`Acl`] approved if it resolved the host, and returns a future
+    /// that connects to it.
+    ///
+    /// [`Acl`]: ../acl/struct.Acl.html
+    pub fn new<C, Fut>(stream: S, connect: C) -> AsyncConnection<S, U>
+        where C: FnMut(&str, Option<&[SocketAddr]>) -> Fut + Send + 'static,
+              Fut: Future<Output = Result<U, io::Error>> + Send + 'static,
+    {
+        let waker = SharedWaker::default();
+        let stream = Compat {
+            inner: stream,
+            waker: waker.clone(),
+        };
+
+        // The resolver wakes the task through the shared waker, as the
+        // streams do
+        let connection = Connection::new(stream, connector(connect, waker.clone()))
+            .with_resolver(default_resolver())
+            .with_waker(Waker::from(Arc::new(TaskWaker(waker.clone()))));
+
+        AsyncConnection {
+            connection: connection,
+            waker: waker,
+            sleep: None,
+        }
+    }
+
+    /// Requires the client to authenticate before the connection is used.
+    /// See [`ProxyAuth`].
+    ///
+    /// [`ProxyAuth`]: ../auth/struct.ProxyAuth.html
+    pub fn with_auth(self, auth: Arc<ProxyAuth>) -> AsyncConnection<S, U> {
+        AsyncConnection {
+            connection: self.connection.with_auth(auth),
+            ..self
+        }
+    }
+
+    /// Restricts the destinations the client can tunnel to. See [`Acl`].
+    ///
+    /// [`Acl`]: ../acl/struct.Acl.html
+    pub fn with_acl(self, acl: Arc<Acl>) -> AsyncConnection<S, U> {
+        AsyncConnection {
+            connection: self.connection.with_acl(acl),
+            ..self
+        }
+    }
+
+    /// Restricts the destinations the client can forward requests to.
+    /// See [`Acl`].
+    ///
+    /// [`Acl`]: ../acl/struct.Acl.html
+    pub fn with_forward_acl(self, acl: Arc<Acl>) -> AsyncConnection<S, U> {
+        AsyncConnection {
+            connection: self.connection.with_forward_acl(acl),
+            ..self
+        }
+    }
+
+    /// Resolves the host names the [`Acl`] needs addresses for. Without
+    /// this, they're resolved by the system on threads shared by every
+    /// connection. See [`Resolver`].
+    ///
+    /// [`Acl`]: ../acl/struct.Acl.html
+    /// [`Resolver`]: ../resolve/trait.Resolver.html
+    pub fn with_resolver(self, resolver: Arc<dyn Resolver + Send + Sync>) -> AsyncConnection<S, U> {
+        AsyncConnection {
+            connection: self.connection.with_resolver(resolver),
+            ..self
+        }
+    }
+
+    /// Sets how forwarded requests and responses are rewritten. See
+    /// [`Rewrite`].
+    ///
+    /// [`Rewrite`]: ../rewrite/struct.Rewrite.html
+    pub fn with_rewrite(self, rewrite: Arc<Rewrite>) -> AsyncConnection<S, U> {
+        AsyncConnection {
+            connection: self.connection.with_rewrite(rewrite),
+            ..self
+        }
+    }
+
+    /// The client's address, which is passed on in forwarded requests
+    /// if the [`Rewrite`] adds it
+    ///
+    /// [`Rewrite`]: ../rewrite/struct.Rewrite.html
+    pub fn with_client_addr(self, client: IpAddr) -> AsyncConnection<S, U> {
+        AsyncConnection {
+            connection: self.connection.with_client_addr(client),
+            ..self
+        }
+    }
+
+    /// Limits how long, and for how many requests, the client's
+    /// connection is kept open. See [`KeepAlive`].
+    ///
+    /// [`KeepAlive`]: ../connection/struct.KeepAlive.html
+    pub fn with_keep_alive(self, keep_alive: KeepAlive) -> AsyncConnection<S, U> {
+        AsyncConnection {
+            connection: self.connection.with_keep_alive(keep_alive),
+            ..self
+        }
+    }
+
+    /// Limits how long checking the [`Acl`] and connecting upstream may
+    /// take, after which the client is sent `504 Gateway Timeout`.
+    ///
+    /// [`Acl`]: ../acl/struct.Acl.html
+    pub fn with_connect_timeout(self, timeout: Duration) -> AsyncConnection<S, U> {
+        AsyncConnection {
+            connection: self.connection.with_connect_timeout(timeout),
+            ..self
+        }
+    }
+
+    /// Reuses idle upstream connections from `pool`, which can be shared
+    /// with other connections. See [`Pool`].
+    ///
+    /// [`Pool`]: ../pool/struct.Pool.html
+    pub fn with_pool(self, pool: Arc<Pool<Compat<U>>>) -> AsyncConnection<S, U> {
+        // A pooled connection wakes the task that last used it until
+        // it's given this one's waker
+        let waker = self.waker.clone();
+        AsyncConnection {
+            connection: self.connection.with_pool(pool, move |upstream: &mut Compat<U>| {
+                upstream.waker = waker.clone();
+                Ok(())
+            }),
+            ..self
+        }
+    }
+}
+
+impl<S, U> Future for AsyncConnection<S, U>
+    where S: AsyncRead + AsyncWrite + Unpin,
+          U: AsyncRead + AsyncWrite + Unpin + 'static,
+{
+    type Output = Result<S, io::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        *this.waker.lock().unwrap() = Some(cx.waker().clone());
+
+        match this.connection.poll() {
+            Ok(Some(stream)) => return Poll::Ready(Ok(stream.into_inner())),
+            Ok(None) => {},
+            Err(e) => return Poll::Ready(Err(e)),
+        }
+
+        // Nothing else wakes the task when the connection's idle or
+        // connect timeout passes
+        let deadline = match this.connection.deadline() {
+            Some(deadline) => Instant::from_std(deadline),
+            None => {
+                this.sleep = None;
+                return Poll::Pending;
+            },
+        };
+
+        let sleep = this.sleep.get_or_insert_with(|| Box::pin(time::sleep_until(deadline)));
+        if sleep.deadline() != deadline {
+            sleep.as_mut().reset(deadline);
+        }
+
+        if sleep.as_mut().poll(cx).is_ready() {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod async_connection_should {
+    use super::*;
+    use std::str;
+
+    /// An in-memory stream that's pending before each chunk of its input.
+    /// Once its input runs out it reports EOF, or is pending for good if
+    /// it's held open.
+    struct Stream {
+        input: Vec<Vec<u8>>,
+        output: Arc<Mutex<Vec<u8>>>,
+        ready: bool,
+        held_open: bool,
+    }
+
+    impl Stream {
+        fn new(input: &[&[u8]]) -> (Stream, Arc<Mutex<Vec<u8>>>) {
+            let output = Arc::new(Mutex::new(vec![]));
+            let stream = Stream {
+                input: input.iter().rev().map(|chunk| chunk.to_vec()).collect(),
+                output: output.clone(),
+                ready: false,
+                held_open: false,
+            };
+
+            (stream, output)
+        }
+    }
+
+    impl AsyncRead for Stream {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context, buffer: &mut ReadBuf) -> Poll<io::Result<()>> {
+            if self.input.is_empty() && self.held_open {
+                return Poll::Pending;
+            }
+
+            if !self.ready {
+                self.ready = true;
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+
+            self.ready = false;
+            if let Some(chunk) = self.input.pop() {
+                buffer.put_slice(&chunk);
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for Stream {
+        fn poll_write(self: Pin<&mut Self>, _: &mut Context, buffer: &[u8]) -> Poll<io::Result<usize>> {
+            self.output.lock().unwrap().extend(buffer);
+            Poll::Ready(Ok(buffer.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// A future that's pending once before completing
+    struct Delayed<T>(Option<T>, bool);
+
+    impl<T: Unpin> Future for Delayed<T> {
+        type Output = T;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+            if !self.1 {
+                self.1 = true;
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+
+            Poll::Ready(self.0.take().unwrap())
+        }
+    }
+
+    fn run<F: Future>(future: F) -> F::Output {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+        let timeout = {
+            let _runtime = runtime.enter();
+            time::timeout(Duration::from_secs(5), future)
+        };
+        runtime.block_on(timeout).expect("timed out")
+    }
+
+    #[test]
+    fn be_spawnable() {
+        fn assert_send<T: Send + 'static>() {}
+        assert_send::<AsyncConnection<Stream, Stream>>();
+    }
+
+    #[test]
+    fn tunnel_through_an_async_upstream() {
+        let (client, client_output) = Stream::new(&[b"CONNECT source:443 HTTP/1.1\r\n\r\n", b"ping"]);
+        let (upstream, upstream_output) = Stream::new(&[b"pong"]);
+        let mut upstream = Some(upstream);
+
+        run(AsyncConnection::new(client, move |dest: &str, _: Option<&[SocketAddr]>| {
+            assert_eq!("source:443", dest);
+            Delayed(Some(Ok(upstream.take().unwrap())), false)
+        })).unwrap();
+
+        assert_eq!("HTTP/1.1 200 OK\r\n\r\npong", str::from_utf8(&client_output.lock().unwrap()).unwrap());
+        assert_eq!("ping", str::from_utf8(&upstream_output.lock().unwrap()).unwrap());
+    }
+
+    #[test]
+    fn respond_bad_gateway_when_connecting_fails() {
+        let (client, client_output) = Stream::new(&[b"GET http://source/ HTTP/1.1\r\n\r\n"]);
+
+        run(AsyncConnection::new(client, |_: &str, _: Option<&[SocketAddr]>| -> Delayed<Result<Stream, io::Error>> {
+            Delayed(Some(Err(io::ErrorKind::ConnectionRefused.into())), false)
+        })).unwrap();
+
+        assert_eq!("HTTP/1.1 502 Bad Gateway\r\n\r\n", str::from_utf8(&client_output.lock().unwrap()).unwrap());
+    }
+
+    #[test]
+    fn close_idle_connections_once_the_timeout_passes() {
+        let (mut client, client_output) = Stream::new(&[b"GET http://source/ HTTP/1.1\r\n\r\n"]);
+        client.held_open = true;
+        let (upstream, _) = Stream::new(&[b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"]);
+        let mut upstream = Some(upstream);
+
+        let keep_alive = KeepAlive { idle_timeout: Duration::from_millis(10), ..KeepAlive::default() };
+        run(AsyncConnection::new(client, move |_: &str, _: Option<&[SocketAddr]>| {
+            Delayed(Some(Ok(upstream.take().unwrap())), false)
+        }).with_keep_alive(keep_alive)).unwrap();
+
+        assert!(str::from_utf8(&client_output.lock().unwrap()).unwrap().starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use bcrypt;
+use sha1::{Digest, Sha1};
+use twister_http::{headers, Header, StatusCode};
+
+use response;
+
+/// A source of user credentials for proxy authentication
+pub trait CredentialStore {
+    /// Returns `true` if `password` is `user`'s password
+    fn verify(&self, user: &str, password: &str) -> bool;
+}
+
+/// Compares without exiting early, so the time taken doesn't reveal how
+/// much of a secret was guessed correctly
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Credentials held in memory as plain text
+#[derive(Default)]
+pub struct MemoryStore(HashMap<String, String>);
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    pub fn insert(&mut self, user: &str, password: &str) {
+        self.0.insert(user.to_string(), password.to_string());
+    }
+}
+
+impl CredentialStore for MemoryStore {
+    fn verify(&self, user: &str, password: &str) -> bool {
+        self.0.get(user)
+            .map(|expected| constant_time_eq(expected.as_bytes(), password.as_bytes()))
+            .unwrap_or(false)
+    }
+}
+
+/// Credentials in the format of an Apache htpasswd file. Each line is
+/// `user:hash`, where the hash is either bcrypt (`$2y$...`) or SHA-1
+/// (`{SHA}...`). Users with other kinds of hash can never authenticate.
+pub struct Htpasswd(HashMap<String, String>);
+
+impl Htpasswd {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Htpasswd, io::Error> {
+        fs::read_to_string(path).map(|contents| Htpasswd::parse(&contents))
+    }
+
+    /// Parses the contents of a htpasswd file. Blank lines, comments
+    /// and lines without a `:` are ignored.
+    pub fn parse(contents: &str) -> Htpasswd {
+        let users = contents.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let mut parts = line.splitn(2, ':');
+                match (parts.next(), parts.next()) {
+                    (Some(user), Some(hash)) => Some((user.to_string(), hash.to_string())),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        Htpasswd(users)
+    }
+}
+
+impl CredentialStore for Htpasswd {
+    fn verify(&self, user: &str, password: &str) -> bool {
+        let hash = match self.0.get(user) {
+            Some(hash) => hash,
+            None => return false,
+        };
+
+        if hash.starts_with("$2") {
+            bcrypt::verify(password, hash).unwrap_or(false)
+        }
+        else if let Some(expected) = hash.strip_prefix("{SHA}") {
+            let digest = BASE64.encode(Sha1::digest(password.as_bytes()));
+            constant_time_eq(digest.as_bytes(), expected.as_bytes())
+        }
+        else {
+            debug!("Unsupported password hash for {}", user);
+            false
+        }
+    }
+}
+
+/// Decodes the user and password from a `Proxy-Authorization` value that
+/// uses the `Basic` scheme - E.g. `Basic dXNlcjpwYXNz`
+fn basic_credentials(value: &[u8]) -> Option<(String, String)> {
+    let value = str::from_utf8(value).ok()?.trim();
+    let split = value.find(' ')?;
+    let (scheme, token) = value.split_at(split);
+
+    if !scheme.eq_ignore_ascii_case("Basic") {
+        return None;
+    }
+
+    let decoded = String::from_utf8(BASE64.decode(token.trim()).ok()?).ok()?;
+    let split = decoded.find(':')?;
+    Some((decoded[..split].to_string(), decoded[split + 1..].to_string()))
+}
+
+/// Requires clients to authenticate to the proxy with `Basic`
+/// credentials in the `Proxy-Authorization` header.
+pub struct ProxyAuth {
+    realm: String,
+    store: Box<dyn CredentialStore + Send + Sync>,
+}
+
+impl ProxyAuth {
+    pub fn new<C>(realm: &str, store: C) -> ProxyAuth
+        where C: CredentialStore + Send + Sync + 'static
+    {
+        ProxyAuth {
+            realm: realm.to_string(),
+            store: Box::new(store),
+        }
+    }
+
+    /// Returns `true` if any `Proxy-Authorization` header in `headers`
+    /// has valid credentials
+    pub fn check(&self, headers: &[Header]) -> bool {
+        headers::get_all(headers, b"Proxy-Authorization")
+            .filter_map(basic_credentials)
+            .any(|(user, password)| self.store.verify(&user, &password))
+    }
+
+    /// The response sent to a client that hasn't authenticated
+    pub fn challenge(&self) -> Vec<u8> {
+        let realm = self.realm.replace('\\', "\\\\").replace('"', "\\\"");
+        let authenticate = format!("Basic realm=\"{}\"", realm);
+        response::status(StatusCode(407), b"Proxy Authentication Required", &[
+            Header(b"Proxy-Authenticate", authenticate.as_bytes()),
+            Header(b"Content-Length", b"0"),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod auth_should {
+    use super::*;
+
+    fn auth() -> ProxyAuth {
+        let mut store = MemoryStore::new();
+        store.insert("user", "pa:ss");
+        ProxyAuth::new("twister", store)
+    }
+
+    #[test]
+    fn accept_valid_credentials() {
+        // user:pa:ss
+        assert!(auth().check(&[Header(b"proxy-authorization", b"basic dXNlcjpwYTpzcw==")]));
+        assert!(auth().check(&[Header(b"Host", b"example.com"),
+                               Header(b"Proxy-Authorization", b"Basic  dXNlcjpwYTpzcw== ")]));
+    }
+
+    #[test]
+    fn reject_invalid_credentials() {
+        assert!(!auth().check(&[]));
+        // user:wrong
+        assert!(!auth().check(&[Header(b"Proxy-Authorization", b"Basic dXNlcjp3cm9uZw==")]));
+        assert!(!auth().check(&[Header(b"Proxy-Authorization", b"Bearer dXNlcjpwYTpzcw==")]));
+        assert!(!auth().check(&[Header(b"Proxy-Authorization", b"Basic !!!")]));
+        assert!(!auth().check(&[Header(b"Authorization", b"Basic dXNlcjpwYTpzcw==")]));
+    }
+
+    #[test]
+    fn verify_htpasswd_hashes() {
+        let bcrypt = bcrypt::hash_with_result("password", 4).unwrap().format_for_version(bcrypt::Version::TwoY);
+        let htpasswd = Htpasswd::parse(&format!(
+            "# Users\n\
+             sha:{{SHA}}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\n\
+             bcrypt:{}\n\
+             md5:$apr1$bXW4m6F9$t5EQ7uv1wXrTTfIr7L4Pk/\n", bcrypt));
+
+        assert!(htpasswd.verify("sha", "password"));
+        assert!(!htpasswd.verify("sha", "Password"));
+        assert!(htpasswd.verify("bcrypt", "password"));
+        assert!(!htpasswd.verify("bcrypt", "Password"));
+        assert!(!htpasswd.verify("md5", "password"));
+        assert!(!htpasswd.verify("nobody", "password"));
+    }
+
+    #[test]
+    fn challenge_with_the_realm() {
+        let challenge = String::from_utf8(auth().challenge()).unwrap();
+        assert!(challenge.starts_with("HTTP/1.1 407 Proxy Authentication Required\r\n"));
+        assert!(challenge.contains("\r\nProxy-Authenticate: Basic realm=\"twister\"\r\n"));
+    }
+}
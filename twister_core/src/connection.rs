@@ -1,9 +1,31 @@
 use std::io::{self, Read, Write};
 use std::mem;
+use std::net::{IpAddr, SocketAddr};
 use std::str;
+use std::sync::Arc;
+use std::task::Waker;
+use std::time::{Duration, Instant};
 
-use twister_http::{HttpMethod, Header, Request};
-use twister_http::parser::HttpObjectParser;
+use twister_http::{HttpMethod, Header, Request, StatusCode};
+use twister_http::parser::{HttpObjectParser, ParseError, ResumeToken, Status};
+use twister_http::target::RequestTarget;
+
+use acl::{Acl, Action, Decision};
+use auth::ProxyAuth;
+use forward::{ForwardError, ForwardRequest, Forwarder};
+use parent::Refused;
+use pool::{Key, Pool};
+use resolve::{Resolver, SystemResolver};
+use response;
+use rewrite::Rewrite;
+use stream::HalfClose;
+use tunnel::Tunnel;
+
+/// The number of headers parsed without allocating
+const INLINE_HEADERS: usize = 32;
+
+/// The most headers a request may have before it's rejected
+const MAX_HEADERS: usize = 256;
 
 fn read_into<S: Read>(buffer: &mut Vec<u8>, from: &mut S) -> Result<u64, io::Error> {
     let mut tmp = [0_u8; 512];
@@ -12,94 +34,494 @@ fn read_into<S: Read>(buffer: &mut Vec<u8>, from: &mut S) -> Result<u64, io::Err
     Ok(n as _)
 }
 
+/// The response sent to a client when the upstream connection can't be
+/// made, or its destination can't be resolved. A parent proxy's refusal
+/// is passed on with its status.
+fn upstream_error_response(e: &io::Error) -> Vec<u8> {
+    if let Some(refused) = e.get_ref().and_then(|e| e.downcast_ref::<Refused>()) {
+        return refused.response();
+    }
+
+    match e.kind() {
+        io::ErrorKind::TimedOut => response::status(StatusCode(504), b"Gateway Timeout", &[]),
+        _ => response::status(StatusCode(502), b"Bad Gateway", &[]),
+    }
+}
+
+/// Limits on keeping a client's connection open for more requests
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeepAlive {
+    /// How long to wait for the client's next request
+    pub idle_timeout: Duration,
+    /// The most requests served on a single connection. `1` closes
+    /// every connection after its first response.
+    pub max_requests: usize,
+}
+
+impl Default for KeepAlive {
+    /// Waits 15 seconds between requests, for up to 100 requests
+    fn default() -> KeepAlive {
+        KeepAlive {
+            idle_timeout: Duration::from_secs(15),
+            max_requests: 100,
+        }
+    }
+}
+
 pub struct Connection<S, F, U>
     where S: Read + Write,
           U: Read + Write,
 {
     state: ConnectionState<S, U>,
     upstream_fn: F,
+    auth: Option<Arc<ProxyAuth>>,
+    acl: Arc<Acl>,
+    forward_acl: Arc<Acl>,
+    resolver: Arc<dyn Resolver + Send + Sync>,
+    waker: Waker,
+    rewrite: Arc<Rewrite>,
+    client: Option<IpAddr>,
+    keep_alive: KeepAlive,
+    requests: usize,
+    idle_since: Option<Instant>,
+    connect_timeout: Duration,
+    connect_since: Option<Instant>,
+    pool: Option<(Arc<Pool<U>>, Reuse<U>)>,
 }
 
+/// Prepares an idle connection taken from the pool to be used again
+type Reuse<U> = Box<dyn FnMut(&mut U) -> Result<(), io::Error> + Send>;
+
+/// A request sent on a pooled connection, and the addresses the [`Acl`]
+/// approved for it, kept to send it again if the connection turns out
+/// to have been closed
+///
+/// [`Acl`]: ../acl/struct.Acl.html
+type Retry = (ForwardRequest, Option<Vec<SocketAddr>>);
+
 enum ConnectionState<S: Read + Write, U: Read + Write> {
     Request(RequestHandler<S>),
     Response(ResponseHandler<S>),
-    AcceptingProxyRequest(ResponseHandler<S>, U),
-    TunnellingWrite(U, S),
-    TunnellingRead(S, U),
+    Checking(Target, S),
+    Connecting(Target, Option<Vec<SocketAddr>>, S),
+    AcceptingProxyRequest(ResponseHandler<S>, U, Vec<u8>),
+    Tunnelling(Tunnel<S, U>),
+    Forwarding(Forwarder<S, U>, Key, Option<Box<Retry>>),
     Done,
 }
 
 impl<S, F, U> Connection<S, F, U> 
-    where S: Read + Write,
-          F: FnMut(&str) -> U,
-          U: Read + Write
+    where S: Read + Write + HalfClose,
+          F: FnMut(&str, Option<&[SocketAddr]>) -> Result<U, io::Error>,
+          U: Read + Write + HalfClose,
 {
+    /// Creates a connection for the client `stream`. `f` is called with
+    /// the `host:port` the client wants, and connects to it. If the
+    /// [`Acl`] resolved the host, `f` is also given the addresses it
+    /// approved, and should connect to one of those rather than
+    /// resolving the host again. If `f` can't connect without blocking
+    /// it can fail with `WouldBlock`, and it'll be called again for the
+    /// same destination on a later poll.
+    ///
+    /// [`Acl`]: ../acl/struct.Acl.html
     pub fn new(stream: S, f: F) -> Connection<S, F, U> {
         Connection {
             state: ConnectionState::new(stream),
             upstream_fn: f,
+            auth: None,
+            acl: Arc::new(Acl::default()),
+            forward_acl: Arc::new(Acl::default_forward()),
+            resolver: Arc::new(SystemResolver),
+            waker: Waker::noop().clone(),
+            rewrite: Arc::new(Rewrite::default()),
+            client: None,
+            keep_alive: KeepAlive::default(),
+            requests: 0,
+            idle_since: None,
+            connect_timeout: Duration::from_secs(30),
+            connect_since: None,
+            pool: None,
         }
     }
 
+    /// Requires the client to authenticate before the connection is used.
+    /// See [`ProxyAuth`].
+    ///
+    /// [`ProxyAuth`]: ../auth/struct.ProxyAuth.html
+    pub fn with_auth(mut self, auth: Arc<ProxyAuth>) -> Connection<S, F, U> {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// Restricts the destinations the client can tunnel to. Without
+    /// this, only port 443 is allowed. See [`Acl`].
+    ///
+    /// [`Acl`]: ../acl/struct.Acl.html
+    pub fn with_acl(mut self, acl: Arc<Acl>) -> Connection<S, F, U> {
+        self.acl = acl;
+        self
+    }
+
+    /// Restricts the destinations the client can forward requests to.
+    /// Without this, only port 80 is allowed. See [`Acl`].
+    ///
+    /// [`Acl`]: ../acl/struct.Acl.html
+    pub fn with_forward_acl(mut self, acl: Arc<Acl>) -> Connection<S, F, U> {
+        self.forward_acl = acl;
+        self
+    }
+
+    /// Resolves the host names the [`Acl`] needs addresses for. Without
+    /// this, the system's resolver is used, which blocks. See
+    /// [`Resolver`].
+    ///
+    /// [`Acl`]: ../acl/struct.Acl.html
+    /// [`Resolver`]: ../resolve/trait.Resolver.html
+    pub fn with_resolver(mut self, resolver: Arc<dyn Resolver + Send + Sync>) -> Connection<S, F, U> {
+        self.resolver = resolver;
+        self
+    }
+
+    /// Is woken when the [`Resolver`] answers a lookup it failed with
+    /// `WouldBlock`, and the connection should be polled again. Without
+    /// this, the connection is only polled again when its streams are
+    /// ready.
+    ///
+    /// [`Resolver`]: ../resolve/trait.Resolver.html
+    pub fn with_waker(mut self, waker: Waker) -> Connection<S, F, U> {
+        self.waker = waker;
+        self
+    }
+
+    /// Sets how forwarded requests and responses are rewritten. Without
+    /// this, only hop-by-hop headers are removed and `Via` is added. See
+    /// [`Rewrite`].
+    ///
+    /// [`Rewrite`]: ../rewrite/struct.Rewrite.html
+    pub fn with_rewrite(mut self, rewrite: Arc<Rewrite>) -> Connection<S, F, U> {
+        self.rewrite = rewrite;
+        self
+    }
+
+    /// The client's address, which is passed on in forwarded requests
+    /// if the [`Rewrite`] adds it
+    ///
+    /// [`Rewrite`]: ../rewrite/struct.Rewrite.html
+    pub fn with_client_addr(mut self, client: IpAddr) -> Connection<S, F, U> {
+        self.client = Some(client);
+        self
+    }
+
+    /// Limits how long, and for how many requests, the client's
+    /// connection is kept open after a forwarded response. See
+    /// [`KeepAlive`].
+    ///
+    /// [`KeepAlive`]: struct.KeepAlive.html
+    pub fn with_keep_alive(mut self, keep_alive: KeepAlive) -> Connection<S, F, U> {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Limits how long checking the [`Acl`] and connecting upstream may
+    /// take, after which the client is sent `504 Gateway Timeout`.
+    /// Without this, the limit is 30 seconds.
+    ///
+    /// [`Acl`]: ../acl/struct.Acl.html
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Connection<S, F, U> {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Forwards requests on idle connections from `pool` when it has one
+    /// to the origin server, and returns upstream connections to it once
+    /// their responses have been read. `reuse` is called with each
+    /// connection taken from the pool before it's used - E.g. to register
+    /// it with an event loop on behalf of this connection. See [`Pool`].
+    ///
+    /// [`Pool`]: ../pool/struct.Pool.html
+    pub fn with_pool<R>(mut self, pool: Arc<Pool<U>>, reuse: R) -> Connection<S, F, U>
+        where R: FnMut(&mut U) -> Result<(), io::Error> + Send + 'static
+    {
+        self.pool = Some((pool, Box::new(reuse)));
+        self
+    }
+
+    /// Takes an idle connection to `key`'s origin server from the pool
+    fn take_pooled(&mut self, key: &Key) -> Option<U> {
+        let (ref pool, ref mut reuse) = *self.pool.as_mut()?;
+        let mut upstream = pool.take(key)?;
+
+        match reuse(&mut upstream) {
+            Ok(()) => {
+                debug!("Reusing connection to {}", key);
+                Some(upstream)
+            },
+            Err(e) => {
+                debug!("Couldn't reuse connection to {}: {}", key, e);
+                None
+            },
+        }
+    }
+
+    /// When the connection gives up waiting for the client's next
+    /// request, or for the upstream connection to be made. The
+    /// connection must be polled after this, even if its streams aren't
+    /// ready, to close it or answer the client.
+    ///
+    /// # Return Value
+    /// `None` unless the connection is idle between requests, or
+    /// connecting upstream
+    pub fn deadline(&self) -> Option<Instant> {
+        match (self.idle_since, self.connect_since, &self.state) {
+            (Some(since), _, ConnectionState::Request(handler)) if handler.1.is_empty() =>
+                Some(since + self.keep_alive.idle_timeout),
+            (_, Some(since), ConnectionState::Checking(..)) | (_, Some(since), ConnectionState::Connecting(..)) =>
+                Some(since + self.connect_timeout),
+            _ => None,
+        }
+    }
+
+    /// Makes as much progress as possible without blocking. Returns the
+    /// client's stream once the connection is finished with.
     pub fn poll(&mut self) -> Result<Option<S>, io::Error> {
-        
+        loop {
+            match self.step() {
+                Step::Done(stream) => return Ok(Some(stream)),
+                Step::Progress => continue,
+                Step::Blocked => return Ok(None),
+            }
+        }
+    }
+
+    fn step(&mut self) -> Step<S> {
+        let mut blocked = false;
+        let expired = self.deadline().is_some_and(|deadline| Instant::now() >= deadline);
+
         let next = match mem::replace(&mut self.state, ConnectionState::Done) {
+            ConnectionState::Request(handler) if expired => {
+                debug!("Connection idle for {:?}, closing", self.keep_alive.idle_timeout);
+                return Step::Done(handler.into_inner());
+            },
+
+            ConnectionState::Checking(target, stream) | ConnectionState::Connecting(target, _, stream) if expired => {
+                debug!("Connecting to {} took longer than {:?}", target.dest(), self.connect_timeout);
+                ConnectionState::Response(
+                    ResponseHandler::new(upstream_error_response(&io::ErrorKind::TimedOut.into()), stream))
+            },
+
             ConnectionState::Request(mut handler) => {
-                debug!("Reading initial request");
-                match handler.poll() {
+                debug!("Reading request");
+                match handler.poll(self.auth.as_deref(), &self.rewrite, self.client) {
                     Ok(RequestHandlerResult::MoreDataRequired) => 
                         ConnectionState::Request(handler),
 
-                    Ok(RequestHandlerResult::WantsProxy(dest, stream)) => 
-                        ConnectionState::AcceptingProxyRequest(
-                            ResponseHandler::new(b"HTTP/1.1 200 OK\r\n\r\n".to_vec(), stream), 
-                            (self.upstream_fn)(&dest)),
+                    Ok(RequestHandlerResult::WouldBlock) => {
+                        blocked = true;
+                        ConnectionState::Request(handler)
+                    },
+
+                    Ok(RequestHandlerResult::WantsProxy(dest, early, stream)) => {
+                        self.connect_since = Some(Instant::now());
+                        ConnectionState::Checking(Target::Tunnel(dest, early), stream)
+                    },
+
+                    Ok(RequestHandlerResult::WantsResource(mut request, stream)) => {
+                        self.requests += 1;
+                        if self.requests >= self.keep_alive.max_requests {
+                            debug!("Closing after {} requests", self.requests);
+                            request.keep_alive = false;
+                        }
+                        self.connect_since = Some(Instant::now());
+                        ConnectionState::Checking(Target::Resource(request), stream)
+                    },
+
+                    Ok(RequestHandlerResult::NotFound(stream)) => 
+                        ConnectionState::Response(
+                            ResponseHandler::new(response::status(StatusCode(404), b"Not Found", &[]), stream)),
+
+                    Ok(RequestHandlerResult::Unauthorized(challenge, stream)) => {
+                        debug!("Request not authorized");
+                        ConnectionState::Response(ResponseHandler::new(challenge, stream))
+                    },
+
+                    Ok(RequestHandlerResult::Invalid(stream)) => 
+                        ConnectionState::Response(
+                            ResponseHandler::new(response::status(StatusCode(400), b"Bad Request", &[]), stream)),
+
+                    Ok(RequestHandlerResult::Loop(stream)) => {
+                        info!("Request loop detected");
+                        ConnectionState::Response(
+                            ResponseHandler::new(response::status(StatusCode(508), b"Loop Detected", &[]), stream))
+                    },
+
+                    Ok(RequestHandlerResult::Malformed(e @ ParseError::TooManyHeaders(..), stream)) => {
+                        debug!("Malformed request: {}", e);
+                        ConnectionState::Response(
+                            ResponseHandler::new(
+                                response::status(StatusCode(431), b"Request Header Fields Too Large", &[]), stream))
+                    },
+
+                    Ok(RequestHandlerResult::Malformed(e, stream)) => {
+                        debug!("Malformed request: {}", e);
+                        ConnectionState::Response(
+                            ResponseHandler::new(response::status(StatusCode(400), b"Bad Request", &[]), stream))
+                    },
+
+                    _ => return Step::Done(handler.into_inner()),
+                }
+            },
+            ConnectionState::Checking(target, stream) => {
+                let acl = match target {
+                    Target::Tunnel(..) => &self.acl,
+                    Target::Resource(_) | Target::Retry(_) => &self.forward_acl,
+                };
 
-                    Ok(RequestHandlerResult::WantsResource(_, stream)) => 
+                match acl.check_with(target.dest(), &*self.resolver, &self.waker) {
+                    Ok(Decision { action: Action::Allow, addrs, .. }) =>
+                        ConnectionState::Connecting(target, addrs, stream),
+                    Ok(Decision { rule, .. }) => {
+                        match rule {
+                            Some(rule) => info!("Connection to {} denied by rule `{}`", target.dest(), rule),
+                            None => info!("Connection to {} denied, no rule allows it", target.dest()),
+                        }
                         ConnectionState::Response(
-                            ResponseHandler::new(b"HTTP/1.1 404 Not Found\r\n\r\n".to_vec(), stream)),
+                            ResponseHandler::new(response::status(StatusCode(403), b"Forbidden", &[]), stream))
+                    },
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        blocked = true;
+                        ConnectionState::Checking(target, stream)
+                    },
+                    Err(e) => {
+                        info!("Couldn't resolve {} to check it: {}", target.dest(), e);
+                        ConnectionState::Response(ResponseHandler::new(upstream_error_response(&e), stream))
+                    },
+                }
+            },
+
+            ConnectionState::Connecting(target, addrs, stream) => {
+                let pooled = match target {
+                    Target::Resource(ref request) => self.take_pooled(&request.key),
+                    Target::Tunnel(..) | Target::Retry(_) => None,
+                };
+
+                // The server may have closed a pooled connection since
+                // it was checked, so keep what's needed to try again
+                let retry = match (&pooled, &target) {
+                    (Some(_), Target::Resource(request)) if request.is_replayable() =>
+                        Some(Box::new((request.clone(), addrs.clone()))),
+                    _ => None,
+                };
 
-                    _ => return Ok(Some(handler.into_inner())),
+                match pooled.map(Ok).unwrap_or_else(|| (self.upstream_fn)(target.dest(), addrs.as_deref())) {
+                    Ok(upstream) => match target {
+                        Target::Tunnel(_, early) => 
+                            ConnectionState::AcceptingProxyRequest(
+                                ResponseHandler::new(response::status(StatusCode(200), b"OK", &[]), stream), 
+                                upstream,
+                                early),
+                        Target::Resource(request) | Target::Retry(request) => {
+                            let key = request.key.clone();
+                            ConnectionState::Forwarding(Forwarder::new(request, stream, upstream, self.rewrite.clone()), key, retry)
+                        },
+                    },
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        blocked = true;
+                        ConnectionState::Connecting(target, addrs, stream)
+                    },
+                    Err(e) => {
+                        debug!("Couldn't connect to {}: {}", target.dest(), e);
+                        ConnectionState::Response(ResponseHandler::new(upstream_error_response(&e), stream))
+                    },
                 }
             },
+
             ConnectionState::Response(mut handler) => {
                 match handler.poll() {
-                    Ok(ResponseHandlerResult::Done(stream)) => return Ok(Some(stream)),
+                    Ok(ResponseHandlerResult::Done(stream)) => return Step::Done(stream),
                     Ok(ResponseHandlerResult::NotDone) => ConnectionState::Response(handler),
-                    _ => return Ok(Some(handler.into_inner())),
+                    Ok(ResponseHandlerResult::WouldBlock) => {
+                        blocked = true;
+                        ConnectionState::Response(handler)
+                    },
+                    _ => return Step::Done(handler.into_inner()),
                 }
             },
 
-            ConnectionState::AcceptingProxyRequest(mut handler, upstream) => {
+            ConnectionState::AcceptingProxyRequest(mut handler, upstream, early) => {
                 match handler.poll() {
-                    Ok(ResponseHandlerResult::Done(stream)) => ConnectionState::TunnellingRead(stream, upstream),
-                    Ok(ResponseHandlerResult::NotDone) => ConnectionState::AcceptingProxyRequest(handler, upstream),
-                    _ => return Ok(Some(handler.into_inner())),
+                    Ok(ResponseHandlerResult::Done(stream)) =>
+                        ConnectionState::Tunnelling(Tunnel::new(stream, upstream).with_early_data(&early)),
+                    Ok(ResponseHandlerResult::NotDone) => ConnectionState::AcceptingProxyRequest(handler, upstream, early),
+                    Ok(ResponseHandlerResult::WouldBlock) => {
+                        blocked = true;
+                        ConnectionState::AcceptingProxyRequest(handler, upstream, early)
+                    },
+                    _ => return Step::Done(handler.into_inner()),
                 }
             },
 
-            ConnectionState::TunnellingRead(mut inside, mut outside) => {
-                match io::copy(&mut inside, &mut outside) {
-                    Ok(0) => return Ok(Some(inside)),
-                    Ok(_) => ConnectionState::TunnellingRead(inside, outside),
-                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => ConnectionState::TunnellingWrite(outside, inside),
-                    _ => return Ok(Some(inside)),
+            ConnectionState::Tunnelling(mut tunnel) => {
+                match tunnel.poll() {
+                    Ok(false) => {
+                        blocked = true;
+                        ConnectionState::Tunnelling(tunnel)
+                    },
+                    Ok(true) => return Step::Done(tunnel.into_inner()),
+                    Err(e) => {
+                        debug!("Tunnel failed: {}", e);
+                        return Step::Done(tunnel.into_inner());
+                    },
                 }
             },
 
-            ConnectionState::TunnellingWrite(mut outside, mut inside) => {
-                match io::copy(&mut outside, &mut inside) {
-                    Ok(0) => return Ok(Some(inside)),
-                    Ok(_) => ConnectionState::TunnellingWrite(outside, inside),
-                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => ConnectionState::TunnellingRead(inside, outside),
-                    _ => return Ok(Some(inside)),
+            ConnectionState::Forwarding(mut forwarder, key, retry) => {
+                match forwarder.poll() {
+                    Ok(Some(finished)) => {
+                        if let (true, Some((pool, _))) = (finished.upstream_reusable, self.pool.as_ref()) {
+                            debug!("Returning connection to {} to the pool", key);
+                            pool.put(key, forwarder.into_upstream());
+                        }
+
+                        match finished.pipelined {
+                            Some(pipelined) => {
+                                self.idle_since = Some(Instant::now());
+                                ConnectionState::Request(RequestHandler::with_buffered(finished.client, pipelined))
+                            },
+                            None => return Step::Done(finished.client),
+                        }
+                    },
+                    Ok(None) => {
+                        blocked = true;
+                        ConnectionState::Forwarding(forwarder, key, retry)
+                    },
+                    Err(e) => {
+                        debug!("Forwarding failed: {}", e);
+                        if !forwarder.failed_before_response() {
+                            return Step::Done(forwarder.into_inner());
+                        }
+
+                        match retry.map(|retry| *retry) {
+                            Some((request, addrs)) => {
+                                debug!("Retrying the request to {} on a new connection", key);
+                                self.connect_since = Some(Instant::now());
+                                ConnectionState::Connecting(Target::Retry(request), addrs, forwarder.into_inner())
+                            },
+                            None => ConnectionState::Response(ResponseHandler::new(upstream_error_response(&e), forwarder.into_inner())),
+                        }
+                    },
                 }
             },
             ConnectionState::Done => panic!("poll called on done!"),
         };
 
         self.state = next;
-        return Ok(None);
+
+        if blocked {
+            Step::Blocked
+        }
+        else {
+            Step::Progress
+        }
     }
 }
 
@@ -112,60 +534,91 @@ impl<S, U> ConnectionState<S, U>
     }
 }
 
+/// What the client wants an upstream connection for. A tunnel carries
+/// the bytes the client sent after its `CONNECT` request. A retry is a
+/// request that failed on a pooled connection, and is only sent on a
+/// new one.
+enum Target {
+    Tunnel(String, Vec<u8>),
+    Resource(ForwardRequest),
+    Retry(ForwardRequest),
+}
+
+impl Target {
+    fn dest(&self) -> &str {
+        match *self {
+            Target::Tunnel(ref dest, _) => dest,
+            Target::Resource(ref request) | Target::Retry(ref request) => &request.dest,
+        }
+    }
+}
+
+/// The outcome of a single step of a connection
+enum Step<S> {
+    Done(S),
+    Progress,
+    Blocked,
+}
+
 #[cfg_attr(test, derive(Debug, PartialEq))]
 enum RequestHandlerResult<S> {
     MoreDataRequired,
-    WantsProxy(String, S),
-    WantsResource(String, S),
-    Invalid,
+    WouldBlock,
+    WantsProxy(String, Vec<u8>, S),
+    WantsResource(ForwardRequest, S),
+    NotFound(S),
+    Unauthorized(Vec<u8>, S),
+    Malformed(ParseError, S),
+    Invalid(S),
+    Loop(S),
 }
 
 enum ResponseHandlerResult<S> {
     Done(S),
     NotDone,
+    WouldBlock,
 }
 
 struct ResponseHandler<S: Write>(Option<S>, io::Cursor<Vec<u8>>);
 
-struct RequestHandler<S: Read>(Option<S>, Vec<u8>);
+struct RequestHandler<S: Read>(Option<S>, Vec<u8>, ResumeToken);
 
 impl<S: Read> RequestHandler<S> {
     fn new(stream: S) -> RequestHandler<S> {
-        RequestHandler(Some(stream), vec![])
+        RequestHandler::with_buffered(stream, vec![])
     }
 
-    fn poll(&mut self) -> Result<RequestHandlerResult<S>, io::Error> {
-        let n = match read_into(&mut self.1, self.0.as_mut().unwrap()) {
-            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
-            Ok(n) => n,
-            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => 0,
-            Err(e) => return Err(e),
-        };
-
-        debug!("Read {} bytes of request", n);
+    /// `buffered` holds bytes already read from `stream` - E.g. The
+    /// start of a request pipelined after the previous one
+    fn with_buffered(stream: S, buffered: Vec<u8>) -> RequestHandler<S> {
+        RequestHandler(Some(stream), buffered, ResumeToken::new())
+    }
 
-        let mut headers = [Header::default(); 32];
-        let object = HttpObjectParser::new(&mut headers)
-            .parse::<Request>(&*self.1);
+    fn poll(&mut self, auth: Option<&ProxyAuth>, rewrite: &Rewrite, client: Option<IpAddr>) -> Result<RequestHandlerResult<S>, io::Error> {
+        // Buffered bytes may already hold a whole request
+        if self.2.offset() == self.1.len() {
+            let n = match read_into(&mut self.1, self.0.as_mut().unwrap()) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(RequestHandlerResult::WouldBlock),
+                Err(e) => return Err(e),
+            };
 
-        if object.is_none() {
-            debug!("Request not done: {}", ::std::str::from_utf8(&*self.1).unwrap());
-            return Ok(RequestHandlerResult::MoreDataRequired);
+            debug!("Read {} bytes of request", n);
         }
 
-        let object = object.unwrap();
-
-        debug!("Recieved request for {}", ::std::str::from_utf8(object.path).unwrap());
+        let mut headers = [Header::default(); INLINE_HEADERS];
+        let result = match HttpObjectParser::new(&mut headers).resume::<Request>(&self.1, &mut self.2) {
+            Err(ParseError::TooManyHeaders(_, count)) if count <= MAX_HEADERS => {
+                debug!("Request has {} headers, retrying", count);
+                let mut headers = vec![Header::default(); count];
+                let result = HttpObjectParser::new(&mut headers).resume::<Request>(&self.1, &mut self.2);
+                handle_request(result, &self.1, &mut self.0, auth, rewrite, client)
+            },
+            result => handle_request(result, &self.1, &mut self.0, auth, rewrite, client),
+        };
 
-        match object.method {
-            HttpMethod::Connect => 
-                Ok(RequestHandlerResult::WantsProxy(
-                    str::from_utf8(object.path).unwrap().to_string(), self.0.take().unwrap())),
-            HttpMethod::Get => 
-                Ok(RequestHandlerResult::WantsResource(
-                    str::from_utf8(object.path).unwrap().to_string(), self.0.take().unwrap())),
-            _ => Ok(RequestHandlerResult::Invalid)
-        }
+        Ok(result)
     }
 
     fn into_inner(mut self) -> S {
@@ -173,18 +626,70 @@ impl<S: Read> RequestHandler<S> {
     }
 }
 
+fn handle_request<'a, S>(result: Result<Status<Request<'a>>, ParseError>,
+                         buffer: &[u8],
+                         stream: &mut Option<S>,
+                         auth: Option<&ProxyAuth>,
+                         rewrite: &Rewrite,
+                         client: Option<IpAddr>) -> RequestHandlerResult<S>
+{
+    let object = match result {
+        Ok(Status::Complete(object)) => object,
+        Ok(Status::Partial) => {
+            debug!("Request not done after {} bytes", buffer.len());
+            return RequestHandlerResult::MoreDataRequired;
+        },
+        Err(e) => return RequestHandlerResult::Malformed(e, stream.take().unwrap()),
+    };
+
+    debug!("Recieved request for {}", ::std::str::from_utf8(object.path).unwrap());
+
+    if let Some(auth) = auth {
+        if !auth.check(object.headers) {
+            return RequestHandlerResult::Unauthorized(auth.challenge(), stream.take().unwrap());
+        }
+    }
+
+    match object.method {
+        HttpMethod::Connect => match RequestTarget::parse(object.path, &object.method) {
+            Ok(RequestTarget::Authority(authority)) =>
+                RequestHandlerResult::WantsProxy(authority.to_string(), object.body.to_vec(), stream.take().unwrap()),
+            _ => {
+                debug!("Invalid CONNECT target");
+                RequestHandlerResult::Invalid(stream.take().unwrap())
+            },
+        },
+        _ => match ForwardRequest::new(&object, rewrite, client) {
+            Ok(request) => RequestHandlerResult::WantsResource(request, stream.take().unwrap()),
+            Err(ForwardError::NotAbsolute) => RequestHandlerResult::NotFound(stream.take().unwrap()),
+            Err(ForwardError::Loop) => RequestHandlerResult::Loop(stream.take().unwrap()),
+            Err(e) => {
+                debug!("Can't forward request: {:?}", e);
+                RequestHandlerResult::Invalid(stream.take().unwrap())
+            },
+        },
+    }
+}
+
 impl<S: Write> ResponseHandler<S> {
     fn new(response: Vec<u8>, stream: S) -> ResponseHandler<S> {
         ResponseHandler(Some(stream), io::Cursor::new(response))
     }
 
     fn poll(&mut self) -> Result<ResponseHandlerResult<S>, io::Error> {
-        let n = io::copy(&mut self.1, self.0.as_mut().unwrap())?;
-        if n == 0 {
-            Ok(ResponseHandlerResult::Done(self.0.take().unwrap()))
+        let position = self.1.position() as usize;
+        if position == self.1.get_ref().len() {
+            return Ok(ResponseHandlerResult::Done(self.0.take().unwrap()));
         }
-        else {
-            Ok(ResponseHandlerResult::NotDone)
+
+        match self.0.as_mut().unwrap().write(&self.1.get_ref()[position..]) {
+            Ok(0) => Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                self.1.set_position((position + n) as u64);
+                Ok(ResponseHandlerResult::NotDone)
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(ResponseHandlerResult::WouldBlock),
+            Err(e) => Err(e),
         }
     }
 
@@ -196,8 +701,12 @@ impl<S: Write> ResponseHandler<S> {
 #[cfg(test)]
 mod connection_should {
     use super::*;
+    use std::collections::VecDeque;
     use std::io::Cursor;
     use std::cmp;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::Wake;
 
     struct Trickle<T>(T);
 
@@ -214,7 +723,19 @@ mod connection_should {
     impl<T: Read> Read for Trickle<T> {
         fn read(&mut self, buffer: &mut [u8]) -> Result<usize, io::Error> {
             let to_read = cmp::min(1, buffer.len());
-            return self.0.read(&mut buffer[..to_read]);
+            self.0.read(&mut buffer[..to_read])
+        }
+    }
+
+    impl<T: HalfClose> HalfClose for Trickle<T> {
+        fn close_write(&mut self) -> Result<(), io::Error> {
+            self.0.close_write()
+        }
+    }
+
+    impl HalfClose for Cursor<Vec<u8>> {
+        fn close_write(&mut self) -> Result<(), io::Error> {
+            Ok(())
         }
     }
 
@@ -229,119 +750,747 @@ mod connection_should {
         }
     }
 
-    enum StagedRead {
-        Connect(usize, Cursor<Vec<u8>>, Cursor<Vec<u8>>),
-        Request(Cursor<Vec<u8>>, Cursor<Vec<u8>>),
-        Done,
+    /// A stream that delivers its input in stages, blocking between
+    /// them, and reports EOF after the last. Its output is shared, so it
+    /// can be checked once the stream has been dropped.
+    struct StagedRead {
+        stages: VecDeque<Cursor<Vec<u8>>>,
+        output: Arc<Mutex<Vec<u8>>>,
     }
 
     impl StagedRead {
-        fn new() -> StagedRead {
-            StagedRead::Connect(
-                b"CONNECT source HTTP/1.0\r\n\r\n".len(),
-                Cursor::new(
-                    b"CONNECT source HTTP/1.0\r\n\
-                      \r\n\
-                      GET /index.html HTTP/1.0\r\n\
-                      \r\n".to_vec()),
-                Cursor::new(vec![])
-            )
-        }
-
-        fn input_buffer_mut(&mut self) -> &mut Cursor<Vec<u8>> {
-            match *self {
-                StagedRead::Connect(.., ref mut input) => input,
-                StagedRead::Request(_ , ref mut input) => input,
-                StagedRead::Done => panic!("Stream invalid"),
-            }
-        }
+        fn new(stages: &[&[u8]]) -> (StagedRead, Arc<Mutex<Vec<u8>>>) {
+            let output = Arc::new(Mutex::new(vec![]));
+            let stream = StagedRead {
+                stages: stages.iter().map(|stage| Cursor::new(stage.to_vec())).collect(),
+                output: output.clone(),
+            };
 
-        fn into_inner(mut self) -> (Cursor<Vec<u8>>, Cursor<Vec<u8>>) {
-            match mem::replace(&mut self, StagedRead::Done) {
-                StagedRead::Connect(_, input, output) => (input, output),
-                StagedRead::Request(input, output) => (input, output),
-                StagedRead::Done => panic!("Stream invalid"),
-            }
+            (stream, output)
         }
     }
 
     impl Read for StagedRead {
         fn read(&mut self, buffer: &mut [u8]) -> Result<usize, io::Error> {
-            let (result, next) = match mem::replace(self, StagedRead::Done) {
-                StagedRead::Connect(read, mut output, input) => {
-                    if read == 0 {
-                        (Err(io::ErrorKind::WouldBlock.into()), StagedRead::Request(output, input))
-                    }
-                    else {
-                        let to_read = cmp::min(read, buffer.len());
-                        let n = output.read(&mut buffer[..to_read])?;
-                        (Ok(n), StagedRead::Connect(read - n, output, input))
-                    }
-                },
-                StagedRead::Request(mut output, input) => (output.read(buffer), StagedRead::Request(output, input)),
-                StagedRead::Done => panic!("Stread invalid!"),
+            let n = match self.stages.front_mut() {
+                Some(stage) => stage.read(buffer)?,
+                None => return Ok(0),
             };
 
-            *self= next;
-            result
+            if n == 0 {
+                self.stages.pop_front();
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+
+            Ok(n)
+        }
+    }
+
+    impl HalfClose for StagedRead {
+        fn close_write(&mut self) -> Result<(), io::Error> {
+            Ok(())
         }
     }
 
     impl Write for StagedRead {
         fn write(&mut self, buffer: &[u8]) -> Result<usize, io::Error> {
-            self.input_buffer_mut().write(buffer)
+            self.output.lock().unwrap().extend(buffer);
+            Ok(buffer.len())
         }
 
         fn flush(&mut self) -> Result<(), io::Error> {
-            self.input_buffer_mut().flush()
+            Ok(())
+        }
+    }
+
+    /// A stream that would block, rather than report EOF, once its
+    /// input is exhausted
+    struct Pending(Cursor<Vec<u8>>, Vec<u8>);
+
+    impl Pending {
+        fn new(input: &[u8]) -> Pending {
+            Pending(Cursor::new(input.to_vec()), vec![])
+        }
+    }
+
+    impl Read for Pending {
+        fn read(&mut self, buffer: &mut [u8]) -> Result<usize, io::Error> {
+            match self.0.read(buffer)? {
+                0 => Err(io::ErrorKind::WouldBlock.into()),
+                n => Ok(n),
+            }
+        }
+    }
+
+    impl HalfClose for Pending {
+        fn close_write(&mut self) -> Result<(), io::Error> {
+            Ok(())
+        }
+    }
+
+    impl Write for Pending {
+        fn write(&mut self, buffer: &[u8]) -> Result<usize, io::Error> {
+            self.1.write(buffer)
+        }
+
+        fn flush(&mut self) -> Result<(), io::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn return_when_the_connection_would_block() {
+        let mut conn = Connection::new(Pending::new(b"CONNECT sou"), |_, _| -> Result<Pending, io::Error> {
+            panic!("Upstream requested for a partial request");
+        });
+        assert!(conn.poll().unwrap().is_none());
+
+        let mut conn = Connection::new(Pending::new(b"CONNECT source:443 HTTP/1.1\r\n\r\n"), |_, _| Ok(Pending::new(b"")));
+        assert!(conn.poll().unwrap().is_none());
+        match conn.state {
+            ConnectionState::Tunnelling(..) => {},
+            _ => panic!("Expected to be tunnelling"),
         }
     }
 
     #[test]
     fn handle_connect_request() {
-        let mut stream = Trickle(StagedRead::new());
-//        let mut stream = Trickle(Cursor::new(b"CONNECT source HTTP/1.0\r\n\r\n".to_vec()));
+        let (stream, _) = StagedRead::new(&[b"CONNECT source:443 HTTP/1.0\r\n\r\nGET /index.html HTTP/1.0\r\n\r\n"]);
         let mut handler = RequestHandler::new(stream);
 
+        let (dest, early) = loop {
+            match handler.poll(None, &Rewrite::default(), None).unwrap() {
+                RequestHandlerResult::MoreDataRequired => continue,
+                RequestHandlerResult::WouldBlock => continue,
+                RequestHandlerResult::WantsProxy(dest, early, _) => break (dest, early),
+                RequestHandlerResult::WantsResource(request, _) => panic!("Got WantsResource {}", request.dest),
+                RequestHandlerResult::NotFound(_) => panic!("Got NotFound"),
+                RequestHandlerResult::Unauthorized(..) => panic!("Got Unauthorized"),
+                RequestHandlerResult::Malformed(e, _) => panic!("Got Malformed {}", e),
+                RequestHandlerResult::Invalid(_) => panic!("Got Invalid"),
+                RequestHandlerResult::Loop(_) => panic!("Got Loop"),
+            }
+        };
+
+        assert_eq!("source:443", &*dest);
+        assert_eq!(&b"GET /index.html HTTP/1.0\r\n\r\n"[..], &*early);
+    }
+
+    #[test]
+    fn only_examine_new_request_bytes() {
+        let mut handler = RequestHandler::new(Trickle::new(Cursor::new(request_with_headers(4))));
+
+        loop {
+            match handler.poll(None, &Rewrite::default(), None).unwrap() {
+                RequestHandlerResult::MoreDataRequired => assert_eq!(handler.1.len(), handler.2.offset()),
+                RequestHandlerResult::WantsProxy(..) => break,
+                _ => panic!("Expected WantsProxy"),
+            }
+        }
+    }
+
+    #[test]
+    fn reject_malformed_request() {
+        let mut handler = RequestHandler::new(Cursor::new(b"\x16\x03\x01\x02\x00\x01\x00\x01".to_vec()));
+
+        match handler.poll(None, &Rewrite::default(), None).unwrap() {
+            RequestHandlerResult::Malformed(e, _) => assert_eq!(ParseError::InvalidMethod(0), e),
+            _ => panic!("Expected Malformed"),
+        }
+    }
+
+    fn request_with_headers(count: usize) -> Vec<u8> {
+        let mut request = b"CONNECT source:443 HTTP/1.1\r\n".to_vec();
+        for i in 0..count {
+            request.extend(format!("X-Header-{}: {}\r\n", i, i).as_bytes());
+        }
+        request.extend(b"\r\n");
+        request
+    }
+
+    #[test]
+    fn handle_request_with_many_headers() {
+        let mut handler = RequestHandler::new(Cursor::new(request_with_headers(INLINE_HEADERS + 1)));
+
         let dest = loop {
-            match handler.poll().unwrap() {
+            match handler.poll(None, &Rewrite::default(), None).unwrap() {
                 RequestHandlerResult::MoreDataRequired => continue,
-                RequestHandlerResult::WantsProxy(dest, _) => break dest,
-                RequestHandlerResult::WantsResource(dest, _) => panic!("Got WantsResource {}", dest),
-                RequestHandlerResult::Invalid => panic!("Got Invalid"),
+                RequestHandlerResult::WantsProxy(dest, ..) => break dest,
+                _ => panic!("Expected WantsProxy"),
             }
         };
 
-        assert_eq!("source", &*dest);
+        assert_eq!("source:443", &*dest);
     }
 
     #[test]
-    fn proxy_request() {
-        let upstream = b"Hello, World!".to_vec();
+    fn reject_connect_request_without_authority() {
+        for target in &["source", "source:", "/index.html", "[::1]", "user@source:443"] {
+            let request = format!("CONNECT {} HTTP/1.1\r\n\r\n", target);
+            let mut handler = RequestHandler::new(Cursor::new(request.into_bytes()));
+
+            match handler.poll(None, &Rewrite::default(), None).unwrap() {
+                RequestHandlerResult::Invalid(_) => {},
+                _ => panic!("Expected Invalid for {}", target),
+            }
+        }
+    }
+
+    #[test]
+    fn reject_request_with_too_many_headers() {
+        let mut handler = RequestHandler::new(Cursor::new(request_with_headers(MAX_HEADERS + 1)));
+
+        let count = loop {
+            match handler.poll(None, &Rewrite::default(), None).unwrap() {
+                RequestHandlerResult::MoreDataRequired => continue,
+                RequestHandlerResult::Malformed(ParseError::TooManyHeaders(_, count), _) => break count,
+                _ => panic!("Expected TooManyHeaders"),
+            }
+        };
+
+        assert_eq!(MAX_HEADERS + 1, count);
+    }
+
+    #[test]
+    fn respond_bad_request_to_malformed_request() {
+        let mut conn = Connection::new(Trickle::new(Cursor::new(b"GET / HTTP/1.1\r\nBad Header: 1\r\n\r\n".to_vec())), |_, _| -> Result<Cursor<Vec<u8>>, io::Error> {
+            panic!("Upstream requested for a malformed request");
+        });
+
+        let s: Trickle<Cursor<Vec<u8>>> = loop {
+            if let Some(stream) = conn.poll().unwrap() {
+                break stream;
+            }
+        };
+
+        let output = s.into_inner().into_inner();
+        assert!(str::from_utf8(&output).unwrap().ends_with("HTTP/1.1 400 Bad Request\r\n\r\n"));
+    }
+
+    #[test]
+    fn forward_absolute_form_request() {
+        let mut requested_upstream = None;
+
+        let (client, output) = StagedRead::new(&[b"GET http://example.com:8080/ HTTP/1.1\r\n\r\n"]);
+
+        {
+            let acl = Acl::new(vec!["allow example.com 8080".parse().unwrap()]);
+            let mut conn = Connection::new(Trickle::new(client), |dest, _| {
+                requested_upstream = Some(dest.to_string());
+                let (upstream, _) = StagedRead::new(&[b"HTTP/1.1 200 OK\r\nContent-Length: 13\r\n\r\nHello, World!"]);
+                Ok(Trickle::new(upstream))
+            }).with_forward_acl(Arc::new(acl));
+
+            while conn.poll().unwrap().is_none() {}
+        }
+
+        assert_eq!(Some("example.com:8080".to_string()), requested_upstream);
+        assert_eq!("HTTP/1.1 200 OK\r\nContent-Length: 13\r\nVia: 1.1 twister\r\n\r\nHello, World!",
+                   str::from_utf8(&output.lock().unwrap()).unwrap());
+    }
+
+    /// Forwards the requests the client sends, in `stages`, on one
+    /// connection. Each upstream connection responds with its number.
+    /// Returns what the client received, and what was sent on each
+    /// upstream connection.
+    fn forward_on_one_connection(stages: &[&[u8]], keep_alive: KeepAlive) -> (String, Vec<String>) {
+        let (client, output) = StagedRead::new(stages);
+        let mut upstreams = vec![];
+
+        {
+            let mut conn = Connection::new(client, |_, _| {
+                let response = format!("HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\n{}", upstreams.len() + 1);
+                let (upstream, sent) = StagedRead::new(&[response.as_bytes()]);
+                upstreams.push(sent);
+                Ok(upstream)
+            }).with_keep_alive(keep_alive);
+
+            while conn.poll().unwrap().is_none() {}
+        }
+
+        let sent = upstreams.iter().map(|sent| String::from_utf8(sent.lock().unwrap().clone()).unwrap()).collect();
+        let received = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        (received, sent)
+    }
+
+    #[test]
+    fn serve_more_requests_on_a_persistent_connection() {
+        let (output, upstreams) = forward_on_one_connection(
+            &[b"GET http://example.com/1 HTTP/1.1\r\n\r\n",
+              b"GET http://example.com/2 HTTP/1.1\r\n\r\n"],
+            KeepAlive::default());
+
+        assert_eq!(2, upstreams.len());
+        assert_eq!("HTTP/1.1 200 OK\r\nContent-Length: 1\r\nVia: 1.1 twister\r\n\r\n1\
+                    HTTP/1.1 200 OK\r\nContent-Length: 1\r\nVia: 1.1 twister\r\n\r\n2", output);
+    }
+
+    #[test]
+    fn answer_pipelined_requests_in_order() {
+        let (output, upstreams) = forward_on_one_connection(
+            &[b"GET http://example.com/1 HTTP/1.1\r\n\r\n\
+                POST http://example.com/2 HTTP/1.1\r\nContent-Length: 5\r\n\r\nHello\
+                GET http://example.com/3 HT",
+              b"TP/1.1\r\n\r\n"],
+            KeepAlive::default());
+
+        assert_eq!(vec!["GET /1 HTTP/1.1\r\nHost: example.com\r\nVia: 1.1 twister\r\n\r\n",
+                        "POST /2 HTTP/1.1\r\nHost: example.com\r\nContent-Length: 5\r\nVia: 1.1 twister\r\n\r\nHello",
+                        "GET /3 HTTP/1.1\r\nHost: example.com\r\nVia: 1.1 twister\r\n\r\n"],
+                   upstreams);
+        assert_eq!("HTTP/1.1 200 OK\r\nContent-Length: 1\r\nVia: 1.1 twister\r\n\r\n1\
+                    HTTP/1.1 200 OK\r\nContent-Length: 1\r\nVia: 1.1 twister\r\n\r\n2\
+                    HTTP/1.1 200 OK\r\nContent-Length: 1\r\nVia: 1.1 twister\r\n\r\n3", output);
+    }
+
+    #[test]
+    fn answer_bad_gateway_when_upstream_closes_without_responding() {
+        let (client, output) = StagedRead::new(&[b"GET http://example.com/ HTTP/1.1\r\n\r\n"]);
+        let mut conn = Connection::new(client, |_, _| Ok(StagedRead::new(&[]).0));
+        while conn.poll().unwrap().is_none() {}
+
+        let received = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(received.starts_with("HTTP/1.1 502 Bad Gateway\r\n"), "{}", received);
+    }
+
+    #[test]
+    fn reuse_pooled_upstream_connections() {
+        let pool = Arc::new(Pool::new());
+        let reused = Arc::new(AtomicUsize::new(0));
+        let mut connects = 0;
+        let mut sent = None;
+
+        let (client, output) = StagedRead::new(&[b"GET http://example.com/1 HTTP/1.1\r\n\r\n",
+                                                 b"GET http://EXAMPLE.com:80/2 HTTP/1.1\r\n\r\n"]);
+
+        {
+            let counter = reused.clone();
+            let mut conn = Connection::new(client, |_, _| {
+                connects += 1;
+                let (upstream, upstream_sent) = StagedRead::new(&[b"HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\n1",
+                                                                  b"HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\n2"]);
+                sent = Some(upstream_sent);
+                Ok(upstream)
+            }).with_pool(pool.clone(), move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            });
+
+            while conn.poll().unwrap().is_none() {}
+        }
+
+        assert_eq!(1, connects);
+        assert_eq!(1, reused.load(Ordering::SeqCst));
+        assert_eq!("GET /1 HTTP/1.1\r\nHost: example.com\r\nVia: 1.1 twister\r\n\r\n\
+                    GET /2 HTTP/1.1\r\nHost: EXAMPLE.com:80\r\nVia: 1.1 twister\r\n\r\n",
+                   str::from_utf8(&sent.unwrap().lock().unwrap()).unwrap());
+        assert!(str::from_utf8(&output.lock().unwrap()).unwrap().ends_with("\r\n\r\n2"));
+        assert_eq!(1, pool.idle(&Key::new("http", "example.com", 80)));
+    }
+
+    #[test]
+    fn retry_idempotent_requests_when_a_pooled_connection_was_closed() {
+        for &(request, connects, response) in &[(&b"GET http://example.com/ HTTP/1.1\r\n\r\n"[..], 1, "HTTP/1.1 200 OK"),
+                                                 (b"POST http://example.com/ HTTP/1.1\r\n\r\n", 0, "HTTP/1.1 502 Bad Gateway")] {
+            let pool = Arc::new(Pool::new());
+            let (stale, stale_sent) = StagedRead::new(&[b""]);
+            pool.put(Key::new("http", "example.com", 80), stale);
+
+            let mut sent = None;
+            let mut made = 0;
+            let (client, output) = StagedRead::new(&[request]);
+
+            {
+                let mut conn = Connection::new(client, |_, _| {
+                    made += 1;
+                    let (upstream, upstream_sent) = StagedRead::new(&[b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"]);
+                    sent = Some(upstream_sent);
+                    Ok(upstream)
+                }).with_pool(pool.clone(), |_| Ok(()));
+
+                while conn.poll().unwrap().is_none() {}
+            }
+
+            assert_eq!(connects, made);
+            assert!(stale_sent.lock().unwrap().starts_with(request.split(|&byte| byte == b' ').next().unwrap()));
+            if let Some(sent) = sent {
+                assert_eq!(*stale_sent.lock().unwrap(), *sent.lock().unwrap());
+            }
+            assert!(str::from_utf8(&output.lock().unwrap()).unwrap().starts_with(response));
+        }
+    }
+
+    #[test]
+    fn close_the_connection_when_the_client_asks() {
+        let (output, upstreams) = forward_on_one_connection(
+            &[b"GET http://example.com/1 HTTP/1.1\r\nConnection: close\r\n\r\n\
+                GET http://example.com/2 HTTP/1.1\r\n\r\n"],
+            KeepAlive::default());
+
+        assert_eq!(1, upstreams.len());
+        assert!(output.ends_with("Connection: close\r\n\r\n1"));
+    }
+
+    #[test]
+    fn close_the_connection_after_the_most_requests() {
+        let keep_alive = KeepAlive { max_requests: 2, ..KeepAlive::default() };
+        let (output, upstreams) = forward_on_one_connection(
+            &[b"GET http://example.com/1 HTTP/1.1\r\n\r\n\
+                GET http://example.com/2 HTTP/1.1\r\n\r\n\
+                GET http://example.com/3 HTTP/1.1\r\n\r\n"],
+            keep_alive);
+
+        assert_eq!(2, upstreams.len());
+        assert!(output.ends_with("\r\n\r\n1HTTP/1.1 200 OK\r\nContent-Length: 1\r\nVia: 1.1 twister\r\nConnection: close\r\n\r\n2"));
+    }
+
+    #[test]
+    fn close_idle_connections() {
+        let request = b"GET http://example.com/ HTTP/1.1\r\n\r\n";
+        let upstream = |_: &str, _: Option<&[SocketAddr]>| Ok(Pending::new(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"));
+
+        let mut conn = Connection::new(Pending::new(request), upstream);
+        assert!(conn.deadline().is_none());
+        assert!(conn.poll().unwrap().is_none());
+        assert!(conn.deadline().is_some());
+
+        let keep_alive = KeepAlive { idle_timeout: Duration::from_secs(0), ..KeepAlive::default() };
+        let mut conn = Connection::new(Pending::new(request), upstream).with_keep_alive(keep_alive);
+        assert!(conn.poll().unwrap().is_some());
+    }
+
+    #[test]
+    fn respond_not_found_to_origin_form_request() {
+        let mut conn = Connection::new(Trickle::new(Cursor::new(b"GET / HTTP/1.1\r\n\r\n".to_vec())), |_, _| -> Result<Cursor<Vec<u8>>, io::Error> {
+            panic!("Upstream requested for an origin-form request");
+        });
+
+        let s: Trickle<Cursor<Vec<u8>>> = loop {
+            if let Some(stream) = conn.poll().unwrap() {
+                break stream;
+            }
+        };
+
+        let output = s.into_inner().into_inner();
+        assert!(str::from_utf8(&output).unwrap().ends_with("HTTP/1.1 404 Not Found\r\n\r\n"));
+    }
+
+    #[test]
+    fn respond_loop_detected_to_requests_via_itself() {
+        let request = b"GET http://example.com/ HTTP/1.1\r\nVia: 1.1 gw\r\n\r\n".to_vec();
+        let mut conn = Connection::new(Trickle::new(Cursor::new(request)), |_, _| -> Result<Cursor<Vec<u8>>, io::Error> {
+            panic!("Upstream requested for a looping request");
+        }).with_rewrite(Arc::new(Rewrite::new("gw")));
+
+        let s: Trickle<Cursor<Vec<u8>>> = loop {
+            if let Some(stream) = conn.poll().unwrap() {
+                break stream;
+            }
+        };
+
+        let output = s.into_inner().into_inner();
+        assert!(str::from_utf8(&output).unwrap().ends_with("HTTP/1.1 508 Loop Detected\r\n\r\n"));
+    }
+
+    /// Tunnels to `source:443` for a client that sends `stages`.
+    /// Returns what the client received and what was sent upstream.
+    fn tunnel(stages: &[&[u8]]) -> (String, String) {
         let mut requested_upstream = false;
+        let mut sent = None;
+        let (client, output) = StagedRead::new(stages);
 
-        let s = {
-            let mut conn = Connection::new(Trickle::new(StagedRead::new()), |dest| {
-                requested_upstream = dest == "source";
-                Trickle::new(Cursor::new(upstream.clone()))
+        {
+            let mut conn = Connection::new(client, |dest, _| {
+                requested_upstream = dest == "source:443";
+                let (upstream, upstream_sent) = StagedRead::new(&[b"Hello, World!"]);
+                sent = Some(upstream_sent);
+                Ok(upstream)
             });
 
+            while conn.poll().unwrap().is_none() {}
+        }
+
+        assert!(requested_upstream);
+        let sent = String::from_utf8(sent.unwrap().lock().unwrap().clone()).unwrap();
+        let received = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        (received, sent)
+    }
+
+    #[test]
+    fn proxy_request() {
+        let (received, sent) = tunnel(&[b"CONNECT source:443 HTTP/1.0\r\n\r\n", b"GET /index.html HTTP/1.0\r\n\r\n"]);
+
+        assert_eq!("GET /index.html HTTP/1.0\r\n\r\n", sent);
+        assert_eq!("HTTP/1.1 200 OK\r\n\r\nHello, World!", received);
+    }
+
+    #[test]
+    fn relay_bytes_sent_with_the_connect_request() {
+        let (received, sent) = tunnel(&[b"CONNECT source:443 HTTP/1.0\r\n\r\nGET /index.html HTTP/1.0\r\n\r\n"]);
+
+        assert_eq!("GET /index.html HTTP/1.0\r\n\r\n", sent);
+        assert_eq!("HTTP/1.1 200 OK\r\n\r\nHello, World!", received);
+    }
+
+    fn respond_to_failed_upstream<E: Into<io::Error>>(request: &[u8], error: E) -> String {
+        let mut error = Some(error.into());
+        let mut conn = Connection::new(Trickle::new(Cursor::new(request.to_vec())), move |_, _| -> Result<Cursor<Vec<u8>>, io::Error> {
+            Err(error.take().unwrap())
+        });
+
+        let s = loop {
+            if let Some(stream) = conn.poll().unwrap() {
+                break stream;
+            }
+        };
+
+        String::from_utf8(s.into_inner().into_inner()).unwrap()
+    }
+
+    #[test]
+    fn respond_bad_gateway_when_upstream_is_unreachable() {
+        let output = respond_to_failed_upstream(b"CONNECT source:443 HTTP/1.1\r\n\r\n", io::ErrorKind::ConnectionRefused);
+        assert!(output.ends_with("HTTP/1.1 502 Bad Gateway\r\n\r\n"));
+
+        let output = respond_to_failed_upstream(b"GET http://source/ HTTP/1.1\r\n\r\n", io::ErrorKind::ConnectionRefused);
+        assert!(output.ends_with("HTTP/1.1 502 Bad Gateway\r\n\r\n"));
+    }
+
+    #[test]
+    fn retry_connector_that_would_block() {
+        let mut attempts = 0;
+        let mut conn = Connection::new(Pending::new(b"CONNECT source:443 HTTP/1.1\r\n\r\n"), |dest, _| {
+            assert_eq!("source:443", dest);
+            attempts += 1;
+            match attempts {
+                1 => Err(io::ErrorKind::WouldBlock.into()),
+                _ => Ok(Pending::new(b"")),
+            }
+        });
+
+        assert!(conn.poll().unwrap().is_none());
+        match conn.state {
+            ConnectionState::Connecting(..) => {},
+            _ => panic!("Expected to be connecting"),
+        }
+
+        assert!(conn.poll().unwrap().is_none());
+        match conn.state {
+            ConnectionState::Tunnelling(..) => {},
+            _ => panic!("Expected to be tunnelling"),
+        }
+    }
+
+    #[test]
+    fn respond_gateway_timeout_when_upstream_times_out() {
+        let output = respond_to_failed_upstream(b"CONNECT source:443 HTTP/1.1\r\n\r\n", io::ErrorKind::TimedOut);
+        assert!(output.ends_with("HTTP/1.1 504 Gateway Timeout\r\n\r\n"));
+    }
+
+    #[test]
+    fn respond_gateway_timeout_when_connecting_takes_too_long() {
+        let request = b"CONNECT source:443 HTTP/1.1\r\n\r\n";
+        let upstream = |_: &str, _: Option<&[SocketAddr]>| -> Result<Pending, io::Error> {
+            Err(io::ErrorKind::WouldBlock.into())
+        };
+
+        let mut conn = Connection::new(Pending::new(request), upstream);
+        assert!(conn.poll().unwrap().is_none());
+        assert!(conn.deadline().is_some());
+
+        let mut conn = Connection::new(Pending::new(request), upstream).with_connect_timeout(Duration::from_secs(0));
+        let s = conn.poll().unwrap().unwrap();
+        assert!(str::from_utf8(&s.1).unwrap().ends_with("HTTP/1.1 504 Gateway Timeout\r\n\r\n"));
+    }
+
+    #[test]
+    fn relay_the_parent_proxys_refusal() {
+        let refused = Refused { status: StatusCode(403), reason: "Forbidden".to_string() };
+        let error = io::Error::new(io::ErrorKind::ConnectionRefused, refused);
+        let output = respond_to_failed_upstream(b"CONNECT source:443 HTTP/1.1\r\n\r\n", error);
+        assert!(output.ends_with("HTTP/1.1 403 Forbidden\r\n\r\n"));
+    }
+
+    fn auth() -> Arc<ProxyAuth> {
+        let mut store = ::auth::MemoryStore::new();
+        store.insert("user", "pass");
+        Arc::new(ProxyAuth::new("twister", store))
+    }
+
+    #[test]
+    fn require_proxy_authorization() {
+        for request in &[&b"CONNECT source:443 HTTP/1.1\r\n\r\n"[..], b"GET http://source/ HTTP/1.1\r\n\r\n"] {
+            let mut conn = Connection::new(Trickle::new(Cursor::new(request.to_vec())), |_, _| -> Result<Cursor<Vec<u8>>, io::Error> {
+                panic!("Upstream requested without authorization");
+            }).with_auth(auth());
+
             let s = loop {
                 if let Some(stream) = conn.poll().unwrap() {
                     break stream;
                 }
             };
 
-            s
+            let output = String::from_utf8(s.into_inner().into_inner()).unwrap();
+            assert!(output.contains("HTTP/1.1 407 Proxy Authentication Required\r\n\
+                                     Proxy-Authenticate: Basic realm=\"twister\"\r\n"));
+        }
+    }
+
+    #[test]
+    fn respond_forbidden_to_denied_tunnels() {
+        let mut conn = Connection::new(Trickle::new(Cursor::new(b"CONNECT source:22 HTTP/1.1\r\n\r\n".to_vec())), |_, _| -> Result<Cursor<Vec<u8>>, io::Error> {
+            panic!("Upstream requested for a denied tunnel");
+        });
+
+        let s = loop {
+            if let Some(stream) = conn.poll().unwrap() {
+                break stream;
+            }
         };
 
-        assert!(requested_upstream);
-        let (stream, sink) = s.into_inner().into_inner();
-        let input = sink.into_inner();
-        let output = stream.into_inner();
+        let output = s.into_inner().into_inner();
+        assert!(str::from_utf8(&output).unwrap().ends_with("HTTP/1.1 403 Forbidden\r\n\r\n"));
+    }
+
+    #[test]
+    fn respond_forbidden_to_tunnels_to_port_80_by_default() {
+        let mut conn = Connection::new(Trickle::new(Cursor::new(b"CONNECT example.com:80 HTTP/1.1\r\n\r\n".to_vec())), |_, _| -> Result<Cursor<Vec<u8>>, io::Error> {
+            panic!("Upstream requested for a denied tunnel");
+        });
 
-//        assert_eq!("GET / HTTP/1.0\r\n\r\n", str::from_utf8(&*output).unwrap());
-        assert_eq!("HTTP/1.1 200 OK\r\n\r\nHello, World!", str::from_utf8(&*input).unwrap());
+        let s = loop {
+            if let Some(stream) = conn.poll().unwrap() {
+                break stream;
+            }
+        };
+
+        let output = s.into_inner().into_inner();
+        assert!(str::from_utf8(&output).unwrap().ends_with("HTTP/1.1 403 Forbidden\r\n\r\n"));
     }
-}
 
+    #[test]
+    fn respond_forbidden_to_denied_requests() {
+        let request = b"GET http://10.0.0.5:8080/admin HTTP/1.1\r\n\r\n";
+        let mut conn = Connection::new(Trickle::new(Cursor::new(request.to_vec())), |_, _| -> Result<Cursor<Vec<u8>>, io::Error> {
+            panic!("Upstream requested for a denied request");
+        });
+
+        let s = loop {
+            if let Some(stream) = conn.poll().unwrap() {
+                break stream;
+            }
+        };
+
+        let output = s.into_inner().into_inner();
+        assert!(str::from_utf8(&output).unwrap().ends_with("HTTP/1.1 403 Forbidden\r\n\r\n"));
+    }
+
+    #[test]
+    fn allow_tunnels_permitted_by_the_acl() {
+        let acl = Acl::new(vec!["allow source 22".parse().unwrap()]);
+        let mut conn = Connection::new(Pending::new(b"CONNECT source:22 HTTP/1.1\r\n\r\n"), |_, _| Ok(Pending::new(b"")))
+            .with_acl(Arc::new(acl));
+
+        assert!(conn.poll().unwrap().is_none());
+        match conn.state {
+            ConnectionState::Tunnelling(..) => {},
+            _ => panic!("Expected to be tunnelling"),
+        }
+    }
+
+    /// Counts the times it's woken
+    struct Woken(AtomicUsize);
+
+    impl Wake for Woken {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn check_tunnels_once_the_resolver_answers() {
+        /// Can't answer until it's told `internal` is in 10/8, and then
+        /// wakes the last waker it was given
+        struct Slow(AtomicUsize, Mutex<Option<Waker>>);
+
+        impl Resolver for Slow {
+            fn resolve(&self, _: &str, waker: &Waker) -> Result<::resolve::Resolved, io::Error> {
+                match self.0.load(Ordering::SeqCst) {
+                    0 => {
+                        *self.1.lock().unwrap() = Some(waker.clone());
+                        Err(io::ErrorKind::WouldBlock.into())
+                    },
+                    _ => Ok(::resolve::Resolved { addrs: vec!["10.0.0.1".parse().unwrap()], ttl: None }),
+                }
+            }
+        }
+
+        let resolver = Arc::new(Slow(AtomicUsize::new(0), Mutex::new(None)));
+        let woken = Arc::new(Woken(AtomicUsize::new(0)));
+        let acl = Acl::new(vec!["deny 10.0.0.0/8 *".parse().unwrap(), "allow * 443".parse().unwrap()]);
+        let mut conn = Connection::new(Pending::new(b"CONNECT internal:443 HTTP/1.1\r\n\r\n"), |_, _| -> Result<Pending, io::Error> {
+                panic!("Upstream requested for a denied tunnel");
+            })
+            .with_acl(Arc::new(acl))
+            .with_resolver(resolver.clone())
+            .with_waker(Waker::from(woken.clone()));
+
+        assert!(conn.poll().unwrap().is_none());
+        match conn.state {
+            ConnectionState::Checking(..) => {},
+            _ => panic!("Expected to be checking the ACL"),
+        }
+
+        resolver.0.store(1, Ordering::SeqCst);
+        resolver.1.lock().unwrap().take().unwrap().wake();
+        assert_eq!(1, woken.0.load(Ordering::SeqCst));
+
+        let s = conn.poll().unwrap().unwrap();
+        assert!(str::from_utf8(&s.1).unwrap().ends_with("HTTP/1.1 403 Forbidden\r\n\r\n"));
+    }
+
+    #[test]
+    fn connect_to_the_addresses_the_acl_approved() {
+        let acl = Acl::new(vec!["allow 192.0.2.0/24 443".parse().unwrap()]);
+        let hosts = "192.0.2.1 internal".parse::<::resolve::StaticHosts>().unwrap();
+        let mut conn = Connection::new(Pending::new(b"CONNECT internal:443 HTTP/1.1\r\n\r\n"), |dest, addrs| {
+                assert_eq!("internal:443", dest);
+                assert_eq!(Some(&["192.0.2.1:443".parse().unwrap()][..]), addrs);
+                Ok(Pending::new(b""))
+            })
+            .with_acl(Arc::new(acl))
+            .with_resolver(Arc::new(hosts));
+
+        assert!(conn.poll().unwrap().is_none());
+        match conn.state {
+            ConnectionState::Tunnelling(..) => {},
+            _ => panic!("Expected to be tunnelling"),
+        }
+    }
+
+    #[test]
+    fn respond_bad_gateway_when_the_acl_cant_resolve_the_destination() {
+        let acl = Acl::new(vec!["deny 10.0.0.0/8 *".parse().unwrap(), "allow * 443".parse().unwrap()]);
+        let mut conn = Connection::new(Pending::new(b"CONNECT unknown:443 HTTP/1.1\r\n\r\n"), |_, _| -> Result<Pending, io::Error> {
+                panic!("Upstream requested for an unchecked tunnel");
+            })
+            .with_acl(Arc::new(acl))
+            .with_resolver(Arc::new(::resolve::StaticHosts::new()));
+
+        let s = conn.poll().unwrap().unwrap();
+        assert!(str::from_utf8(&s.1).unwrap().ends_with("HTTP/1.1 502 Bad Gateway\r\n\r\n"));
+    }
+
+    #[test]
+    fn accept_authorized_request() {
+        let request = b"CONNECT source:443 HTTP/1.1\r\nProxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n";
+        let mut conn = Connection::new(Pending::new(request), |_, _| Ok(Pending::new(b"")))
+            .with_auth(auth());
+
+        assert!(conn.poll().unwrap().is_none());
+        match conn.state {
+            ConnectionState::Tunnelling(..) => {},
+            _ => panic!("Expected to be tunnelling"),
+        }
+    }
+}
@@ -0,0 +1,808 @@
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use twister_http::{headers, Header, HttpMethod, Request, Response, Version};
+use twister_http::headers::{ConnectionOptions, HeaderError};
+use twister_http::body::{self, BodyDecoder, BodyError, BodyPart, Framing};
+use twister_http::parser::{HttpObjectParser, ParseError, ResumeToken, Status};
+use twister_http::target::{Authority, RequestTarget};
+
+use pool::Key;
+use rewrite::Rewrite;
+
+const READ_SIZE: usize = 4096;
+
+/// The number of response headers parsed without allocating
+pub const INLINE_HEADERS: usize = 32;
+
+/// The most headers a response may have before it's rejected
+const MAX_HEADERS: usize = 256;
+
+#[derive(Debug, PartialEq)]
+pub enum ForwardError {
+    /// The request target isn't in absolute-form, so the request is
+    /// for the proxy itself
+    NotAbsolute,
+    /// The request target isn't a `http` URL the proxy can forward to
+    InvalidTarget,
+    /// The request's body can't be framed
+    Body(BodyError),
+    /// The request has already passed through the proxy
+    Loop,
+}
+
+/// A request to forward to an origin server. The request's head has
+/// been rewritten into the form the origin server expects.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ForwardRequest {
+    /// The `host:port` of the origin server
+    pub dest: String,
+    /// Identifies the origin server's connections in a [`Pool`]
+    ///
+    /// [`Pool`]: ../pool/struct.Pool.html
+    pub key: Key,
+    /// Whether the client's connection can be kept open for another
+    /// request once the response has been forwarded
+    pub keep_alive: bool,
+    method: Vec<u8>,
+    version: Version,
+    head: Vec<u8>,
+    framing: Framing,
+    body: Vec<u8>,
+}
+
+/// Whether a connection persists after a message with `version` and
+/// the connection `options`. HTTP/1.1 connections persist unless
+/// `close` is given, and HTTP/1.0 connections only persist with
+/// `keep-alive`.
+fn persists(version: Version, options: &[Result<ConnectionOptions, HeaderError>]) -> bool {
+    // Options that can't be understood can't be relied on either
+    if options.iter().any(|options| options.as_ref().map(|options| options.is_close()).unwrap_or(true)) {
+        return false;
+    }
+
+    version.is_persistent_by_default() ||
+        options.iter().any(|options| options.as_ref().map(|options| options.is_keep_alive()).unwrap_or(false))
+}
+
+/// Whether the client wants its connection kept open after the
+/// response. Clients often send their options in `Proxy-Connection`
+/// when talking to a proxy, so both headers are checked.
+fn wants_keep_alive(request: &Request) -> bool {
+    persists(request.version, &[headers::connection(request.headers), headers::proxy_connection(request.headers)])
+}
+
+/// The `Connection` option sent to a client using `version`, so it
+/// knows whether its connection will be kept open
+fn connection_option(version: Version, keep_alive: bool) -> Option<&'static [u8]> {
+    match (keep_alive, version) {
+        (false, _) => Some(b"close"),
+        (true, Version::Http10) => Some(b"keep-alive"),
+        (true, Version::Http11) => None,
+    }
+}
+
+
+impl ForwardRequest {
+    /// Rewrites `request`, whose target must be in absolute-form. The
+    /// request line is rewritten to origin-form, `Host` is replaced by the
+    /// target's authority, and the headers are rewritten by `rewrite`.
+    /// `client` is the client's address, if it's known.
+    pub fn new(request: &Request, rewrite: &Rewrite, client: Option<IpAddr>) -> Result<ForwardRequest, ForwardError> {
+        // Fragments are never sent to a server
+        let target = &request.path[..request.path.iter().position(|byte| *byte == b'#').unwrap_or(request.path.len())];
+        let (authority, path, query) = match RequestTarget::parse(target, &request.method) {
+            Ok(RequestTarget::Absolute { scheme, authority, path, query }) if scheme.eq_ignore_ascii_case(b"http") =>
+                (authority, path, query),
+            Ok(RequestTarget::Origin { .. }) | Ok(RequestTarget::Asterisk) => return Err(ForwardError::NotAbsolute),
+            _ => return Err(ForwardError::InvalidTarget),
+        };
+
+        let host = Authority { port: None, ..authority }.to_string();
+        let key = Key::new("http", &host, authority.port.unwrap_or(80));
+        let authority = authority.to_string();
+        let framing = body::request_framing(request).map_err(ForwardError::Body)?;
+
+        if rewrite.is_loop(request.headers) {
+            return Err(ForwardError::Loop);
+        }
+
+        let mut head = vec![];
+        head.extend(request.method.as_bytes());
+        head.extend(b" ");
+        if path.is_empty() {
+            head.extend(b"/");
+        }
+        head.extend(path);
+        if let Some(query) = query {
+            head.extend(b"?");
+            head.extend(query);
+        }
+        head.extend(b" HTTP/1.1\r\nHost: ");
+        head.extend(authority.as_bytes());
+        head.extend(b"\r\n");
+        rewrite.write_request_headers(&mut head, request, authority.as_bytes(), client);
+
+        Ok(ForwardRequest {
+            dest: key.authority(),
+            key: key,
+            keep_alive: wants_keep_alive(request),
+            method: request.method.as_bytes().to_vec(),
+            version: request.version,
+            head: head,
+            framing: framing,
+            body: request.body.to_vec(),
+        })
+    }
+
+    /// Returns `true` if the request can be sent again on another
+    /// connection when the first fails before it's answered - Its method
+    /// is idempotent, and it has no body to have been partly sent.
+    pub fn is_replayable(&self) -> bool {
+        self.framing == Framing::Length(0) && matches!(HttpMethod::from(&self.method[..]),
+            HttpMethod::Get | HttpMethod::Head | HttpMethod::Options |
+            HttpMethod::Trace | HttpMethod::Put | HttpMethod::Delete)
+    }
+}
+
+/// Bytes waiting to be written to a stream
+pub struct Outgoing(Vec<u8>, usize);
+
+impl Outgoing {
+    pub fn new(data: Vec<u8>) -> Outgoing {
+        Outgoing(data, 0)
+    }
+
+    /// Writes as much as possible to `to`. Returns `true` once
+    /// everything has been written.
+    pub fn flush<W: Write>(&mut self, to: &mut W) -> Result<bool, io::Error> {
+        while self.1 < self.0.len() {
+            match to.write(&self.0[self.1..]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => self.1 += n,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.0.clear();
+        self.1 = 0;
+        Ok(true)
+    }
+}
+
+/// Reads once from `from`, appending to `buffer`. Returns `None`
+/// if the read would block.
+fn read_some<R: Read>(from: &mut R, buffer: &mut Vec<u8>) -> Result<Option<usize>, io::Error> {
+    let mut tmp = [0_u8; READ_SIZE];
+    match from.read(&mut tmp) {
+        Ok(n) => {
+            buffer.extend(&tmp[..n]);
+            Ok(Some(n))
+        },
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn invalid_data<E: ::std::fmt::Display>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// Passes as much of `pending` through `decoder` as possible, moving
+/// the bytes it consumes to `out`. With `dechunk`, only the body's data
+/// is moved, without its chunked framing or trailers. Returns `true` if
+/// the body ended.
+fn frame_body(decoder: &mut BodyDecoder, pending: &mut Vec<u8>, out: &mut Vec<u8>, dechunk: bool) -> Result<bool, io::Error> {
+    let mut consumed = 0;
+    let mut ended = false;
+
+    while let Status::Complete((n, part)) = decoder.decode(&pending[consumed..]).map_err(invalid_data)? {
+        match part {
+            BodyPart::Data(data) if dechunk => out.extend(data),
+            _ if dechunk => {},
+            _ => out.extend(&pending[consumed..consumed + n]),
+        }
+
+        consumed += n;
+        if part == BodyPart::End {
+            ended = true;
+            break;
+        }
+    }
+
+    pending.drain(..consumed);
+    Ok(ended)
+}
+
+/// What the response depends on from the request it answers
+struct Exchange {
+    method: Vec<u8>,
+    version: Version,
+    keep_alive: bool,
+    upstream_persists: bool,
+    /// Whether the response's chunked framing is removed, for a
+    /// HTTP/1.0 client
+    dechunk: bool,
+}
+
+/// A forwarded request whose response has been written to the client
+pub struct Finished<S> {
+    pub client: S,
+    /// The bytes the client sent after the request, which start its
+    /// next, pipelined, request. `None` if the client's connection
+    /// can't be used for another request.
+    pub pipelined: Option<Vec<u8>>,
+    /// Whether the upstream connection can be used for another request
+    pub upstream_reusable: bool,
+}
+
+/// The rewritten head of a response, and how its body is framed
+struct ResponseHead {
+    head: Vec<u8>,
+    framing: Framing,
+    body: Vec<u8>,
+    informational: bool,
+    keep_alive: bool,
+    upstream_persists: bool,
+    dechunk: bool,
+}
+
+impl ResponseHead {
+    fn new(response: &Response, exchange: &Exchange, rewrite: &Rewrite) -> Result<ResponseHead, io::Error> {
+        let framing = body::response_framing(response, &HttpMethod::from(&exchange.method[..]))
+            .map_err(invalid_data)?;
+
+        // HTTP/1.0 clients don't understand chunked framing, so the body
+        // is passed on without it and ends when the connection closes
+        let dechunk = framing == Framing::Chunked && exchange.version == Version::Http10;
+
+        // A body that ends when the upstream connection closes can only
+        // be passed on by closing the client's connection too
+        let keep_alive = exchange.keep_alive && framing != Framing::Close && !dechunk;
+        let upstream_persists = framing != Framing::Close &&
+            persists(response.version, &[headers::connection(response.headers)]);
+
+        // The status line carries the proxy's own version, not the
+        // origin server's
+        let mut head = b"HTTP/1.1 ".to_vec();
+        head.extend(response.status_code.to_string().as_bytes());
+        head.extend(b" ");
+        head.extend(response.status_text);
+        head.extend(b"\r\n");
+        rewrite.write_response_headers(&mut head, response, dechunk, connection_option(exchange.version, keep_alive));
+
+        Ok(ResponseHead {
+            head: head,
+            framing: framing,
+            body: response.body.to_vec(),
+            // `101 Switching Protocols` can't happen because `Upgrade`
+            // is never forwarded
+            informational: response.status_code.is_informational(),
+            keep_alive: keep_alive,
+            upstream_persists: upstream_persists,
+            dechunk: dechunk,
+        })
+    }
+}
+
+fn parse_response(buffer: &[u8], token: &mut ResumeToken, exchange: &Exchange, rewrite: &Rewrite) -> Result<Option<ResponseHead>, io::Error> {
+    let mut headers = [Header::default(); INLINE_HEADERS];
+    match HttpObjectParser::new(&mut headers).resume::<Response>(buffer, token) {
+        Ok(Status::Complete(response)) => ResponseHead::new(&response, exchange, rewrite).map(Some),
+        Ok(Status::Partial) => Ok(None),
+        Err(ParseError::TooManyHeaders(_, count)) if count <= MAX_HEADERS => {
+            let mut headers = vec![Header::default(); count];
+            let response = HttpObjectParser::new(&mut headers)
+                .parse::<Response>(buffer)
+                .map_err(invalid_data)?
+                .unwrap();
+            ResponseHead::new(&response, exchange, rewrite).map(Some)
+        },
+        Err(e) => Err(invalid_data(e)),
+    }
+}
+
+enum ResponseState {
+    Head(Vec<u8>, ResumeToken),
+    Body(BodyDecoder, Vec<u8>),
+    Done,
+}
+
+/// Forwards a single request to an origin server, and streams the
+/// response back to the client.
+pub struct Forwarder<S, U> {
+    client: Option<S>,
+    upstream: U,
+    exchange: Exchange,
+    request_body: Option<(BodyDecoder, Vec<u8>)>,
+    leftover: Vec<u8>,
+    to_upstream: Outgoing,
+    response: ResponseState,
+    to_client: Outgoing,
+    rewrite: Arc<Rewrite>,
+    upstream_failed: bool,
+}
+
+/// Notes whether `result`, of using the upstream connection, failed
+fn upstream_result<T>(failed: &mut bool, result: Result<T, io::Error>) -> Result<T, io::Error> {
+    *failed |= result.is_err();
+    result
+}
+
+impl<S, U> Forwarder<S, U>
+    where S: Read + Write,
+          U: Read + Write,
+{
+    /// Responses are rewritten by `rewrite`
+    pub fn new(request: ForwardRequest, client: S, upstream: U, rewrite: Arc<Rewrite>) -> Forwarder<S, U> {
+        Forwarder {
+            client: Some(client),
+            upstream: upstream,
+            exchange: Exchange {
+                method: request.method,
+                version: request.version,
+                keep_alive: request.keep_alive,
+                upstream_persists: false,
+                dechunk: false,
+            },
+            request_body: Some((BodyDecoder::new(request.framing), request.body)),
+            leftover: vec![],
+            to_upstream: Outgoing::new(request.head),
+            response: ResponseState::Head(vec![], ResumeToken::new()),
+            to_client: Outgoing::new(vec![]),
+            rewrite: rewrite,
+            upstream_failed: false,
+        }
+    }
+
+    /// Makes as much progress as possible without blocking. Returns
+    /// the client stream once the whole response has been written to it.
+    /// See [`Finished`].
+    ///
+    /// [`Finished`]: struct.Finished.html
+    pub fn poll(&mut self) -> Result<Option<Finished<S>>, io::Error> {
+        self.poll_request()?;
+        self.poll_response()?;
+
+        match self.response {
+            ResponseState::Done if self.to_client.0.is_empty() => {
+                // The rest of an unread request body would be taken as
+                // the next request, on either connection
+                let request_sent = self.request_body.is_none() && self.to_upstream.0.is_empty();
+                let pipelined = match (self.exchange.keep_alive, &self.request_body) {
+                    (true, &None) => Some(mem::take(&mut self.leftover)),
+                    _ => None,
+                };
+
+                Ok(self.client.take().map(|client| Finished {
+                    client: client,
+                    pipelined: pipelined,
+                    upstream_reusable: self.exchange.upstream_persists && request_sent,
+                }))
+            },
+            _ => Ok(None),
+        }
+    }
+
+    fn poll_request(&mut self) -> Result<(), io::Error> {
+        let client = self.client.as_mut().unwrap();
+
+        loop {
+            let ended = match self.request_body {
+                Some((ref mut decoder, ref mut pending)) =>
+                    frame_body(decoder, pending, &mut self.to_upstream.0, false)?,
+                None => false,
+            };
+
+            if ended {
+                // Anything after the body belongs to the next request
+                if let Some((_, pending)) = self.request_body.take() {
+                    self.leftover = pending;
+                }
+            }
+
+            if !upstream_result(&mut self.upstream_failed, self.to_upstream.flush(&mut self.upstream))? {
+                return Ok(());
+            }
+
+            match self.request_body {
+                Some((_, ref mut pending)) => match read_some(client, pending)? {
+                    Some(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                    Some(_) => {},
+                    None => return Ok(()),
+                },
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn poll_response(&mut self) -> Result<(), io::Error> {
+        let client = self.client.as_mut().unwrap();
+
+        loop {
+            if !self.to_client.flush(client)? {
+                return Ok(());
+            }
+
+            let next = match self.response {
+                ResponseState::Head(ref mut buffer, ref mut token) => {
+                    // Bytes following an informational response may
+                    // already hold the next response
+                    if token.offset() == buffer.len() {
+                        match upstream_result(&mut self.upstream_failed, read_some(&mut self.upstream, buffer))? {
+                            Some(0) => {
+                                self.upstream_failed = true;
+                                return Err(io::ErrorKind::UnexpectedEof.into());
+                            },
+                            Some(_) => {},
+                            None => return Ok(()),
+                        }
+                    }
+
+                    let parsed = parse_response(buffer, token, &self.exchange, &self.rewrite);
+                    match upstream_result(&mut self.upstream_failed, parsed)? {
+                        Some(head) => {
+                            if head.informational {
+                                // HTTP/1.0 has no informational responses
+                                if self.exchange.version != Version::Http10 {
+                                    self.to_client.0.extend(head.head);
+                                }
+                                ResponseState::Head(head.body, ResumeToken::new())
+                            }
+                            else {
+                                self.to_client.0.extend(head.head);
+                                self.exchange.keep_alive = head.keep_alive;
+                                self.exchange.upstream_persists = head.upstream_persists;
+                                self.exchange.dechunk = head.dechunk;
+                                let mut decoder = BodyDecoder::new(head.framing);
+                                let mut pending = head.body;
+                                if frame_body(&mut decoder, &mut pending, &mut self.to_client.0, head.dechunk)? {
+                                    // Bytes after the response are unexpected, so
+                                    // the upstream connection can't be trusted
+                                    self.exchange.upstream_persists &= pending.is_empty();
+                                    ResponseState::Done
+                                }
+                                else {
+                                    ResponseState::Body(decoder, pending)
+                                }
+                            }
+                        },
+                        None => continue,
+                    }
+                },
+                ResponseState::Body(ref mut decoder, ref mut pending) => {
+                    match read_some(&mut self.upstream, pending)? {
+                        Some(0) => match *decoder {
+                            BodyDecoder::Close => ResponseState::Done,
+                            _ => return Err(io::ErrorKind::UnexpectedEof.into()),
+                        },
+                        Some(_) => {
+                            if frame_body(decoder, pending, &mut self.to_client.0, self.exchange.dechunk)? {
+                                self.exchange.upstream_persists &= pending.is_empty();
+                                ResponseState::Done
+                            }
+                            else {
+                                continue;
+                            }
+                        },
+                        None => return Ok(()),
+                    }
+                },
+                ResponseState::Done => return Ok(()),
+            };
+
+            self.response = next;
+        }
+    }
+
+    /// Returns `true` if [`poll`] failed because of the upstream connection
+    /// before any of the final response was sent to the client, so the
+    /// client can still be answered with an error response
+    ///
+    /// [`poll`]: #method.poll
+    pub fn failed_before_response(&self) -> bool {
+        self.upstream_failed && matches!(self.response, ResponseState::Head(..)) && self.to_client.0.is_empty()
+    }
+
+    pub fn into_inner(mut self) -> S {
+        self.client.take().unwrap()
+    }
+
+    /// The upstream connection, to be reused once the Forwarder has
+    /// [`Finished`] and found it reusable
+    ///
+    /// [`Finished`]: struct.Finished.html
+    pub fn into_upstream(self) -> U {
+        self.upstream
+    }
+}
+
+#[cfg(test)]
+mod forward_should {
+    use super::*;
+    use std::io::Cursor;
+    use std::str;
+
+    /// A stream that reads from one buffer and writes to another
+    struct Duplex(Cursor<Vec<u8>>, Vec<u8>);
+
+    impl Duplex {
+        fn new(input: &[u8]) -> Duplex {
+            Duplex(Cursor::new(input.to_vec()), vec![])
+        }
+    }
+
+    impl Read for Duplex {
+        fn read(&mut self, buffer: &mut [u8]) -> Result<usize, io::Error> {
+            self.0.read(buffer)
+        }
+    }
+
+    impl Write for Duplex {
+        fn write(&mut self, buffer: &[u8]) -> Result<usize, io::Error> {
+            self.1.write(buffer)
+        }
+
+        fn flush(&mut self) -> Result<(), io::Error> {
+            Ok(())
+        }
+    }
+
+    fn forward_request(request: &[u8]) -> Result<ForwardRequest, ForwardError> {
+        let mut headers = [Header::default(); 16];
+        let request = HttpObjectParser::new(&mut headers).parse::<Request>(request).unwrap().unwrap();
+        ForwardRequest::new(&request, &Rewrite::default(), None)
+    }
+
+    /// Returns what was sent upstream, what was sent to the client, and
+    /// how the exchange finished
+    fn forward(request: &[u8], client: &[u8], upstream: &[u8]) -> (String, String, Finished<()>) {
+        let request = forward_request(request).unwrap();
+        let mut forwarder = Forwarder::new(request, Duplex::new(client), Duplex::new(upstream), Arc::new(Rewrite::default()));
+
+        let finished = loop {
+            if let Some(finished) = forwarder.poll().unwrap() {
+                break finished;
+            }
+        };
+
+        let received = String::from_utf8(finished.client.1).unwrap();
+        let finished = Finished { client: (), pipelined: finished.pipelined, upstream_reusable: finished.upstream_reusable };
+        (String::from_utf8(forwarder.into_upstream().1).unwrap(), received, finished)
+    }
+
+    #[test]
+    fn rewrite_absolute_form_request() {
+        let request = forward_request(
+            b"GET http://example.com:8080/index.html?q=1#top HTTP/1.1\r\n\
+              Host: elsewhere\r\n\
+              Connection: keep-alive, X-Trace\r\n\
+              X-Trace: 1\r\n\
+              Proxy-Connection: keep-alive\r\n\
+              Accept: */*\r\n\
+              \r\n").unwrap();
+
+        assert_eq!("example.com:8080", &*request.dest);
+        assert_eq!("GET /index.html?q=1 HTTP/1.1\r\n\
+                    Host: example.com:8080\r\n\
+                    Accept: */*\r\n\
+                    Via: 1.1 twister\r\n\
+                    \r\n",
+                   str::from_utf8(&request.head).unwrap());
+    }
+
+    #[test]
+    fn default_port_and_path() {
+        let request = forward_request(b"GET HTTP://example.com?q HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!("example.com:80", &*request.dest);
+        assert!(request.head.starts_with(b"GET /?q HTTP/1.1\r\nHost: example.com\r\n"));
+
+        let request = forward_request(b"GET http://[::1]/ HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!("[::1]:80", &*request.dest);
+    }
+
+    #[test]
+    fn reject_targets_that_cant_be_forwarded() {
+        assert_eq!(Err(ForwardError::NotAbsolute), forward_request(b"GET /index.html HTTP/1.1\r\n\r\n"));
+        assert_eq!(Err(ForwardError::NotAbsolute), forward_request(b"OPTIONS * HTTP/1.1\r\n\r\n"));
+
+        for target in &["https://example.com/", "http://user@example.com/", "http://example.com:x/", "http:///", "example.com"] {
+            let request = format!("GET {} HTTP/1.1\r\n\r\n", target);
+            assert_eq!(Err(ForwardError::InvalidTarget), forward_request(request.as_bytes()), "{}", target);
+        }
+    }
+
+    #[test]
+    fn reject_requests_that_loop() {
+        assert_eq!(Err(ForwardError::Loop),
+                   forward_request(b"GET http://example.com/ HTTP/1.1\r\nVia: 1.1 upstream, 1.1 twister\r\n\r\n"));
+    }
+
+    #[test]
+    fn only_replay_idempotent_requests_without_bodies() {
+        for request in &[&b"GET http://example.com/ HTTP/1.1\r\n\r\n"[..],
+                         b"DELETE http://example.com/ HTTP/1.1\r\nContent-Length: 0\r\n\r\n"] {
+            assert!(forward_request(request).unwrap().is_replayable(), "{}", str::from_utf8(request).unwrap());
+        }
+
+        for request in &[&b"POST http://example.com/ HTTP/1.1\r\n\r\n"[..],
+                         b"PUT http://example.com/ HTTP/1.1\r\nContent-Length: 1\r\n\r\n1",
+                         b"GET http://example.com/ HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n"] {
+            assert!(!forward_request(request).unwrap().is_replayable(), "{}", str::from_utf8(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn forward_request_body_and_response() {
+        let (sent, received, finished) = forward(
+            b"POST http://example.com/form HTTP/1.1\r\n\
+              Content-Length: 11\r\n\
+              \r\n\
+              Hello",
+            b", World!",
+            b"HTTP/1.1 200 OK\r\n\
+              Keep-Alive: timeout=5\r\n\
+              Transfer-Encoding: chunked\r\n\
+              \r\n\
+              5\r\nHello\r\n0\r\n\r\n\
+              HTTP/1.1 200 OK\r\n\r\n");
+
+        assert_eq!("POST /form HTTP/1.1\r\n\
+                    Host: example.com\r\n\
+                    Content-Length: 11\r\n\
+                    Via: 1.1 twister\r\n\
+                    \r\n\
+                    Hello, Worl", sent);
+        assert_eq!("HTTP/1.1 200 OK\r\n\
+                    Transfer-Encoding: chunked\r\n\
+                    Via: 1.1 twister\r\n\
+                    \r\n\
+                    5\r\nHello\r\n0\r\n\r\n", received);
+        assert!(finished.pipelined.is_some());
+    }
+
+    #[test]
+    fn forward_informational_responses() {
+        let (_, received, _) = forward(
+            b"GET http://example.com/ HTTP/1.1\r\n\r\n",
+            b"",
+            b"HTTP/1.1 100 Continue\r\n\r\n\
+              HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nOK");
+
+        assert_eq!("HTTP/1.1 100 Continue\r\nVia: 1.1 twister\r\n\r\n\
+                    HTTP/1.1 200 OK\r\nContent-Length: 2\r\nVia: 1.1 twister\r\n\r\nOK", received);
+    }
+
+    #[test]
+    fn forward_response_until_upstream_closes() {
+        let (_, received, finished) = forward(
+            b"GET http://example.com/ HTTP/1.1\r\n\r\n",
+            b"",
+            b"HTTP/1.0 200 OK\r\n\r\nHello, World!");
+
+        assert_eq!("HTTP/1.1 200 OK\r\nVia: 1.0 twister\r\nConnection: close\r\n\r\nHello, World!", received);
+        assert!(finished.pipelined.is_none());
+    }
+
+    #[test]
+    fn dechunk_responses_for_http10_clients() {
+        let (_, received, finished) = forward(
+            b"GET http://example.com/ HTTP/1.0\r\nConnection: keep-alive\r\n\r\n",
+            b"",
+            b"HTTP/1.1 100 Continue\r\n\r\n\
+              HTTP/1.1 200 OK\r\n\
+              Transfer-Encoding: chunked\r\n\
+              Content-Type: text/plain\r\n\
+              \r\n\
+              5\r\nHello\r\n8;ext=1\r\n, World!\r\n0\r\nX-Trailer: 1\r\n\r\n");
+
+        assert_eq!("HTTP/1.1 200 OK\r\n\
+                    Content-Type: text/plain\r\n\
+                    Via: 1.1 twister\r\n\
+                    Connection: close\r\n\
+                    \r\n\
+                    Hello, World!", received);
+        assert!(finished.pipelined.is_none());
+        assert!(finished.upstream_reusable);
+    }
+
+    #[test]
+    fn keep_bytes_after_the_request_for_the_next_request() {
+        let (sent, _, finished) = forward(
+            b"POST http://example.com/ HTTP/1.1\r\n\
+              Content-Length: 5\r\n\
+              \r\n\
+              HelloGET http://",
+            b"example.com/ HTTP/1.1\r\n\r\n",
+            b"HTTP/1.1 204 No Content\r\n\r\n");
+
+        assert!(sent.ends_with("\r\n\r\nHello"));
+        assert_eq!(Some(b"GET http://".to_vec()), finished.pipelined);
+    }
+
+    #[test]
+    fn keep_connections_alive_when_the_client_wants() {
+        for &(request, keep_alive) in &[
+            (&b"GET http://example.com/ HTTP/1.1\r\n\r\n"[..], true),
+            (b"GET http://example.com/ HTTP/1.1\r\nConnection: close\r\n\r\n", false),
+            (b"GET http://example.com/ HTTP/1.1\r\nProxy-Connection: close\r\n\r\n", false),
+            (b"GET http://example.com/ HTTP/1.0\r\n\r\n", false),
+            (b"GET http://example.com/ HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n", true),
+            (b"GET http://example.com/ HTTP/1.0\r\nProxy-Connection: keep-alive\r\n\r\n", true),
+        ] {
+            assert_eq!(keep_alive, forward_request(request).unwrap().keep_alive, "{}", str::from_utf8(request).unwrap());
+        }
+    }
+
+    #[test]
+    fn tell_the_client_whether_its_connection_is_kept_open() {
+        let response = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nOK";
+
+        let (_, received, finished) = forward(
+            b"GET http://example.com/ HTTP/1.0\r\nConnection: keep-alive\r\n\r\n", b"", response);
+        assert!(received.contains("Via: 1.1 twister\r\nConnection: keep-alive\r\n\r\n"));
+        assert!(finished.pipelined.is_some());
+
+        let (_, received, finished) = forward(
+            b"GET http://example.com/ HTTP/1.1\r\nConnection: close\r\n\r\n", b"", response);
+        assert!(received.contains("Via: 1.1 twister\r\nConnection: close\r\n\r\n"));
+        assert!(finished.pipelined.is_none());
+    }
+
+    #[test]
+    fn reuse_upstream_connections_after_framed_responses() {
+        for &(response, reusable) in &[
+            (&b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nOK"[..], true),
+            (b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nOK\r\n0\r\n\r\n", true),
+            (b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nOK", false),
+            (b"HTTP/1.0 200 OK\r\nContent-Length: 2\r\n\r\nOK", false),
+            (b"HTTP/1.0 200 OK\r\nContent-Length: 2\r\nConnection: keep-alive\r\n\r\nOK", true),
+            (b"HTTP/1.1 200 OK\r\n\r\nOK", false),
+            (b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nOK, and more", false),
+        ] {
+            let (_, _, finished) = forward(b"GET http://example.com/ HTTP/1.1\r\n\r\n", b"", response);
+            assert_eq!(reusable, finished.upstream_reusable, "{}", str::from_utf8(response).unwrap());
+        }
+    }
+
+    #[test]
+    fn report_truncated_responses() {
+        let request = forward_request(b"GET http://example.com/ HTTP/1.1\r\n\r\n").unwrap();
+        let mut forwarder = Forwarder::new(request,
+                                           Duplex::new(b""),
+                                           Duplex::new(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHel"),
+                                           Arc::new(Rewrite::default()));
+
+        let e = loop {
+            match forwarder.poll() {
+                Ok(None) => continue,
+                Ok(Some(_)) => panic!("Expected an error"),
+                Err(e) => break e,
+            }
+        };
+
+        assert_eq!(io::ErrorKind::UnexpectedEof, e.kind());
+        assert!(!forwarder.failed_before_response());
+    }
+
+    #[test]
+    fn report_upstream_failures_before_the_response() {
+        for upstream in &[&b""[..], b"HTTP/1.1 200 OK\r\n", b"HTTP/1.1 100 Continue\r\n\r\n", b"HTTP/1.1 OK\r\n\r\n"] {
+            let request = forward_request(b"GET http://example.com/ HTTP/1.1\r\n\r\n").unwrap();
+            let mut forwarder = Forwarder::new(request, Duplex::new(b""), Duplex::new(upstream), Arc::new(Rewrite::default()));
+
+            loop {
+                match forwarder.poll() {
+                    Ok(None) => continue,
+                    Ok(Some(_)) => panic!("Expected an error"),
+                    Err(_) => break,
+                }
+            }
+
+            assert!(forwarder.failed_before_response(), "{}", str::from_utf8(upstream).unwrap());
+        }
+    }
+}
@@ -1,4 +1,20 @@
 extern crate twister_http;
+extern crate base64;
+extern crate bcrypt;
+extern crate sha1;
 #[macro_use] extern crate log;
+#[cfg(feature = "mio")] extern crate mio;
+#[cfg(feature = "tokio")] extern crate tokio;
 
+pub mod acl;
+pub mod auth;
 pub mod connection;
+#[cfg(feature = "tokio")] pub mod async_connection;
+pub mod parent;
+pub mod pool;
+pub mod resolve;
+pub mod rewrite;
+pub mod stream;
+mod forward;
+mod response;
+mod tunnel;
@@ -0,0 +1,295 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use twister_http::{Header, HttpMethod, Request, Response, StatusCode, Version};
+use twister_http::parser::{HttpObjectParser, Status};
+use twister_http::serialize::Serialize;
+
+use forward::{invalid_data, Outgoing, INLINE_HEADERS};
+use response;
+
+/// The longest reply head accepted from a parent proxy
+const MAX_HEAD: usize = 8 * 1024;
+
+/// A parent proxy's refusal to open a tunnel. Connectors fail with this
+/// as the inner error, so the client is sent the same status - Unless
+/// the parent refused the proxy's credentials, which the client can't
+/// do anything about.
+#[derive(Debug, PartialEq)]
+pub struct Refused {
+    pub status: StatusCode,
+    pub reason: String,
+}
+
+impl Refused {
+    /// The response sent to the client. The parent failing to
+    /// authenticate the proxy is sent as `502 Bad Gateway`, so the client
+    /// isn't asked for credentials of its own.
+    pub fn response(&self) -> Vec<u8> {
+        match self.status {
+            StatusCode(401) | StatusCode(407) => response::status(StatusCode(502), b"Bad Gateway", &[]),
+            status => response::status(status, self.reason.as_bytes(), &[]),
+        }
+    }
+}
+
+impl fmt::Display for Refused {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "parent proxy refused with {} {}", self.status, self.reason)
+    }
+}
+
+impl Error for Refused {}
+
+/// A `CONNECT` sent to the parent proxy, and its reply as it arrives
+struct Handshake<U> {
+    stream: U,
+    request: Outgoing,
+    reply: Vec<u8>,
+}
+
+impl<U: Read + Write> Handshake<U> {
+    /// # Return Value
+    /// `true` once the parent has replied with `2xx`, and the stream is
+    /// tunnelled to the destination.
+    fn poll(&mut self) -> Result<bool, io::Error> {
+        if !self.request.flush(&mut self.stream)? {
+            return Ok(false);
+        }
+
+        // The reply is read a byte at a time so nothing that follows it,
+        // which belongs to the tunnel, is consumed
+        let mut byte = [0_u8; 1];
+        while !self.reply.ends_with(b"\r\n\r\n") {
+            match self.stream.read(&mut byte) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(_) => self.reply.push(byte[0]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) => return Err(e),
+            }
+
+            if self.reply.len() > MAX_HEAD {
+                return Err(invalid_data("parent proxy reply is too long"));
+            }
+        }
+
+        let mut headers = [Header::default(); INLINE_HEADERS];
+        let reply = match HttpObjectParser::new(&mut headers).parse::<Response>(&self.reply) {
+            Ok(Status::Complete(reply)) => reply,
+            Ok(Status::Partial) => return Err(invalid_data("incomplete parent proxy reply")),
+            Err(e) => return Err(invalid_data(e)),
+        };
+
+        if reply.status_code.is_success() {
+            return Ok(true);
+        }
+
+        let refused = Refused {
+            status: reply.status_code,
+            reason: String::from_utf8_lossy(reply.status_text).into_owned(),
+        };
+        Err(io::Error::new(io::ErrorKind::ConnectionRefused, refused))
+    }
+}
+
+/// A proxy that upstream connections are made through. Each connection
+/// is tunnelled to its destination with `CONNECT`.
+#[derive(Debug, Clone)]
+pub struct ParentProxy {
+    addr: String,
+    authorization: Option<String>,
+}
+
+impl ParentProxy {
+    /// `addr` is the parent's `host:port`
+    pub fn new(addr: &str) -> ParentProxy {
+        ParentProxy {
+            addr: addr.to_string(),
+            authorization: None,
+        }
+    }
+
+    /// Authenticates to the parent with `Basic` credentials
+    pub fn with_credentials(mut self, user: &str, password: &str) -> ParentProxy {
+        let token = BASE64.encode(format!("{}:{}", user, password));
+        self.authorization = Some(format!("Basic {}", token));
+        self
+    }
+
+    fn request(&self, dest: &str) -> Vec<u8> {
+        let mut headers = vec![Header(b"Host", dest.as_bytes())];
+        if let Some(ref authorization) = self.authorization {
+            headers.push(Header(b"Proxy-Authorization", authorization.as_bytes()));
+        }
+
+        let request = Request {
+            method: HttpMethod::Connect,
+            path: dest.as_bytes(),
+            version: Version::Http11,
+            headers: &headers,
+            body: b"",
+        };
+
+        let mut buffer = vec![0; request.serialized_len()];
+        request.write_to(&mut buffer).unwrap();
+        buffer
+    }
+
+    /// Creates a connector for a [`Connection`] that goes through the
+    /// parent. `connect` is called with the parent's address, and the
+    /// stream it returns is tunnelled to the destination the connector
+    /// is called with. The parent resolves the destination itself, so
+    /// any addresses the connector is given are unused.
+    ///
+    /// # Return Value
+    /// The connector fails with `WouldBlock` until the parent has
+    /// replied, and must be called again for the same destination. If
+    /// it's called for another, the pending tunnel is dropped and a new
+    /// one is started. If the parent refuses, it fails with a
+    /// [`Refused`] error, so the client gets the parent's status.
+    ///
+    /// # Examples
+    /// ```no_compile
+    /// let parent = ParentProxy::new("proxy.corp:3128").with_credentials("user", "password");
+    /// let conn = Connection::new(stream, parent.connector(|addr, _| TcpStream::connect(addr)));
+    /// ```
+    ///
+    /// [`Connection`]: ../connection/struct.Connection.html
+    /// [`Refused`]: struct.Refused.html
+    pub fn connector<C, U>(self, mut connect: C) -> impl FnMut(&str, Option<&[SocketAddr]>) -> Result<U, io::Error>
+        where C: FnMut(&str, Option<&[SocketAddr]>) -> Result<U, io::Error>,
+              U: Read + Write,
+    {
+        let mut pending: Option<(String, Handshake<U>)> = None;
+
+        move |dest, _| {
+            let mut handshake = match pending.take() {
+                Some((pending_dest, handshake)) if pending_dest == dest => handshake,
+                _ => Handshake {
+                    stream: connect(&self.addr, None)?,
+                    request: Outgoing::new(self.request(dest)),
+                    reply: vec![],
+                },
+            };
+
+            if handshake.poll()? {
+                Ok(handshake.stream)
+            }
+            else {
+                pending = Some((dest.to_string(), handshake));
+                Err(io::ErrorKind::WouldBlock.into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod parent_should {
+    use super::*;
+    use std::io::Cursor;
+    use std::str;
+
+    /// A stream that reads from one buffer and writes to another. Reads
+    /// block at the end of the input.
+    struct Duplex(Cursor<Vec<u8>>, Vec<u8>);
+
+    impl Read for Duplex {
+        fn read(&mut self, buffer: &mut [u8]) -> Result<usize, io::Error> {
+            match self.0.read(buffer)? {
+                0 => Err(io::ErrorKind::WouldBlock.into()),
+                n => Ok(n),
+            }
+        }
+    }
+
+    impl Write for Duplex {
+        fn write(&mut self, buffer: &[u8]) -> Result<usize, io::Error> {
+            self.1.write(buffer)
+        }
+
+        fn flush(&mut self) -> Result<(), io::Error> {
+            Ok(())
+        }
+    }
+
+    fn connect(parent: ParentProxy, reply: &[u8]) -> Result<Duplex, io::Error> {
+        let reply = reply.to_vec();
+        let mut connector = parent.connector(move |addr, _| {
+            assert_eq!("parent:3128", addr);
+            Ok(Duplex(Cursor::new(reply.clone()), vec![]))
+        });
+
+        connector("example.com:443", None)
+    }
+
+    #[test]
+    fn tunnel_through_the_parent() {
+        let parent = ParentProxy::new("parent:3128").with_credentials("user", "pa:ss");
+        let mut stream = connect(parent, b"HTTP/1.1 200 Connection established\r\n\r\nhello").unwrap();
+
+        assert_eq!(
+            "CONNECT example.com:443 HTTP/1.1\r\n\
+             Host: example.com:443\r\n\
+             Proxy-Authorization: Basic dXNlcjpwYTpzcw==\r\n\
+             \r\n",
+            str::from_utf8(&stream.1).unwrap());
+
+        let mut tunnelled = vec![0; 5];
+        stream.read_exact(&mut tunnelled).unwrap();
+        assert_eq!(b"hello", &*tunnelled);
+    }
+
+    #[test]
+    fn wait_for_the_parent_to_reply() {
+        let mut connector = ParentProxy::new("parent:3128").connector(|_, _| {
+            Ok(Duplex(Cursor::new(b"HTTP/1.1 200 OK\r\n".to_vec()), vec![]))
+        });
+
+        let e = connector("example.com:443", None).err().unwrap();
+        assert_eq!(io::ErrorKind::WouldBlock, e.kind());
+    }
+
+    #[test]
+    fn start_again_for_another_destination() {
+        let mut replies = vec![&b"HTTP/1.1 200 OK\r\n\r\n"[..], b"HTTP/1.1 200 OK\r\n"];
+        let mut connector = ParentProxy::new("parent:3128").connector(|_, _| {
+            Ok(Duplex(Cursor::new(replies.pop().unwrap().to_vec()), vec![]))
+        });
+
+        assert_eq!(io::ErrorKind::WouldBlock, connector("a.example.com:443", None).err().unwrap().kind());
+        let stream = connector("b.example.com:443", None).unwrap();
+        assert!(stream.1.starts_with(b"CONNECT b.example.com:443 HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn report_the_parents_refusal() {
+        let e = connect(ParentProxy::new("parent:3128"), b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n")
+            .err()
+            .unwrap();
+
+        let refused = e.get_ref().and_then(|e| e.downcast_ref::<Refused>()).unwrap();
+        assert_eq!(&Refused { status: StatusCode(403), reason: "Forbidden".to_string() }, refused);
+        assert_eq!(b"HTTP/1.1 403 Forbidden\r\n\r\n".to_vec(), refused.response());
+    }
+
+    #[test]
+    fn report_bad_gateway_when_the_parent_refuses_the_credentials() {
+        let parent = ParentProxy::new("parent:3128").with_credentials("user", "wrong");
+        let reply = b"HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic realm=\"corp\"\r\n\r\n";
+        let e = connect(parent, reply).err().unwrap();
+
+        let refused = e.get_ref().and_then(|e| e.downcast_ref::<Refused>()).unwrap();
+        assert_eq!(StatusCode(407), refused.status);
+        assert_eq!(b"HTTP/1.1 502 Bad Gateway\r\n\r\n".to_vec(), refused.response());
+    }
+
+    #[test]
+    fn reject_invalid_replies() {
+        let e = connect(ParentProxy::new("parent:3128"), b"SSH-2.0-OpenSSH\r\n\r\n").err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, e.kind());
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Identifies the origin servers whose connections are interchangeable
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct Key {
+    scheme: String,
+    host: String,
+    port: u16,
+}
+
+impl Key {
+    /// The scheme and host are compared without regard to case. IPv6
+    /// hosts keep their brackets - E.g. `[::1]`.
+    pub fn new(scheme: &str, host: &str, port: u16) -> Key {
+        Key {
+            scheme: scheme.to_ascii_lowercase(),
+            host: host.to_ascii_lowercase(),
+            port: port,
+        }
+    }
+
+    /// The `host:port` to connect to
+    pub fn authority(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}://{}:{}", self.scheme, self.host, self.port)
+    }
+}
+
+/// Returns `true` if nothing can be read from `stream` yet. Anything
+/// else - EOF, an error, or bytes the server sent without being asked -
+/// means the connection can't be used for another request. `stream`
+/// must be non-blocking.
+fn is_alive<U: Read>(stream: &mut U) -> bool {
+    let mut byte = [0_u8; 1];
+    matches!(stream.read(&mut byte), Err(ref e) if e.kind() == io::ErrorKind::WouldBlock)
+}
+
+/// Idle upstream connections, kept so later requests to the same origin
+/// server can skip connecting. Connections are shared between client
+/// connections, and are checked before they're reused.
+///
+/// # Examples
+/// ```
+/// use std::net::TcpStream;
+/// use std::time::Duration;
+/// use twister_core::pool::Pool;
+///
+/// let pool: Pool<TcpStream> = Pool::new()
+///     .with_max_idle_per_host(8)
+///     .with_idle_timeout(Duration::from_secs(60));
+/// ```
+pub struct Pool<U> {
+    idle: Mutex<HashMap<Key, Vec<(U, Instant)>>>,
+    max_idle_per_host: usize,
+    idle_timeout: Duration,
+}
+
+impl<U: Read> Default for Pool<U> {
+    fn default() -> Pool<U> {
+        Pool::new()
+    }
+}
+
+impl<U: Read> Pool<U> {
+    /// Keeps up to 4 connections per origin server, for 30 seconds each
+    pub fn new() -> Pool<U> {
+        Pool {
+            idle: Mutex::new(HashMap::new()),
+            max_idle_per_host: 4,
+            idle_timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// The most idle connections kept for each origin server. Once
+    /// there are this many, the longest idle is closed to make room.
+    /// `0` disables pooling.
+    pub fn with_max_idle_per_host(mut self, max: usize) -> Pool<U> {
+        self.max_idle_per_host = max;
+        self
+    }
+
+    /// How long a connection is kept before it's closed
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Pool<U> {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Takes the most recently used connection to `key`'s origin server.
+    /// Expired connections, and any the server has closed, are dropped.
+    pub fn take(&self, key: &Key) -> Option<U> {
+        let mut idle = self.idle.lock().unwrap();
+        let now = Instant::now();
+
+        let found = match idle.get_mut(key) {
+            Some(connections) => loop {
+                match connections.pop() {
+                    Some((mut upstream, since)) => {
+                        if now.duration_since(since) < self.idle_timeout && is_alive(&mut upstream) {
+                            break Some(upstream);
+                        }
+                        debug!("Dropping stale connection to {}", key);
+                    },
+                    None => break None,
+                }
+            },
+            None => return None,
+        };
+
+        if idle.get(key).is_some_and(|connections| connections.is_empty()) {
+            idle.remove(key);
+        }
+
+        found
+    }
+
+    /// Keeps `upstream`, whose last response was read in full, for
+    /// another request to `key`'s origin server
+    pub fn put(&self, key: Key, upstream: U) {
+        if self.max_idle_per_host == 0 {
+            return;
+        }
+
+        let mut idle = self.idle.lock().unwrap();
+        let connections = idle.entry(key).or_default();
+        if connections.len() >= self.max_idle_per_host {
+            connections.remove(0);
+        }
+
+        connections.push((upstream, Instant::now()));
+    }
+
+    /// Closes every connection that has been idle for too long. They're
+    /// also dropped when they're found by `take`, so this only frees
+    /// them sooner.
+    pub fn expire(&self) {
+        let now = Instant::now();
+        let timeout = self.idle_timeout;
+
+        let mut idle = self.idle.lock().unwrap();
+        for connections in idle.values_mut() {
+            connections.retain(|&(_, since)| now.duration_since(since) < timeout);
+        }
+        idle.retain(|_, connections| !connections.is_empty());
+    }
+
+    /// The number of idle connections to `key`'s origin server
+    pub fn idle(&self, key: &Key) -> usize {
+        self.idle.lock().unwrap().get(key).map_or(0, |connections| connections.len())
+    }
+}
+
+#[cfg(test)]
+mod pool_should {
+    use super::*;
+
+    /// A connection that's either still open or closed by the server
+    #[derive(Debug, PartialEq)]
+    struct Upstream(u32, bool);
+
+    impl Read for Upstream {
+        fn read(&mut self, _: &mut [u8]) -> Result<usize, io::Error> {
+            if self.1 {
+                Err(io::ErrorKind::WouldBlock.into())
+            }
+            else {
+                Ok(0)
+            }
+        }
+    }
+
+    fn key(host: &str) -> Key {
+        Key::new("http", host, 80)
+    }
+
+    #[test]
+    fn reuse_connections_to_the_same_origin() {
+        let pool = Pool::new();
+        pool.put(key("example.com"), Upstream(1, true));
+        pool.put(key("example.org"), Upstream(2, true));
+
+        assert_eq!(None, pool.take(&Key::new("http", "example.com", 8080)));
+        assert_eq!(Some(Upstream(1, true)), pool.take(&Key::new("HTTP", "Example.COM", 80)));
+        assert_eq!(None, pool.take(&key("example.com")));
+        assert_eq!(1, pool.idle(&key("example.org")));
+    }
+
+    #[test]
+    fn take_the_most_recently_used_connection() {
+        let pool = Pool::new();
+        pool.put(key("example.com"), Upstream(1, true));
+        pool.put(key("example.com"), Upstream(2, true));
+
+        assert_eq!(Some(Upstream(2, true)), pool.take(&key("example.com")));
+        assert_eq!(Some(Upstream(1, true)), pool.take(&key("example.com")));
+    }
+
+    #[test]
+    fn close_the_longest_idle_connection_when_full() {
+        let pool = Pool::new().with_max_idle_per_host(2);
+        for id in 1..4 {
+            pool.put(key("example.com"), Upstream(id, true));
+        }
+
+        assert_eq!(2, pool.idle(&key("example.com")));
+        assert_eq!(Some(Upstream(3, true)), pool.take(&key("example.com")));
+        assert_eq!(Some(Upstream(2, true)), pool.take(&key("example.com")));
+
+        let pool = Pool::new().with_max_idle_per_host(0);
+        pool.put(key("example.com"), Upstream(1, true));
+        assert_eq!(0, pool.idle(&key("example.com")));
+    }
+
+    #[test]
+    fn drop_connections_the_server_closed() {
+        let pool = Pool::new();
+        pool.put(key("example.com"), Upstream(1, true));
+        pool.put(key("example.com"), Upstream(2, false));
+
+        assert_eq!(Some(Upstream(1, true)), pool.take(&key("example.com")));
+        assert_eq!(0, pool.idle(&key("example.com")));
+    }
+
+    #[test]
+    fn expire_idle_connections() {
+        let pool = Pool::new().with_idle_timeout(Duration::from_secs(0));
+        pool.put(key("example.com"), Upstream(1, true));
+        assert_eq!(None, pool.take(&key("example.com")));
+
+        pool.put(key("example.com"), Upstream(2, true));
+        pool.expire();
+        assert_eq!(0, pool.idle(&key("example.com")));
+    }
+}
@@ -0,0 +1,443 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::task::Waker;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a [`ThreadedResolver`]'s answer is kept for a lookup that's
+/// never asked for again
+///
+/// [`ThreadedResolver`]: struct.ThreadedResolver.html
+const ABANDONED: Duration = Duration::from_secs(60);
+
+/// The addresses a host name resolves to
+#[derive(Debug, PartialEq, Clone)]
+pub struct Resolved {
+    pub addrs: Vec<IpAddr>,
+    /// How long the addresses can be cached for, if the resolver knows
+    pub ttl: Option<Duration>,
+}
+
+/// Resolves host names to addresses. A resolver that can't answer
+/// without blocking - E.g. because the lookup runs on another thread -
+/// can fail with `WouldBlock`, and wakes `waker` once it can. It'll be
+/// asked again for the same host then.
+pub trait Resolver {
+    fn resolve(&self, host: &str, waker: &Waker) -> Result<Resolved, io::Error>;
+}
+
+/// Splits a `host:port` destination. IPv6 hosts are bracketed -
+/// E.g. `[::1]:443` - and are returned without their brackets.
+pub(crate) fn split_dest(dest: &str) -> Option<(&str, u16)> {
+    let split = dest.rfind(':')?;
+    let (host, port) = (&dest[..split], dest[split + 1..].parse().ok()?);
+
+    if host.starts_with('[') && host.ends_with(']') {
+        let host = &host[1..host.len() - 1];
+        return host.parse::<IpAddr>().ok().filter(IpAddr::is_ipv6).map(|_| (host, port));
+    }
+
+    if host.is_empty() || host.contains(':') || host.contains('[') {
+        return None;
+    }
+
+    Some((host, port))
+}
+
+/// Resolves `dest`, a `host:port`, to the addresses to connect to.
+/// Hosts that are already addresses aren't passed to `resolver`.
+///
+/// # Examples
+/// ```
+/// use std::task::Waker;
+/// use twister_core::resolve::{self, StaticHosts};
+///
+/// let hosts = "192.0.2.1 example.com".parse::<StaticHosts>().unwrap();
+/// let addrs = resolve::socket_addrs(&hosts, "example.com:80", Waker::noop()).unwrap();
+/// assert_eq!("192.0.2.1:80", addrs[0].to_string());
+/// ```
+pub fn socket_addrs<R: Resolver + ?Sized>(resolver: &R, dest: &str, waker: &Waker) -> Result<Vec<SocketAddr>, io::Error> {
+    let (host, port) = split_dest(dest)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} isn't a host:port", dest)))?;
+
+    let addrs = match host.parse::<IpAddr>() {
+        Ok(addr) => vec![addr],
+        Err(_) => resolver.resolve(host, waker)?.addrs,
+    };
+
+    Ok(addrs.into_iter().map(|addr| SocketAddr::new(addr, port)).collect())
+}
+
+/// Resolves with the operating system's resolver, which blocks the
+/// calling thread. See [`ThreadedResolver`] to resolve without blocking.
+///
+/// [`ThreadedResolver`]: struct.ThreadedResolver.html
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve(&self, host: &str, _: &Waker) -> Result<Resolved, io::Error> {
+        let mut addrs = vec![];
+        for addr in (host, 0).to_socket_addrs()? {
+            if !addrs.contains(&addr.ip()) {
+                addrs.push(addr.ip());
+            }
+        }
+
+        Ok(Resolved {
+            addrs: addrs,
+            ttl: None,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct InvalidHosts;
+
+/// A fixed table of host names, like a hosts file. Names are matched
+/// without regard to case, and names that aren't in the table fail to
+/// resolve with `NotFound`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StaticHosts(HashMap<String, Vec<IpAddr>>);
+
+impl StaticHosts {
+    pub fn new() -> StaticHosts {
+        StaticHosts::default()
+    }
+
+    /// Adds `addr` to the addresses `host` resolves to
+    pub fn insert(&mut self, host: &str, addr: IpAddr) {
+        let addrs = self.0.entry(host.to_ascii_lowercase()).or_default();
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+}
+
+impl Resolver for StaticHosts {
+    fn resolve(&self, host: &str, _: &Waker) -> Result<Resolved, io::Error> {
+        match self.0.get(&host.to_ascii_lowercase()) {
+            Some(addrs) => Ok(Resolved { addrs: addrs.clone(), ttl: None }),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, format!("{} isn't a known host", host))),
+        }
+    }
+}
+
+/// Parses the hosts file format - An address followed by the names it
+/// belongs to, per line. Blank lines and `#` comments are ignored.
+impl FromStr for StaticHosts {
+    type Err = InvalidHosts;
+
+    fn from_str(s: &str) -> Result<StaticHosts, InvalidHosts> {
+        let mut hosts = StaticHosts::new();
+
+        for line in s.lines() {
+            let line = &line[..line.find('#').unwrap_or(line.len())];
+            let mut parts = line.split_whitespace();
+
+            let addr = match parts.next() {
+                Some(addr) => addr.parse::<IpAddr>().map_err(|_| InvalidHosts)?,
+                None => continue,
+            };
+
+            let mut named = false;
+            for name in parts {
+                hosts.insert(name, addr);
+                named = true;
+            }
+
+            if !named {
+                return Err(InvalidHosts);
+            }
+        }
+
+        Ok(hosts)
+    }
+}
+
+/// Caches the answers of another resolver for as long as their TTL
+/// allows. Answers without a TTL are kept for a default time instead.
+/// Failures aren't cached.
+///
+/// # Examples
+/// ```
+/// use std::time::Duration;
+/// use twister_core::resolve::{CachingResolver, SystemResolver};
+///
+/// let resolver = CachingResolver::new(SystemResolver).with_default_ttl(Duration::from_secs(300));
+/// ```
+pub struct CachingResolver<R> {
+    inner: R,
+    cache: Mutex<HashMap<String, (Vec<IpAddr>, Instant)>>,
+    default_ttl: Duration,
+}
+
+impl<R: Resolver> CachingResolver<R> {
+    /// Answers without a TTL are kept for 60 seconds
+    pub fn new(inner: R) -> CachingResolver<R> {
+        CachingResolver {
+            inner: inner,
+            cache: Mutex::new(HashMap::new()),
+            default_ttl: Duration::from_secs(60),
+        }
+    }
+
+    /// How long answers without a TTL are kept
+    pub fn with_default_ttl(mut self, ttl: Duration) -> CachingResolver<R> {
+        self.default_ttl = ttl;
+        self
+    }
+
+    /// Forgets every answer whose TTL has passed. They're also replaced
+    /// when they're next looked up, so this only frees them sooner.
+    pub fn expire(&self) {
+        let now = Instant::now();
+        self.cache.lock().unwrap().retain(|_, &mut (_, expires)| expires > now);
+    }
+}
+
+impl<R: Resolver> Resolver for CachingResolver<R> {
+    fn resolve(&self, host: &str, waker: &Waker) -> Result<Resolved, io::Error> {
+        let host = host.to_ascii_lowercase();
+        let now = Instant::now();
+
+        if let Some(&(ref addrs, expires)) = self.cache.lock().unwrap().get(&host) {
+            if expires > now {
+                return Ok(Resolved { addrs: addrs.clone(), ttl: Some(expires - now) });
+            }
+        }
+
+        // The lock isn't held while resolving, so a slow lookup doesn't
+        // hold up others
+        let resolved = self.inner.resolve(&host, waker)?;
+        let expires = now + resolved.ttl.unwrap_or(self.default_ttl);
+        self.cache.lock().unwrap().insert(host, (resolved.addrs.clone(), expires));
+
+        Ok(resolved)
+    }
+}
+
+/// A host being resolved by a [`ThreadedResolver`], or its answer
+///
+/// [`ThreadedResolver`]: struct.ThreadedResolver.html
+enum Lookup {
+    /// The wakers to wake once it's answered
+    Pending(Vec<Waker>),
+    /// The answer, and when it was made
+    Done(Result<Resolved, io::Error>, Instant),
+}
+
+type Lookups = Arc<Mutex<HashMap<String, Lookup>>>;
+
+/// Resolves with another resolver, which may block, on a pool of
+/// threads. A host that isn't answered yet fails with `WouldBlock`, and
+/// its wakers are woken once it is. Each answer is given to the next
+/// lookup of its host, so this is best wrapped in a [`CachingResolver`]
+/// when several connections look up the same hosts.
+///
+/// # Examples
+/// ```
+/// use twister_core::resolve::{CachingResolver, SystemResolver, ThreadedResolver};
+///
+/// let resolver = CachingResolver::new(ThreadedResolver::new(SystemResolver, 4));
+/// ```
+///
+/// [`CachingResolver`]: struct.CachingResolver.html
+pub struct ThreadedResolver {
+    jobs: mpsc::Sender<String>,
+    lookups: Lookups,
+}
+
+impl ThreadedResolver {
+    /// Resolves with `inner` on `threads` threads, which stop when the
+    /// resolver is dropped
+    ///
+    /// # Panics
+    /// If `threads` is zero, or a thread can't be started.
+    pub fn new<R>(inner: R, threads: usize) -> ThreadedResolver
+        where R: Resolver + Send + Sync + 'static
+    {
+        assert!(threads > 0, "a threaded resolver needs a thread");
+
+        let (jobs, queue) = mpsc::channel::<String>();
+        let queue = Arc::new(Mutex::new(queue));
+        let inner = Arc::new(inner);
+        let lookups = Lookups::default();
+
+        for _ in 0..threads {
+            let (queue, inner, lookups) = (queue.clone(), inner.clone(), lookups.clone());
+            thread::Builder::new()
+                .name("resolver".to_string())
+                .spawn(move || ThreadedResolver::work(&queue, &*inner, &lookups))
+                .expect("couldn't start a resolver thread");
+        }
+
+        ThreadedResolver {
+            jobs: jobs,
+            lookups: lookups,
+        }
+    }
+
+    fn work(queue: &Mutex<mpsc::Receiver<String>>, inner: &dyn Resolver, lookups: &Mutex<HashMap<String, Lookup>>) {
+        loop {
+            let host = match queue.lock().unwrap().recv() {
+                Ok(host) => host,
+                Err(_) => return,
+            };
+
+            let resolved = inner.resolve(&host, Waker::noop());
+            let done = Lookup::Done(resolved, Instant::now());
+            if let Some(Lookup::Pending(wakers)) = lookups.lock().unwrap().insert(host, done) {
+                for waker in wakers {
+                    waker.wake();
+                }
+            }
+        }
+    }
+}
+
+impl Resolver for ThreadedResolver {
+    fn resolve(&self, host: &str, waker: &Waker) -> Result<Resolved, io::Error> {
+        let host = host.to_ascii_lowercase();
+        let mut lookups = self.lookups.lock().unwrap();
+
+        match lookups.remove(&host) {
+            Some(Lookup::Done(resolved, _)) => return resolved,
+            Some(Lookup::Pending(mut wakers)) => {
+                if !wakers.iter().any(|w| w.will_wake(waker)) {
+                    wakers.push(waker.clone());
+                }
+                lookups.insert(host, Lookup::Pending(wakers));
+            },
+            None => {
+                // Answers nobody came back for are dropped as new
+                // lookups are made
+                let now = Instant::now();
+                lookups.retain(|_, lookup| match *lookup {
+                    Lookup::Done(_, at) => now - at < ABANDONED,
+                    Lookup::Pending(_) => true,
+                });
+
+                self.jobs.send(host.clone()).map_err(|_| io::Error::other("resolver threads have stopped"))?;
+                lookups.insert(host, Lookup::Pending(vec![waker.clone()]));
+            },
+        }
+
+        Err(io::ErrorKind::WouldBlock.into())
+    }
+}
+
+#[cfg(test)]
+mod resolve_should {
+    use super::*;
+    use std::cell::Cell;
+    use std::task::Wake;
+
+    /// Sends on a channel each time it's woken
+    struct Notify(Mutex<mpsc::Sender<()>>);
+
+    impl Wake for Notify {
+        fn wake(self: Arc<Self>) {
+            self.0.lock().unwrap().send(()).ok();
+        }
+    }
+
+    fn notify() -> (Waker, mpsc::Receiver<()>) {
+        let (woken, wakes) = mpsc::channel();
+        (Waker::from(Arc::new(Notify(Mutex::new(woken)))), wakes)
+    }
+
+    /// Answers every lookup with the number of lookups so far
+    struct Counting(Cell<u8>, Option<Duration>);
+
+    impl Resolver for Counting {
+        fn resolve(&self, _: &str, _: &Waker) -> Result<Resolved, io::Error> {
+            self.0.set(self.0.get() + 1);
+            Ok(Resolved { addrs: vec![IpAddr::from([192, 0, 2, self.0.get()])], ttl: self.1 })
+        }
+    }
+
+    fn hosts() -> StaticHosts {
+        "# Test hosts\n\
+         192.0.2.1   example.com www.example.com\n\
+         \n\
+         2001:db8::1 example.com  # Also IPv6\n".parse().unwrap()
+    }
+
+    #[test]
+    fn parse_hosts_files() {
+        let resolved = hosts().resolve("EXAMPLE.com", Waker::noop()).unwrap();
+        assert_eq!(vec!["192.0.2.1".parse::<IpAddr>().unwrap(), "2001:db8::1".parse().unwrap()], resolved.addrs);
+        assert_eq!(1, hosts().resolve("www.example.com", Waker::noop()).unwrap().addrs.len());
+        assert_eq!(io::ErrorKind::NotFound, hosts().resolve("example.org", Waker::noop()).unwrap_err().kind());
+
+        for hosts in &["192.0.2.1\n", "example.com 192.0.2.1\n"] {
+            assert_eq!(Err(InvalidHosts), hosts.parse::<StaticHosts>(), "{}", hosts);
+        }
+    }
+
+    #[test]
+    fn resolve_destinations_to_socket_addresses() {
+        let addrs = socket_addrs(&hosts(), "example.com:8080", Waker::noop()).unwrap();
+        assert_eq!(vec!["192.0.2.1:8080".parse::<SocketAddr>().unwrap(), "[2001:db8::1]:8080".parse().unwrap()], addrs);
+
+        // Addresses skip the resolver
+        assert_eq!(vec!["[::1]:443".parse::<SocketAddr>().unwrap()], socket_addrs(&hosts(), "[::1]:443", Waker::noop()).unwrap());
+        assert_eq!(io::ErrorKind::InvalidInput, socket_addrs(&hosts(), "example.com", Waker::noop()).unwrap_err().kind());
+    }
+
+    #[test]
+    fn cache_answers_until_their_ttl_passes() {
+        let resolver = CachingResolver::new(Counting(Cell::new(0), Some(Duration::from_secs(60))));
+        assert_eq!(resolver.resolve("example.com", Waker::noop()).unwrap().addrs, resolver.resolve("Example.com", Waker::noop()).unwrap().addrs);
+        assert_eq!(1, resolver.inner.0.get());
+
+        resolver.resolve("example.org", Waker::noop()).unwrap();
+        assert_eq!(2, resolver.inner.0.get());
+
+        let resolver = CachingResolver::new(Counting(Cell::new(0), Some(Duration::from_secs(0))));
+        resolver.resolve("example.com", Waker::noop()).unwrap();
+        resolver.resolve("example.com", Waker::noop()).unwrap();
+        assert_eq!(2, resolver.inner.0.get());
+    }
+
+    #[test]
+    fn cache_answers_without_a_ttl_for_the_default_time() {
+        let resolver = CachingResolver::new(Counting(Cell::new(0), None));
+        resolver.resolve("example.com", Waker::noop()).unwrap();
+        assert!(resolver.resolve("example.com", Waker::noop()).unwrap().ttl.unwrap() <= Duration::from_secs(60));
+        assert_eq!(1, resolver.inner.0.get());
+
+        let resolver = CachingResolver::new(Counting(Cell::new(0), None)).with_default_ttl(Duration::from_secs(0));
+        resolver.resolve("example.com", Waker::noop()).unwrap();
+        resolver.expire();
+        resolver.resolve("example.com", Waker::noop()).unwrap();
+        assert_eq!(2, resolver.inner.0.get());
+    }
+
+    #[test]
+    fn resolve_on_another_thread() {
+        let resolver = ThreadedResolver::new(hosts(), 2);
+        let (waker, wakes) = notify();
+
+        assert_eq!(io::ErrorKind::WouldBlock, resolver.resolve("Example.com", &waker).unwrap_err().kind());
+        wakes.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(hosts().resolve("example.com", Waker::noop()).unwrap(), resolver.resolve("example.com", &waker).unwrap());
+
+        // Each answer is only given once
+        assert_eq!(io::ErrorKind::WouldBlock, resolver.resolve("example.com", &waker).unwrap_err().kind());
+    }
+
+    #[test]
+    fn pass_on_failures_from_another_thread() {
+        let resolver = ThreadedResolver::new(hosts(), 1);
+        let (waker, wakes) = notify();
+
+        assert_eq!(io::ErrorKind::WouldBlock, resolver.resolve("example.org", &waker).unwrap_err().kind());
+        wakes.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(io::ErrorKind::NotFound, resolver.resolve("example.org", &waker).unwrap_err().kind());
+    }
+}
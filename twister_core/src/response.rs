@@ -0,0 +1,18 @@
+use twister_http::{Header, Response, StatusCode, Version};
+use twister_http::serialize::Serialize;
+
+/// Serializes a `HTTP/1.1` response that has no body - E.g.
+/// `status(StatusCode(404), b"Not Found", &[])`
+pub fn status(code: StatusCode, text: &[u8], headers: &[Header]) -> Vec<u8> {
+    let response = Response {
+        version: Version::Http11,
+        status_code: code,
+        status_text: text,
+        headers: headers,
+        body: b"",
+    };
+
+    let mut buffer = vec![0; response.serialized_len()];
+    response.write_to(&mut buffer).unwrap();
+    buffer
+}
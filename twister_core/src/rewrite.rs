@@ -0,0 +1,269 @@
+use std::net::IpAddr;
+
+use twister_http::{headers, Header, Request, Response, Version};
+
+/// Headers that only apply to a single connection, so are never
+/// forwarded. `Transfer-Encoding` is also hop-by-hop, but bodies are
+/// forwarded without being decoded so it must be kept.
+const HOP_BY_HOP: &[&[u8]] = &[
+    b"Connection",
+    b"Proxy-Connection",
+    b"Keep-Alive",
+    b"TE",
+    b"Upgrade",
+    b"Proxy-Authenticate",
+    b"Proxy-Authorization",
+];
+
+fn is_hop_by_hop(name: &[u8], headers: &[Header]) -> bool {
+    HOP_BY_HOP.iter().any(|hop| hop.eq_ignore_ascii_case(name)) ||
+        headers::get_list(headers, b"Connection").any(|option| option.eq_ignore_ascii_case(name))
+}
+
+/// Joins every `name` value with `value` as a comma-separated list
+fn append(headers: &[Header], name: &[u8], value: &[u8]) -> Vec<u8> {
+    let mut list = vec![];
+    for existing in headers::get_all(headers, name) {
+        list.extend(existing);
+        list.extend(b", ");
+    }
+
+    list.extend(value);
+    list
+}
+
+/// `value` as a quoted string - E.g. `"[::1]:8080"`
+fn quoted(value: &[u8]) -> Vec<u8> {
+    let mut quoted = vec![b'"'];
+    for byte in value {
+        if *byte == b'"' || *byte == b'\\' {
+            quoted.push(b'\\');
+        }
+        quoted.push(*byte);
+    }
+
+    quoted.push(b'"');
+    quoted
+}
+
+/// How the heads of forwarded requests and responses are rewritten.
+/// Hop-by-hop headers are removed, and the proxy adds itself to `Via`
+/// under its pseudonym. The client's address can also be passed on with
+/// `X-Forwarded-For`, `X-Forwarded-Proto`, or RFC 7239 `Forwarded`.
+///
+/// # Examples
+/// ```
+/// use twister_core::rewrite::Rewrite;
+///
+/// let rewrite = Rewrite::new("gateway").with_x_forwarded_for().with_forwarded();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Rewrite {
+    pseudonym: String,
+    x_forwarded_for: bool,
+    x_forwarded_proto: bool,
+    forwarded: bool,
+}
+
+impl Default for Rewrite {
+    /// Adds `Via` with the pseudonym `twister`, and nothing else
+    fn default() -> Rewrite {
+        Rewrite::new("twister")
+    }
+}
+
+impl Rewrite {
+    /// `pseudonym` identifies the proxy in `Via`, and should be unique
+    /// among the proxies a request may pass through
+    pub fn new(pseudonym: &str) -> Rewrite {
+        Rewrite {
+            pseudonym: pseudonym.to_string(),
+            x_forwarded_for: false,
+            x_forwarded_proto: false,
+            forwarded: false,
+        }
+    }
+
+    /// Appends the client's address to `X-Forwarded-For`
+    pub fn with_x_forwarded_for(mut self) -> Rewrite {
+        self.x_forwarded_for = true;
+        self
+    }
+
+    /// Adds `X-Forwarded-Proto: http` if an earlier proxy hasn't
+    pub fn with_x_forwarded_proto(mut self) -> Rewrite {
+        self.x_forwarded_proto = true;
+        self
+    }
+
+    /// Appends the client's address, the protocol and the requested host
+    /// to `Forwarded`
+    pub fn with_forwarded(mut self) -> Rewrite {
+        self.forwarded = true;
+        self
+    }
+
+    /// Returns `true` if the proxy's pseudonym is already in `Via`, so
+    /// the request has looped back to it
+    pub fn is_loop(&self, headers: &[Header]) -> bool {
+        let pseudonym = self.pseudonym.as_bytes();
+        match headers::via(headers) {
+            Ok(mut entries) => entries.any(|entry| entry.received_by.eq_ignore_ascii_case(pseudonym)),
+            Err(_) => false,
+        }
+    }
+
+    /// The `Via` entry for a message received with `version`
+    fn via(&self, version: Version) -> Vec<u8> {
+        let mut entry = version.as_bytes()[b"HTTP/".len()..].to_vec();
+        entry.push(b' ');
+        entry.extend(self.pseudonym.as_bytes());
+        entry
+    }
+
+    /// Writes the end-to-end headers of `request`, except `Host`, along
+    /// with the headers the proxy adds. `host` is the authority of the
+    /// request's target, and `client` is the client's address if it's
+    /// known. No `Connection` options are sent, so the upstream connection
+    /// can persist and be pooled.
+    pub(crate) fn write_request_headers(&self, out: &mut Vec<u8>, request: &Request, host: &[u8], client: Option<IpAddr>) {
+        let headers = request.headers;
+        let client = client.map(|client| client.to_string());
+        let mut skip: Vec<&[u8]> = vec![b"Host", b"Via"];
+        let mut added = vec![(&b"Via"[..], append(headers, b"Via", &self.via(request.version)))];
+
+        if let (true, Some(client)) = (self.x_forwarded_for, client.as_ref()) {
+            skip.push(b"X-Forwarded-For");
+            added.push((b"X-Forwarded-For", append(headers, b"X-Forwarded-For", client.as_bytes())));
+        }
+
+        if self.x_forwarded_proto && headers::get(headers, b"X-Forwarded-Proto").is_none() {
+            added.push((b"X-Forwarded-Proto", b"http".to_vec()));
+        }
+
+        if self.forwarded {
+            let mut element = b"for=".to_vec();
+            match client {
+                Some(ref client) if client.contains(':') => element.extend(quoted(format!("[{}]", client).as_bytes())),
+                Some(ref client) => element.extend(client.as_bytes()),
+                None => element.extend(b"unknown"),
+            }
+            element.extend(b";proto=http;host=");
+            element.extend(quoted(host));
+
+            skip.push(b"Forwarded");
+            added.push((b"Forwarded", append(headers, b"Forwarded", &element)));
+        }
+
+        write_headers(out, headers, &skip, &added, None);
+    }
+
+    /// Writes the end-to-end headers of `response`, along with the
+    /// headers the proxy adds. `dechunked` removes `Transfer-Encoding`, for
+    /// a body passed on without its chunked framing. `connection` is the
+    /// option that tells the client whether its connection is kept open,
+    /// if it needs telling.
+    pub(crate) fn write_response_headers(&self, out: &mut Vec<u8>, response: &Response, dechunked: bool, connection: Option<&[u8]>) {
+        let via = append(response.headers, b"Via", &self.via(response.version));
+        let skip: &[&[u8]] = if dechunked { &[b"Via", b"Transfer-Encoding"] } else { &[b"Via"] };
+        write_headers(out, response.headers, skip, &[(b"Via", via)], connection);
+    }
+}
+
+/// Writes the end-to-end headers that aren't in `skip`, then `added`,
+/// followed by `Connection` if there's a `connection` option and the
+/// blank line that ends the head.
+fn write_headers(out: &mut Vec<u8>, headers: &[Header], skip: &[&[u8]], added: &[(&[u8], Vec<u8>)], connection: Option<&[u8]>) {
+    let kept = headers.iter()
+        .filter(|header| !is_hop_by_hop(header.0, headers) && !skip.iter().any(|s| s.eq_ignore_ascii_case(header.0)))
+        .map(|header| (header.0, header.1));
+
+    for (name, value) in kept.chain(added.iter().map(|&(name, ref value)| (name, &value[..]))) {
+        out.extend(name);
+        out.extend(b": ");
+        out.extend(value);
+        out.extend(b"\r\n");
+    }
+
+    if let Some(option) = connection {
+        out.extend(b"Connection: ");
+        out.extend(option);
+        out.extend(b"\r\n");
+    }
+
+    out.extend(b"\r\n");
+}
+
+#[cfg(test)]
+mod rewrite_should {
+    use super::*;
+    use twister_http::parser::HttpObjectParser;
+
+    fn rewrite_request(rewrite: &Rewrite, request: &[u8], client: Option<IpAddr>) -> String {
+        let mut headers = [Header::default(); 16];
+        let request = HttpObjectParser::new(&mut headers).parse::<Request>(request).unwrap().unwrap();
+        let mut out = vec![];
+        rewrite.write_request_headers(&mut out, &request, b"example.com:8080", client);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn remove_hop_by_hop_headers() {
+        let out = rewrite_request(&Rewrite::default(),
+            b"GET http://example.com:8080/ HTTP/1.0\r\n\
+              Host: elsewhere\r\n\
+              Connection: keep-alive, X-Trace\r\n\
+              Keep-Alive: timeout=5\r\n\
+              Proxy-Connection: keep-alive\r\n\
+              Proxy-Authorization: Basic dTpw\r\n\
+              TE: trailers\r\n\
+              Upgrade: websocket\r\n\
+              X-Trace: 1\r\n\
+              Accept: */*\r\n\
+              \r\n",
+            None);
+
+        assert_eq!("Accept: */*\r\nVia: 1.0 twister\r\n\r\n", out);
+    }
+
+    #[test]
+    fn append_to_forwarding_headers() {
+        let rewrite = Rewrite::new("gw").with_x_forwarded_for().with_x_forwarded_proto().with_forwarded();
+        let out = rewrite_request(&rewrite,
+            b"GET http://example.com:8080/ HTTP/1.1\r\n\
+              Via: 1.1 first\r\n\
+              X-Forwarded-For: 192.0.2.1\r\n\
+              X-Forwarded-Proto: https\r\n\
+              Forwarded: for=192.0.2.1\r\n\
+              Via: 1.0 second\r\n\
+              \r\n",
+            Some("2001:db8::1".parse().unwrap()));
+
+        assert_eq!("X-Forwarded-Proto: https\r\n\
+                    Via: 1.1 first, 1.0 second, 1.1 gw\r\n\
+                    X-Forwarded-For: 192.0.2.1, 2001:db8::1\r\n\
+                    Forwarded: for=192.0.2.1, for=\"[2001:db8::1]\";proto=http;host=\"example.com:8080\"\r\n\r\n", out);
+    }
+
+    #[test]
+    fn add_forwarding_headers() {
+        let rewrite = Rewrite::default().with_x_forwarded_for().with_x_forwarded_proto().with_forwarded();
+        let out = rewrite_request(&rewrite, b"GET http://example.com:8080/ HTTP/1.1\r\n\r\n", Some("192.0.2.7".parse().unwrap()));
+
+        assert_eq!("Via: 1.1 twister\r\n\
+                    X-Forwarded-For: 192.0.2.7\r\n\
+                    X-Forwarded-Proto: http\r\n\
+                    Forwarded: for=192.0.2.7;proto=http;host=\"example.com:8080\"\r\n\r\n", out);
+
+        let out = rewrite_request(&Rewrite::default().with_forwarded(), b"GET http://example.com:8080/ HTTP/1.1\r\n\r\n", None);
+        assert!(out.contains("Forwarded: for=unknown;"));
+    }
+
+    #[test]
+    fn detect_loops_through_its_pseudonym() {
+        let rewrite = Rewrite::new("gw");
+        assert!(rewrite.is_loop(&[Header(b"Via", b"1.1 first, HTTP/1.1 GW (twister)")]));
+        assert!(!rewrite.is_loop(&[Header(b"Via", b"1.1 gateway")]));
+        assert!(!rewrite.is_loop(&[]));
+    }
+}
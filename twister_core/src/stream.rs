@@ -0,0 +1,22 @@
+use std::io;
+use std::net::{self, Shutdown};
+
+/// A stream whose write half can be closed while its read half stays
+/// open. Tunnels use this to pass an EOF from one side on to the other.
+pub trait HalfClose {
+    /// Signals EOF to the peer. Reading is unaffected.
+    fn close_write(&mut self) -> Result<(), io::Error>;
+}
+
+impl HalfClose for net::TcpStream {
+    fn close_write(&mut self) -> Result<(), io::Error> {
+        self.shutdown(Shutdown::Write)
+    }
+}
+
+#[cfg(feature = "mio")]
+impl HalfClose for ::mio::net::TcpStream {
+    fn close_write(&mut self) -> Result<(), io::Error> {
+        self.shutdown(Shutdown::Write)
+    }
+}
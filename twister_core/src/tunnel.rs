@@ -0,0 +1,290 @@
+use std::cmp;
+use std::io::{self, Read, Write};
+
+use stream::HalfClose;
+
+const BUFFER_SIZE: usize = 16 * 1024;
+
+/// Moves bytes in one direction through a fixed size buffer. Bytes
+/// are only removed from the buffer once they've been written, so a
+/// partial write never loses data.
+struct Relay {
+    buffer: Box<[u8]>,
+    start: usize,
+    end: usize,
+    eof: bool,
+    closed: bool,
+}
+
+impl Relay {
+    fn new() -> Relay {
+        Relay::with_data(&[])
+    }
+
+    /// A relay that starts with `data` waiting to be written
+    fn with_data(data: &[u8]) -> Relay {
+        let mut buffer = vec![0; cmp::max(BUFFER_SIZE, data.len())];
+        buffer[..data.len()].copy_from_slice(data);
+
+        Relay {
+            buffer: buffer.into_boxed_slice(),
+            start: 0,
+            end: data.len(),
+            eof: false,
+            closed: false,
+        }
+    }
+
+    /// Reads from `from` and writes to `to` until neither can make
+    /// progress. Once `from` reaches EOF and everything has been written,
+    /// the write half of `to` is closed.
+    fn poll<R: Read, W: Write + HalfClose>(&mut self, from: &mut R, to: &mut W) -> Result<(), io::Error> {
+        loop {
+            let mut progress = false;
+
+            if self.start < self.end {
+                match to.write(&self.buffer[self.start..self.end]) {
+                    Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                    Ok(n) => {
+                        self.start += n;
+                        progress = true;
+                    },
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {},
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if self.start == self.end {
+                self.start = 0;
+                self.end = 0;
+            }
+            else if self.end == self.buffer.len() && self.start > 0 {
+                self.buffer.copy_within(self.start..self.end, 0);
+                self.end -= self.start;
+                self.start = 0;
+            }
+
+            if !self.eof && self.end < self.buffer.len() {
+                match from.read(&mut self.buffer[self.end..]) {
+                    Ok(0) => {
+                        self.eof = true;
+                        progress = true;
+                    },
+                    Ok(n) => {
+                        self.end += n;
+                        progress = true;
+                    },
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {},
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if !progress {
+                break;
+            }
+        }
+
+        if self.eof && self.start == self.end && !self.closed {
+            match to.close_write() {
+                Ok(()) => self.closed = true,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {},
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `true` once the source has closed, everything read from it has
+    /// been written, and the destination has been told
+    fn is_done(&self) -> bool {
+        self.closed
+    }
+}
+
+/// Relays bytes between a client and an upstream server in both
+/// directions at once.
+pub struct Tunnel<S, U> {
+    client: S,
+    upstream: U,
+    outbound: Relay,
+    inbound: Relay,
+}
+
+impl<S, U> Tunnel<S, U>
+    where S: Read + Write + HalfClose,
+          U: Read + Write + HalfClose,
+{
+    pub fn new(client: S, upstream: U) -> Tunnel<S, U> {
+        Tunnel {
+            client: client,
+            upstream: upstream,
+            outbound: Relay::new(),
+            inbound: Relay::new(),
+        }
+    }
+
+    /// Bytes the client sent after its `CONNECT` request, before the
+    /// tunnel was established. They're relayed upstream before anything
+    /// else the client sends.
+    pub fn with_early_data(mut self, data: &[u8]) -> Tunnel<S, U> {
+        self.outbound = Relay::with_data(data);
+        self
+    }
+
+    /// Relays as much as possible in both directions without blocking.
+    ///
+    /// # Return Value
+    /// `true` once the tunnel is finished. When one side closes, the
+    /// write half of the other side is closed, and the other direction
+    /// keeps relaying. The tunnel finishes once both sides have closed.
+    pub fn poll(&mut self) -> Result<bool, io::Error> {
+        self.outbound.poll(&mut self.client, &mut self.upstream)?;
+        self.inbound.poll(&mut self.upstream, &mut self.client)?;
+
+        Ok(self.outbound.is_done() && self.inbound.is_done())
+    }
+
+    pub fn into_inner(self) -> S {
+        self.client
+    }
+}
+
+#[cfg(test)]
+mod tunnel_should {
+    use super::*;
+    use std::io::Cursor;
+    use std::cmp;
+
+    /// A stream that, when `choppy`, alternates between blocking and
+    /// transferring a few bytes, in both directions. Once its input is
+    /// exhausted it either reports EOF or blocks.
+    struct Choppy {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+        choppy: bool,
+        read_blocks: bool,
+        write_blocks: bool,
+        eof: bool,
+        write_closed: bool,
+    }
+
+    impl Choppy {
+        fn new(input: Vec<u8>, eof: bool) -> Choppy {
+            Choppy {
+                input: Cursor::new(input),
+                output: vec![],
+                choppy: true,
+                read_blocks: false,
+                write_blocks: false,
+                eof: eof,
+                write_closed: false,
+            }
+        }
+
+        fn smooth(input: &[u8], eof: bool) -> Choppy {
+            Choppy {
+                choppy: false,
+                ..Choppy::new(input.to_vec(), eof)
+            }
+        }
+    }
+
+    impl Read for Choppy {
+        fn read(&mut self, buffer: &mut [u8]) -> Result<usize, io::Error> {
+            if self.choppy {
+                self.read_blocks = !self.read_blocks;
+                if self.read_blocks {
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+            }
+
+            let to_read = if self.choppy { cmp::min(7, buffer.len()) } else { buffer.len() };
+            match self.input.read(&mut buffer[..to_read])? {
+                0 if !self.eof => Err(io::ErrorKind::WouldBlock.into()),
+                n => Ok(n),
+            }
+        }
+    }
+
+    impl HalfClose for Choppy {
+        fn close_write(&mut self) -> Result<(), io::Error> {
+            self.write_closed = true;
+            Ok(())
+        }
+    }
+
+    impl Write for Choppy {
+        fn write(&mut self, buffer: &[u8]) -> Result<usize, io::Error> {
+            assert!(!self.write_closed, "Write after close_write");
+
+            if self.choppy {
+                self.write_blocks = !self.write_blocks;
+                if self.write_blocks {
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+            }
+
+            let to_write = if self.choppy { cmp::min(5, buffer.len()) } else { buffer.len() };
+            self.output.write(&buffer[..to_write])
+        }
+
+        fn flush(&mut self) -> Result<(), io::Error> {
+            Ok(())
+        }
+    }
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|n| (n % 251) as u8).collect()
+    }
+
+    #[test]
+    fn relay_without_losing_bytes() {
+        let sent = data(BUFFER_SIZE * 3);
+        let received = data(BUFFER_SIZE * 2 + 1);
+        let mut tunnel = Tunnel::new(Choppy::new(sent.clone(), true), Choppy::new(received.clone(), true));
+
+        while !tunnel.poll().unwrap() {}
+
+        assert_eq!(sent, tunnel.upstream.output);
+        assert_eq!(received, tunnel.client.output);
+    }
+
+    #[test]
+    fn relay_both_directions_in_one_poll() {
+        let mut tunnel = Tunnel::new(Choppy::smooth(b"ping", false), Choppy::smooth(b"pong", false));
+        assert!(!tunnel.poll().unwrap());
+        assert!(!tunnel.poll().unwrap());
+
+        assert_eq!(b"ping", &*tunnel.upstream.output);
+        assert_eq!(b"pong", &*tunnel.client.output);
+    }
+
+    #[test]
+    fn relay_early_data_first() {
+        let early = data(BUFFER_SIZE + 1);
+        let mut tunnel = Tunnel::new(Choppy::new(b"after".to_vec(), true), Choppy::new(vec![], true))
+            .with_early_data(&early);
+
+        while !tunnel.poll().unwrap() {}
+
+        assert_eq!(&early[..], &tunnel.upstream.output[..early.len()]);
+        assert_eq!(b"after", &tunnel.upstream.output[early.len()..]);
+    }
+
+    #[test]
+    fn keep_relaying_after_one_side_closes() {
+        let mut tunnel = Tunnel::new(Choppy::smooth(b"ping", true), Choppy::smooth(b"po", false));
+        assert!(!tunnel.poll().unwrap());
+        assert!(tunnel.upstream.write_closed);
+        assert!(!tunnel.client.write_closed);
+
+        tunnel.upstream.input.get_mut().extend(b"ng");
+        tunnel.upstream.eof = true;
+        assert!(tunnel.poll().unwrap());
+        assert!(tunnel.client.write_closed);
+
+        assert_eq!(b"ping", &*tunnel.upstream.output);
+        assert_eq!(b"pong", &*tunnel.client.output);
+    }
+}
@@ -0,0 +1,630 @@
+use core::cmp;
+use core::fmt;
+use {Header, HttpMethod, Request, Response};
+use headers;
+use parser::{is_token, is_whitespace, HeaderParser, ParseError, Status};
+
+/// How the end of a HTTP object's body is determined. See
+/// RFC 9112, section 6.3.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Framing {
+    /// The body is exactly this many bytes long. A body of length
+    /// zero also describes objects that can't have a body, such as a
+    /// response to a `HEAD` request.
+    Length(u64),
+    /// The body uses the *chunked* transfer coding
+    Chunked,
+    /// The body continues until the connection is closed. Only
+    /// responses can be framed this way.
+    Close,
+}
+
+/// The errors that can occur when framing or decoding a body. Errors
+/// produced while decoding carry the byte offset, relative to the start
+/// of the data given to the decoder, at which decoding failed.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BodyError {
+    /// A `Content-Length` value isn't a number, or there are several
+    /// values that disagree
+    InvalidContentLength,
+    /// A `Transfer-Encoding` value isn't a list of codings, or the
+    /// *chunked* coding is applied more than once, or a request's
+    /// final coding isn't *chunked*
+    InvalidTransferEncoding,
+    /// A request has both `Content-Length` and `Transfer-Encoding`
+    ConflictingFraming,
+    /// A chunk size isn't hexadecimal, or is too large
+    InvalidChunkSize(usize),
+    /// A chunk extension isn't of the form `;name` or `;name=value`
+    InvalidChunkExtension(usize),
+    /// A chunk's data isn't followed by a line ending
+    InvalidChunkData(usize),
+    /// A trailer field is invalid
+    InvalidTrailer(ParseError),
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::BodyError::*;
+
+        match *self {
+            InvalidContentLength => write!(f, "invalid content length"),
+            InvalidTransferEncoding => write!(f, "invalid transfer encoding"),
+            ConflictingFraming => write!(f, "both content length and transfer encoding present"),
+            InvalidChunkSize(n) => write!(f, "invalid chunk size at byte {}", n),
+            InvalidChunkExtension(n) => write!(f, "invalid chunk extension at byte {}", n),
+            InvalidChunkData(n) => write!(f, "invalid chunk data at byte {}", n),
+            InvalidTrailer(e) => write!(f, "invalid trailer: {}", e),
+        }
+    }
+}
+
+/// Combines every `Content-Length` value. Repeated values are allowed
+/// provided they're identical.
+fn content_length(headers: &[Header]) -> Result<Option<u64>, BodyError> {
+    headers::content_length(headers).map_err(|_| BodyError::InvalidContentLength)
+}
+
+/// Determines whether the final `Transfer-Encoding` coding is
+/// *chunked*. Returns `None` if there's no `Transfer-Encoding`.
+fn transfer_coding(headers: &[Header]) -> Result<Option<bool>, BodyError> {
+    headers::transfer_encoding(headers)
+        .map(|codings| codings.map(|codings| codings.is_chunked()))
+        .map_err(|_| BodyError::InvalidTransferEncoding)
+}
+
+/// Determines how a request's body is framed.
+///
+/// A request that uses neither `Content-Length` nor `Transfer-Encoding`
+/// has no body. A request using both is rejected, as is a request whose
+/// final transfer coding isn't *chunked*, because its length can't be
+/// determined reliably.
+///
+/// # Examples
+///
+/// ```
+/// use twister_http::{Header, HttpMethod, Request, Version};
+/// use twister_http::body::{self, Framing};
+///
+/// let headers = [Header(b"Content-Length", b"13")];
+/// let request = Request {
+///     method: HttpMethod::Post,
+///     path: b"/",
+///     version: Version::Http11,
+///     headers: &headers,
+///     body: b"",
+/// };
+///
+/// assert_eq!(Ok(Framing::Length(13)), body::request_framing(&request));
+/// ```
+pub fn request_framing(request: &Request) -> Result<Framing, BodyError> {
+    match (transfer_coding(request.headers)?, content_length(request.headers)?) {
+        (Some(_), Some(_)) => Err(BodyError::ConflictingFraming),
+        (Some(true), None) => Ok(Framing::Chunked),
+        (Some(false), None) => Err(BodyError::InvalidTransferEncoding),
+        (None, Some(length)) => Ok(Framing::Length(length)),
+        (None, None) => Ok(Framing::Length(0)),
+    }
+}
+
+/// Determines how a response's body is framed. `method` is the method of
+/// the request the response answers.
+///
+/// Responses to `HEAD` requests, `1xx`, `204` and `304` responses, and
+/// `2xx` responses to `CONNECT` requests have no body. A `2xx` response to
+/// `CONNECT` means the connection becomes a tunnel once its headers end.
+pub fn response_framing(response: &Response, method: &HttpMethod) -> Result<Framing, BodyError> {
+    let no_body = matches!((method, response.status_code.0),
+        (&HttpMethod::Head, _) |
+        (&HttpMethod::Connect, 200..=299) |
+        (_, 100..=199) | (_, 204) | (_, 304));
+
+    if no_body {
+        return Ok(Framing::Length(0));
+    }
+
+    // `Transfer-Encoding` overrides `Content-Length`
+    match (transfer_coding(response.headers)?, content_length(response.headers)?) {
+        (Some(true), _) => Ok(Framing::Chunked),
+        (Some(false), _) => Ok(Framing::Close),
+        (None, Some(length)) => Ok(Framing::Length(length)),
+        (None, None) => Ok(Framing::Close),
+    }
+}
+
+/// A part of a body produced by [`BodyDecoder::decode`]
+///
+/// [`BodyDecoder::decode`]: enum.BodyDecoder.html#method.decode
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BodyPart<'a> {
+    /// The start of a chunk of the given size. Only produced for chunked
+    /// bodies. A chunk of size zero is the last chunk, and is followed by
+    /// any trailer fields.
+    Chunk(u64),
+    /// Some of the body's data
+    Data(&'a [u8]),
+    /// A trailer field of a chunked body
+    Trailer(Header<'a>),
+    /// The end of the body. Any data that follows belongs to the next
+    /// HTTP object.
+    End,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum ChunkState {
+    Size,
+    Data(u64),
+    DataEnd,
+    Trailers,
+}
+
+/// Consumes the line ending at the start of `data`, returning the
+/// number of bytes it occupies. `None` means `data` doesn't start
+/// with a line ending.
+fn newline_len(data: &[u8]) -> Option<Status<usize>> {
+    match data.first() {
+        None => Some(Status::Partial),
+        Some(&b'\n') => Some(Status::Complete(1)),
+        Some(&b'\r') => match data.get(1) {
+            None => Some(Status::Partial),
+            Some(&b'\n') => Some(Status::Complete(2)),
+            Some(_) => None,
+        },
+        Some(_) => None,
+    }
+}
+
+fn skip_whitespace(data: &[u8], from: usize) -> usize {
+    data[from..].iter()
+        .position(|byte| !is_whitespace(*byte))
+        .map(|p| from + p)
+        .unwrap_or(data.len())
+}
+
+fn skip_token(data: &[u8], from: usize) -> usize {
+    data[from..].iter()
+        .position(|byte| !is_token(*byte))
+        .map(|p| from + p)
+        .unwrap_or(data.len())
+}
+
+/// Skips a quoted string starting at `from`, returning the offset
+/// just past its closing quote, or `None` if `data` ends first.
+fn skip_quoted_string(data: &[u8], from: usize) -> Result<Option<usize>, BodyError> {
+    let mut pos = from + 1;
+    while pos < data.len() {
+        match data[pos] {
+            b'"' => return Ok(Some(pos + 1)),
+            b'\\' => pos += 2,
+            b'\t' | b' '..=b'~' | 0x80..=0xff => pos += 1,
+            _ => return Err(BodyError::InvalidChunkExtension(pos)),
+        }
+    }
+
+    Ok(None)
+}
+
+/// Parses a chunk's size line, including any extensions. Returns
+/// the chunk size and the length of the line.
+fn parse_chunk_size(data: &[u8]) -> Result<Status<(u64, usize)>, BodyError> {
+    let digits = data.iter()
+        .position(|byte| !byte.is_ascii_hexdigit())
+        .unwrap_or(data.len());
+
+    if digits == 0 && !data.is_empty() {
+        return Err(BodyError::InvalidChunkSize(0));
+    }
+
+    let mut size = 0_u64;
+    for (i, byte) in data[..digits].iter().enumerate() {
+        let value = (*byte as char).to_digit(16).unwrap() as u64;
+        size = size.checked_mul(16)
+            .and_then(|n| n.checked_add(value))
+            .ok_or(BodyError::InvalidChunkSize(i))?;
+    }
+
+    let mut pos = digits;
+    loop {
+        pos = skip_whitespace(data, pos);
+        match data.get(pos) {
+            None => return Ok(Status::Partial),
+            Some(&b';') => {},
+            Some(_) => break,
+        }
+
+        pos = skip_whitespace(data, pos + 1);
+        let name_end = skip_token(data, pos);
+        if name_end == data.len() {
+            return Ok(Status::Partial);
+        }
+        else if name_end == pos {
+            return Err(BodyError::InvalidChunkExtension(pos));
+        }
+
+        pos = skip_whitespace(data, name_end);
+        if data.get(pos) != Some(&b'=') {
+            continue;
+        }
+
+        pos = skip_whitespace(data, pos + 1);
+        pos = match data.get(pos) {
+            None => return Ok(Status::Partial),
+            Some(&b'"') => match skip_quoted_string(data, pos)? {
+                Some(end) => end,
+                None => return Ok(Status::Partial),
+            },
+            Some(_) => {
+                let value_end = skip_token(data, pos);
+                if value_end == pos {
+                    return Err(BodyError::InvalidChunkExtension(pos));
+                }
+                value_end
+            },
+        };
+    }
+
+    match newline_len(&data[pos..]) {
+        Some(Status::Complete(n)) => Ok(Status::Complete((size, pos + n))),
+        Some(Status::Partial) => Ok(Status::Partial),
+        None if pos == digits => Err(BodyError::InvalidChunkSize(pos)),
+        None => Err(BodyError::InvalidChunkExtension(pos)),
+    }
+}
+
+/// An incremental decoder for a HTTP object's body. The decoder doesn't
+/// buffer or copy any data. Instead, each call to [`decode`] consumes
+/// some of the data given to it and produces a [`BodyPart`].
+///
+/// [`decode`]: enum.BodyDecoder.html#method.decode
+/// [`BodyPart`]: enum.BodyPart.html
+pub enum BodyDecoder {
+    #[doc(hidden)]
+    Length(u64),
+    #[doc(hidden)]
+    Chunked(ChunkedDecoder),
+    #[doc(hidden)]
+    Close,
+    #[doc(hidden)]
+    Done,
+}
+
+/// The state of a chunked body's decoding. See [`BodyDecoder`]
+///
+/// [`BodyDecoder`]: enum.BodyDecoder.html
+pub struct ChunkedDecoder(ChunkState);
+
+impl BodyDecoder {
+    /// Creates a new instance to decode a body framed as `framing`.
+    pub fn new(framing: Framing) -> BodyDecoder {
+        match framing {
+            Framing::Length(length) => BodyDecoder::Length(length),
+            Framing::Chunked => BodyDecoder::Chunked(ChunkedDecoder(ChunkState::Size)),
+            Framing::Close => BodyDecoder::Close,
+        }
+    }
+
+    /// Decodes the next part of the body from the start of `data`.
+    ///
+    /// # Return Value
+    /// If successful, a tuple is returned consisting of `(consumed: usize,
+    /// part: BodyPart)`. `consumed` is the number of bytes of `data` that
+    /// were used. The next call should be given the data that follows them.
+    ///
+    /// Once [`BodyPart::End`] is returned the body is complete, and any
+    /// data following the consumed bytes is the start of the next
+    /// HTTP object. A body framed by [`Framing::Close`] never ends this way;
+    /// it ends when the connection closes.
+    ///
+    /// If `data` doesn't contain enough to produce a part then
+    /// [`Status::Partial`] is returned and nothing is consumed.
+    ///
+    /// # Panics
+    /// This function will `panic` if it's called after the body has ended.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use twister_http::body::{BodyDecoder, BodyPart, Framing};
+    ///
+    /// const BODY: &'static [u8] = b"5\r\nHello\r\n0\r\n\r\nGET / HTTP/1.1\r\n";
+    ///
+    /// let mut decoder = BodyDecoder::new(Framing::Chunked);
+    /// let mut data = BODY;
+    /// let mut body = vec![];
+    ///
+    /// loop {
+    ///     let (consumed, part) = decoder.decode(data).unwrap().unwrap();
+    ///     data = &data[consumed..];
+    ///
+    ///     match part {
+    ///         BodyPart::Data(bytes) => body.extend_from_slice(bytes),
+    ///         BodyPart::End => break,
+    ///         _ => {},
+    ///     }
+    /// }
+    ///
+    /// assert_eq!(b"Hello", &*body);
+    /// assert_eq!(b"GET / HTTP/1.1\r\n", data);
+    /// ```
+    ///
+    /// [`BodyPart::End`]: enum.BodyPart.html#variant.End
+    /// [`Framing::Close`]: enum.Framing.html#variant.Close
+    /// [`Status::Partial`]: ../parser/enum.Status.html#variant.Partial
+    pub fn decode<'a>(&mut self, data: &'a [u8]) -> Result<Status<(usize, BodyPart<'a>)>, BodyError> {
+        match *self {
+            BodyDecoder::Length(0) => {
+                *self = BodyDecoder::Done;
+                Ok(Status::Complete((0, BodyPart::End)))
+            },
+            BodyDecoder::Length(ref mut remaining) => {
+                if data.is_empty() {
+                    return Ok(Status::Partial);
+                }
+
+                let n = cmp::min(*remaining, data.len() as u64) as usize;
+                *remaining -= n as u64;
+                Ok(Status::Complete((n, BodyPart::Data(&data[..n]))))
+            },
+            BodyDecoder::Chunked(ref mut decoder) => {
+                let result = decoder.decode(data);
+                if let Ok(Status::Complete((_, BodyPart::End))) = result {
+                    *self = BodyDecoder::Done;
+                }
+
+                result
+            },
+            BodyDecoder::Close => {
+                if data.is_empty() {
+                    return Ok(Status::Partial);
+                }
+
+                Ok(Status::Complete((data.len(), BodyPart::Data(data))))
+            },
+            BodyDecoder::Done => panic!("decode called after end"),
+        }
+    }
+}
+
+impl ChunkedDecoder {
+    fn decode<'a>(&mut self, data: &'a [u8]) -> Result<Status<(usize, BodyPart<'a>)>, BodyError> {
+        match self.0 {
+            ChunkState::Size => {
+                let (size, n) = match parse_chunk_size(data)? {
+                    Status::Complete(parts) => parts,
+                    Status::Partial => return Ok(Status::Partial),
+                };
+
+                self.0 = if size == 0 { ChunkState::Trailers } else { ChunkState::Data(size) };
+                Ok(Status::Complete((n, BodyPart::Chunk(size))))
+            },
+            ChunkState::Data(remaining) => {
+                if data.is_empty() {
+                    return Ok(Status::Partial);
+                }
+
+                let n = cmp::min(remaining, data.len() as u64) as usize;
+                self.0 = match remaining - n as u64 {
+                    0 => ChunkState::DataEnd,
+                    remaining => ChunkState::Data(remaining),
+                };
+                Ok(Status::Complete((n, BodyPart::Data(&data[..n]))))
+            },
+            ChunkState::DataEnd => {
+                // The line ending is consumed along with the next chunk's
+                // size, so it isn't reported as a part of its own
+                let n = match newline_len(data) {
+                    Some(Status::Complete(n)) => n,
+                    Some(Status::Partial) => return Ok(Status::Partial),
+                    None => return Err(BodyError::InvalidChunkData(0)),
+                };
+
+                let result = parse_chunk_size(&data[n..])
+                    .map_err(|e| match e {
+                        BodyError::InvalidChunkSize(p) => BodyError::InvalidChunkSize(p + n),
+                        BodyError::InvalidChunkExtension(p) => BodyError::InvalidChunkExtension(p + n),
+                        e => e,
+                    })?;
+
+                let (size, len) = match result {
+                    Status::Complete(parts) => parts,
+                    Status::Partial => return Ok(Status::Partial),
+                };
+
+                self.0 = if size == 0 { ChunkState::Trailers } else { ChunkState::Data(size) };
+                Ok(Status::Complete((n + len, BodyPart::Chunk(size))))
+            },
+            ChunkState::Trailers => {
+                let (header, tail) = match HeaderParser::new(data).parse()
+                    .map_err(BodyError::InvalidTrailer)?
+                {
+                    Status::Complete(parts) => parts,
+                    Status::Partial => return Ok(Status::Partial),
+                };
+
+                let n = data.len() - tail.len();
+                if header.0.is_empty() {
+                    return Ok(Status::Complete((n, BodyPart::End)));
+                }
+
+                Ok(Status::Complete((n, BodyPart::Trailer(header))))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod framing_should {
+    use super::*;
+    use {HttpMethod, Request, Response, StatusCode, Version};
+
+    fn request<'a>(headers: &'a [Header<'a>]) -> Request<'a> {
+        Request {
+            method: HttpMethod::Post,
+            path: b"/",
+            version: Version::Http11,
+            headers: headers,
+            body: b"",
+        }
+    }
+
+    fn response<'a>(status: u16, headers: &'a [Header<'a>]) -> Response<'a> {
+        Response {
+            version: Version::Http11,
+            status_code: StatusCode(status),
+            status_text: b"",
+            headers: headers,
+            body: b"",
+        }
+    }
+
+    #[test]
+    fn frame_requests() {
+        assert_eq!(Ok(Framing::Length(0)), request_framing(&request(&[])));
+        assert_eq!(Ok(Framing::Length(5)),
+                   request_framing(&request(&[Header(b"content-length", b"5")])));
+        assert_eq!(Ok(Framing::Length(5)),
+                   request_framing(&request(&[Header(b"Content-Length", b"5, 5"),
+                                              Header(b"Content-Length", b"5")])));
+        assert_eq!(Ok(Framing::Chunked),
+                   request_framing(&request(&[Header(b"Transfer-Encoding", b"gzip, Chunked")])));
+    }
+
+    #[test]
+    fn reject_ambiguous_requests() {
+        assert_eq!(Err(BodyError::InvalidContentLength),
+                   request_framing(&request(&[Header(b"Content-Length", b"5, 6")])));
+        assert_eq!(Err(BodyError::InvalidContentLength),
+                   request_framing(&request(&[Header(b"Content-Length", b"+5")])));
+        assert_eq!(Err(BodyError::InvalidContentLength),
+                   request_framing(&request(&[Header(b"Content-Length", b"")])));
+        assert_eq!(Err(BodyError::InvalidTransferEncoding),
+                   request_framing(&request(&[Header(b"Transfer-Encoding", b"chunked, gzip")])));
+        assert_eq!(Err(BodyError::InvalidTransferEncoding),
+                   request_framing(&request(&[Header(b"Transfer-Encoding", b"chunked"),
+                                              Header(b"Transfer-Encoding", b"chunked")])));
+        assert_eq!(Err(BodyError::ConflictingFraming),
+                   request_framing(&request(&[Header(b"Transfer-Encoding", b"chunked"),
+                                              Header(b"Content-Length", b"5")])));
+    }
+
+    #[test]
+    fn frame_responses() {
+        let chunked = [Header(b"Transfer-Encoding", b"chunked"), Header(b"Content-Length", b"5")];
+        let length = [Header(b"Content-Length", b"5")];
+
+        assert_eq!(Ok(Framing::Chunked), response_framing(&response(200, &chunked), &HttpMethod::Get));
+        assert_eq!(Ok(Framing::Length(5)), response_framing(&response(200, &length), &HttpMethod::Get));
+        assert_eq!(Ok(Framing::Close), response_framing(&response(200, &[]), &HttpMethod::Get));
+        assert_eq!(Ok(Framing::Close),
+                   response_framing(&response(200, &[Header(b"Transfer-Encoding", b"gzip")]), &HttpMethod::Get));
+    }
+
+    #[test]
+    fn frame_responses_without_bodies() {
+        let length = [Header(b"Content-Length", b"5")];
+
+        assert_eq!(Ok(Framing::Length(0)), response_framing(&response(200, &length), &HttpMethod::Head));
+        assert_eq!(Ok(Framing::Length(0)), response_framing(&response(200, &length), &HttpMethod::Connect));
+        assert_eq!(Ok(Framing::Length(0)), response_framing(&response(101, &[]), &HttpMethod::Get));
+        assert_eq!(Ok(Framing::Length(0)), response_framing(&response(204, &[]), &HttpMethod::Get));
+        assert_eq!(Ok(Framing::Length(0)), response_framing(&response(304, &length), &HttpMethod::Get));
+        assert_eq!(Ok(Framing::Length(5)), response_framing(&response(407, &length), &HttpMethod::Connect));
+    }
+}
+
+#[cfg(test)]
+mod body_decoder_should {
+    use super::*;
+
+    fn decode_all<'a>(decoder: &mut BodyDecoder, mut data: &'a [u8]) -> (Vec<BodyPart<'a>>, &'a [u8]) {
+        let mut parts = vec![];
+        loop {
+            match decoder.decode(data).unwrap() {
+                Status::Complete((n, part)) => {
+                    data = &data[n..];
+                    parts.push(part);
+                    if part == BodyPart::End {
+                        return (parts, data);
+                    }
+                },
+                Status::Partial => return (parts, data),
+            }
+        }
+    }
+
+    #[test]
+    fn decode_fixed_length_body() {
+        let mut decoder = BodyDecoder::new(Framing::Length(5));
+        let (parts, tail) = decode_all(&mut decoder, b"HelloGET");
+
+        assert_eq!(vec![BodyPart::Data(b"Hello"), BodyPart::End], parts);
+        assert_eq!(b"GET", tail);
+    }
+
+    #[test]
+    fn decode_chunked_body() {
+        let mut decoder = BodyDecoder::new(Framing::Chunked);
+        let (parts, tail) = decode_all(
+            &mut decoder,
+            b"5;name=value;quoted=\"a \\\" b\"\r\nHello\r\n\
+              8 ; ext\r\n, World!\r\n\
+              0\r\n\
+              Expires: never\r\n\
+              \r\n\
+              GET / HTTP/1.1\r\n");
+
+        assert_eq!(vec![
+            BodyPart::Chunk(5),
+            BodyPart::Data(b"Hello"),
+            BodyPart::Chunk(8),
+            BodyPart::Data(b", World!"),
+            BodyPart::Chunk(0),
+            BodyPart::Trailer(Header(b"Expires", b"never")),
+            BodyPart::End,
+        ], parts);
+        assert_eq!(b"GET / HTTP/1.1\r\n", tail);
+    }
+
+    #[test]
+    fn decode_trickled_chunked_body() {
+        const BODY: &[u8] = b"a\r\n0123456789\r\n0\r\n\r\n";
+
+        let mut decoder = BodyDecoder::new(Framing::Chunked);
+        let mut body = vec![];
+        let mut start = 0;
+
+        for end in 0..BODY.len() + 1 {
+            let (parts, tail) = decode_all(&mut decoder, &BODY[start..end]);
+            start = end - tail.len();
+
+            for part in parts {
+                match part {
+                    BodyPart::Data(data) => body.extend_from_slice(data),
+                    BodyPart::End => {
+                        assert_eq!(b"0123456789", &*body);
+                        assert_eq!(BODY.len(), end);
+                        return;
+                    },
+                    _ => {},
+                }
+            }
+        }
+
+        panic!("Body never ended");
+    }
+
+    #[test]
+    fn reject_invalid_chunks() {
+        assert_eq!(Err(BodyError::InvalidChunkSize(0)),
+                   BodyDecoder::new(Framing::Chunked).decode(b"x\r\n"));
+        assert_eq!(Err(BodyError::InvalidChunkSize(16)),
+                   BodyDecoder::new(Framing::Chunked).decode(b"10000000000000000\r\n"));
+        assert_eq!(Err(BodyError::InvalidChunkExtension(3)),
+                   BodyDecoder::new(Framing::Chunked).decode(b"1; =x\r\n"));
+
+        let mut decoder = BodyDecoder::new(Framing::Chunked);
+        assert_eq!(Ok(Status::Complete((3, BodyPart::Chunk(1)))), decoder.decode(b"1\r\nab\r\n"));
+        assert_eq!(Ok(Status::Complete((1, BodyPart::Data(b"a")))), decoder.decode(b"ab\r\n"));
+        assert_eq!(Err(BodyError::InvalidChunkData(0)), decoder.decode(b"b\r\n"));
+    }
+}
@@ -0,0 +1,779 @@
+use core::fmt;
+use core::slice;
+
+use Header;
+use parser::{is_token, trim_whitespace};
+use target::Authority;
+
+/// The value of the first header named `name`, compared ASCII
+/// case-insensitively. See [`Request::get`].
+///
+/// [`Request::get`]: ../struct.Request.html#method.get
+pub fn get<'a>(headers: &'a [Header<'a>], name: &[u8]) -> Option<&'a [u8]> {
+    get_all(headers, name).next()
+}
+
+/// The values of every header named `name`, in the order they appear.
+/// See [`Request::get_all`].
+///
+/// [`Request::get_all`]: ../struct.Request.html#method.get_all
+pub fn get_all<'a, 'n>(headers: &'a [Header<'a>], name: &'n [u8]) -> GetAll<'a, 'n> {
+    GetAll {
+        headers: headers.iter(),
+        name: name,
+    }
+}
+
+/// The elements of every comma-separated header named `name`. See
+/// [`Request::get_list`].
+///
+/// [`Request::get_list`]: ../struct.Request.html#method.get_list
+pub fn get_list<'a, 'n>(headers: &'a [Header<'a>], name: &'n [u8]) -> ListValues<'a, 'n> {
+    ListValues {
+        fields: get_all(headers, name),
+        rest: None,
+    }
+}
+
+/// An iterator over the values of the headers with a given name.
+/// Created by [`get_all`].
+///
+/// [`get_all`]: fn.get_all.html
+#[derive(Debug, Clone)]
+pub struct GetAll<'a, 'n> {
+    headers: slice::Iter<'a, Header<'a>>,
+    name: &'n [u8],
+}
+
+impl<'a, 'n> Iterator for GetAll<'a, 'n> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let name = self.name;
+        self.headers
+            .find(|header| header.0.eq_ignore_ascii_case(name))
+            .map(|header| header.1)
+    }
+}
+
+/// An iterator over the elements of comma-separated list headers.
+/// Elements are trimmed of whitespace, and empty elements are skipped.
+/// Commas inside quoted strings and comments don't separate elements.
+/// Created by [`get_list`].
+///
+/// [`get_list`]: fn.get_list.html
+#[derive(Debug, Clone)]
+pub struct ListValues<'a, 'n> {
+    fields: GetAll<'a, 'n>,
+    rest: Option<&'a [u8]>,
+}
+
+/// Splits `data` at its first comma that isn't inside a quoted string
+/// or a comment
+fn split_element(data: &[u8]) -> (&[u8], Option<&[u8]>) {
+    let mut quoted = false;
+    let mut comments = 0_usize;
+    let mut escaped = false;
+
+    for (i, byte) in data.iter().enumerate() {
+        match *byte {
+            _ if escaped => escaped = false,
+            b'\\' if quoted || comments > 0 => escaped = true,
+            b'"' if comments == 0 => quoted = !quoted,
+            b'(' if !quoted => comments += 1,
+            b')' if !quoted && comments > 0 => comments -= 1,
+            b',' if !quoted && comments == 0 => return (&data[..i], Some(&data[i + 1..])),
+            _ => {},
+        }
+    }
+
+    (data, None)
+}
+
+impl<'a, 'n> Iterator for ListValues<'a, 'n> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        loop {
+            let data = match self.rest.take() {
+                Some(data) => data,
+                None => self.fields.next()?,
+            };
+
+            let (element, rest) = split_element(data);
+            self.rest = rest;
+
+            let element = trim_whitespace(element);
+            if !element.is_empty() {
+                return Some(element);
+            }
+        }
+    }
+}
+
+/// The errors that can occur when decoding a header. Each variant
+/// carries the header's name.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum HeaderError {
+    /// A header that may only appear once appears several times
+    Duplicate(&'static str),
+    /// A value isn't of the form the header requires
+    Invalid(&'static str),
+    /// Several values disagree - E.g. `Content-Length: 5, 6`
+    Conflicting(&'static str),
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HeaderError::Duplicate(name) => write!(f, "duplicate {} header", name),
+            HeaderError::Invalid(name) => write!(f, "invalid {} header", name),
+            HeaderError::Conflicting(name) => write!(f, "conflicting {} values", name),
+        }
+    }
+}
+
+fn is_token_string(data: &[u8]) -> bool {
+    !data.is_empty() && data.iter().all(|byte| is_token(*byte))
+}
+
+fn is_rws(byte: u8) -> bool {
+    byte == b' ' || byte == b'\t'
+}
+
+/// Checks `data` is a quoted string, without its escapes being followed
+/// by control characters
+fn is_quoted_string(data: &[u8]) -> bool {
+    if data.len() < 2 || data[0] != b'"' {
+        return false;
+    }
+
+    let mut escaped = false;
+    for (i, byte) in data[1..].iter().enumerate() {
+        match *byte {
+            b'\t' | b' '..=b'~' | 0x80..=0xff if escaped => escaped = false,
+            _ if escaped => return false,
+            b'\\' => escaped = true,
+            b'"' => return i == data.len() - 2,
+            b'\t' | b' '..=b'~' | 0x80..=0xff => {},
+            _ => return false,
+        }
+    }
+
+    false
+}
+
+/// Checks `data` is `token "=" ( token / quoted-string )`
+fn is_parameter(data: &[u8]) -> bool {
+    match data.iter().position(|byte| *byte == b'=') {
+        Some(p) => {
+            let value = trim_whitespace(&data[p + 1..]);
+            is_token_string(trim_whitespace(&data[..p])) && (is_token_string(value) || is_quoted_string(value))
+        },
+        None => false,
+    }
+}
+
+/// Splits `data` at its first run of whitespace, returning what precedes
+/// and follows it
+fn split_rws(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let end = data.iter().position(|byte| is_rws(*byte))?;
+    let rest = &data[end..];
+    let start = rest.iter().position(|byte| !is_rws(*byte)).unwrap_or(rest.len());
+
+    Some((&data[..end], &rest[start..]))
+}
+
+/// The value of the only header named `name`
+fn single<'a>(headers: &'a [Header<'a>], name: &'static str) -> Result<Option<&'a [u8]>, HeaderError> {
+    let mut values = get_all(headers, name.as_bytes());
+    let value = values.next();
+    if values.next().is_some() {
+        return Err(HeaderError::Duplicate(name));
+    }
+
+    Ok(value)
+}
+
+/// Decodes `Host`. An empty value, which is sent when the request target
+/// has no authority, decodes as `None`.
+///
+/// # Examples
+///
+/// ```
+/// use twister_http::Header;
+/// use twister_http::headers::{self, HeaderError};
+/// use twister_http::target::{Authority, Host};
+///
+/// let authority = headers::host(&[Header(b"Host", b"docs.rs:8080")]).unwrap();
+/// assert_eq!(Some(Authority { host: Host::Name(b"docs.rs"), port: Some(8080) }), authority);
+///
+/// let duplicated = [Header(b"Host", b"a"), Header(b"host", b"b")];
+/// assert_eq!(Err(HeaderError::Duplicate("Host")), headers::host(&duplicated));
+/// ```
+pub fn host<'a>(headers: &'a [Header<'a>]) -> Result<Option<Authority<'a>>, HeaderError> {
+    match single(headers, "Host")?.map(trim_whitespace) {
+        None => Ok(None),
+        Some(b"") => Ok(None),
+        Some(value) => Authority::parse(value, 0).map(Some).map_err(|_| HeaderError::Invalid("Host")),
+    }
+}
+
+fn parse_decimal(data: &[u8]) -> Option<u64> {
+    if data.is_empty() {
+        return None;
+    }
+
+    data.iter().try_fold(0_u64, |n, &byte| {
+        match byte {
+            b'0'..=b'9' => n.checked_mul(10)?.checked_add((byte - b'0') as u64),
+            _ => None,
+        }
+    })
+}
+
+/// Decodes `Content-Length`, combining every value. Repeated values are
+/// allowed provided they're identical.
+///
+/// # Return Value
+/// The length, or [`HeaderError::Conflicting`] if values disagree, or
+/// [`HeaderError::Invalid`] if a value isn't a number.
+///
+/// [`HeaderError::Conflicting`]: enum.HeaderError.html#variant.Conflicting
+/// [`HeaderError::Invalid`]: enum.HeaderError.html#variant.Invalid
+pub fn content_length(headers: &[Header]) -> Result<Option<u64>, HeaderError> {
+    let mut length = None;
+
+    for field in get_all(headers, b"Content-Length") {
+        // Empty elements aren't allowed, so the value isn't split with
+        // `get_list`
+        for value in field.split(|byte| *byte == b',').map(trim_whitespace) {
+            let value = parse_decimal(value).ok_or(HeaderError::Invalid("Content-Length"))?;
+            if length.map(|n| n != value).unwrap_or(false) {
+                return Err(HeaderError::Conflicting("Content-Length"));
+            }
+
+            length = Some(value);
+        }
+    }
+
+    Ok(length)
+}
+
+/// The codings of `Transfer-Encoding`, in the order they were applied.
+/// Created by [`transfer_encoding`].
+///
+/// [`transfer_encoding`]: fn.transfer_encoding.html
+#[derive(Debug, Clone)]
+pub struct TransferEncoding<'a> {
+    values: ListValues<'a, 'static>,
+    chunked: bool,
+}
+
+impl<'a> TransferEncoding<'a> {
+    /// Returns `true` if the final coding is *chunked*
+    pub fn is_chunked(&self) -> bool {
+        self.chunked
+    }
+
+    /// The names of the codings, without their parameters - E.g. `gzip`
+    pub fn codings(&self) -> Codings<'a> {
+        Codings(self.values.clone())
+    }
+}
+
+/// An iterator over the coding names of a [`TransferEncoding`]
+///
+/// [`TransferEncoding`]: struct.TransferEncoding.html
+#[derive(Debug, Clone)]
+pub struct Codings<'a>(ListValues<'a, 'static>);
+
+impl<'a> Iterator for Codings<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        self.0.next().map(coding_name)
+    }
+}
+
+fn coding_name(coding: &[u8]) -> &[u8] {
+    trim_whitespace(coding.split(|byte| *byte == b';').next().unwrap())
+}
+
+/// Decodes `Transfer-Encoding`
+///
+/// # Return Value
+/// The codings, or [`HeaderError::Invalid`] if a coding isn't a token,
+/// the header has no codings, or *chunked* is followed by another
+/// coding. *chunked* may only be applied once, and must be applied last.
+///
+/// [`HeaderError::Invalid`]: enum.HeaderError.html#variant.Invalid
+pub fn transfer_encoding<'a>(headers: &'a [Header<'a>]) -> Result<Option<TransferEncoding<'a>>, HeaderError> {
+    if get(headers, b"Transfer-Encoding").is_none() {
+        return Ok(None);
+    }
+
+    let values = get_list(headers, b"Transfer-Encoding");
+    let mut found = false;
+    let mut chunked = false;
+
+    for coding in values.clone() {
+        let mut parts = coding.split(|byte| *byte == b';');
+        let name = trim_whitespace(parts.next().unwrap());
+        if !is_token_string(name) || chunked || !parts.all(|p| is_parameter(trim_whitespace(p))) {
+            return Err(HeaderError::Invalid("Transfer-Encoding"));
+        }
+
+        found = true;
+        chunked = name.eq_ignore_ascii_case(b"chunked");
+    }
+
+    if !found {
+        return Err(HeaderError::Invalid("Transfer-Encoding"));
+    }
+
+    Ok(Some(TransferEncoding {
+        values: values,
+        chunked: chunked,
+    }))
+}
+
+/// The options of a `Connection` or `Proxy-Connection` header. Created
+/// by [`connection`] and [`proxy_connection`].
+///
+/// [`connection`]: fn.connection.html
+/// [`proxy_connection`]: fn.proxy_connection.html
+#[derive(Debug, Clone)]
+pub struct ConnectionOptions<'a>(ListValues<'a, 'static>);
+
+impl<'a> ConnectionOptions<'a> {
+    /// Returns `true` if `option` is present, compared ASCII
+    /// case-insensitively
+    pub fn contains(&self, option: &[u8]) -> bool {
+        self.iter().any(|o| o.eq_ignore_ascii_case(option))
+    }
+
+    /// Returns `true` if the `close` option is present
+    pub fn is_close(&self) -> bool {
+        self.contains(b"close")
+    }
+
+    /// Returns `true` if the `keep-alive` option is present
+    pub fn is_keep_alive(&self) -> bool {
+        self.contains(b"keep-alive")
+    }
+
+    pub fn iter(&self) -> ListValues<'a, 'static> {
+        self.0.clone()
+    }
+}
+
+fn options<'a>(headers: &'a [Header<'a>], name: &'static str) -> Result<ConnectionOptions<'a>, HeaderError> {
+    let values = get_list(headers, name.as_bytes());
+    if !values.clone().all(is_token_string) {
+        return Err(HeaderError::Invalid(name));
+    }
+
+    Ok(ConnectionOptions(values))
+}
+
+/// Decodes `Connection`. A request without the header has no options.
+///
+/// # Examples
+///
+/// ```
+/// use twister_http::Header;
+/// use twister_http::headers;
+///
+/// let options = headers::connection(&[Header(b"Connection", b"Keep-Alive, Upgrade")]).unwrap();
+/// assert!(options.is_keep_alive());
+/// assert!(options.contains(b"upgrade"));
+/// assert!(!options.is_close());
+/// ```
+pub fn connection<'a>(headers: &'a [Header<'a>]) -> Result<ConnectionOptions<'a>, HeaderError> {
+    options(headers, "Connection")
+}
+
+/// Decodes `Proxy-Connection`, which some clients send to proxies in
+/// place of `Connection`
+pub fn proxy_connection<'a>(headers: &'a [Header<'a>]) -> Result<ConnectionOptions<'a>, HeaderError> {
+    options(headers, "Proxy-Connection")
+}
+
+/// The credentials of a `Proxy-Authorization` header - E.g. `Basic` and
+/// `dXNlcjpwYXNz`
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Credentials<'a> {
+    pub scheme: &'a [u8],
+    /// A `token68`, or a list of `name=value` parameters. Empty if the
+    /// scheme has neither.
+    pub params: &'a [u8],
+}
+
+fn is_token68(data: &[u8]) -> bool {
+    let end = data.iter().rposition(|byte| *byte != b'=').map(|p| p + 1).unwrap_or(0);
+    end > 0 && data[..end].iter().all(|byte| byte.is_ascii_alphanumeric() || b"-._~+/".contains(byte))
+}
+
+/// Decodes `Proxy-Authorization`
+///
+/// # Return Value
+/// The credentials, or [`HeaderError::Duplicate`] if there are several,
+/// or [`HeaderError::Invalid`] if the scheme isn't a token or isn't
+/// followed by a `token68` or parameters.
+///
+/// [`HeaderError::Duplicate`]: enum.HeaderError.html#variant.Duplicate
+/// [`HeaderError::Invalid`]: enum.HeaderError.html#variant.Invalid
+pub fn proxy_authorization<'a>(headers: &'a [Header<'a>]) -> Result<Option<Credentials<'a>>, HeaderError> {
+    let value = match single(headers, "Proxy-Authorization")? {
+        Some(value) => trim_whitespace(value),
+        None => return Ok(None),
+    };
+
+    let (scheme, params) = split_rws(value).unwrap_or((value, b""));
+    let valid_params = params.is_empty() ||
+        is_token68(params) ||
+        params.split(|byte| *byte == b',').map(trim_whitespace).all(|p| p.is_empty() || is_parameter(p));
+
+    if !is_token_string(scheme) || !valid_params {
+        return Err(HeaderError::Invalid("Proxy-Authorization"));
+    }
+
+    Ok(Some(Credentials {
+        scheme: scheme,
+        params: params,
+    }))
+}
+
+/// A protocol offered by `Upgrade` - E.g. `websocket` or `HTTP/2.0`
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Protocol<'a> {
+    pub name: &'a [u8],
+    pub version: Option<&'a [u8]>,
+}
+
+impl<'a> Protocol<'a> {
+    fn parse(data: &'a [u8]) -> Option<Protocol<'a>> {
+        let (name, version) = match data.iter().position(|byte| *byte == b'/') {
+            Some(p) => (&data[..p], Some(&data[p + 1..])),
+            None => (data, None),
+        };
+
+        if !is_token_string(name) || !version.map(is_token_string).unwrap_or(true) {
+            return None;
+        }
+
+        Some(Protocol {
+            name: name,
+            version: version,
+        })
+    }
+}
+
+/// An iterator over the protocols of an `Upgrade` header, in order of
+/// preference. Created by [`upgrade`].
+///
+/// [`upgrade`]: fn.upgrade.html
+#[derive(Debug, Clone)]
+pub struct Protocols<'a>(ListValues<'a, 'static>);
+
+impl<'a> Iterator for Protocols<'a> {
+    type Item = Protocol<'a>;
+
+    fn next(&mut self) -> Option<Protocol<'a>> {
+        self.0.next().and_then(Protocol::parse)
+    }
+}
+
+/// Decodes `Upgrade`
+///
+/// # Return Value
+/// The protocols, or [`HeaderError::Invalid`] if the header has no
+/// protocols or one isn't of the form `name[/version]`.
+///
+/// [`HeaderError::Invalid`]: enum.HeaderError.html#variant.Invalid
+pub fn upgrade<'a>(headers: &'a [Header<'a>]) -> Result<Option<Protocols<'a>>, HeaderError> {
+    if get(headers, b"Upgrade").is_none() {
+        return Ok(None);
+    }
+
+    let values = get_list(headers, b"Upgrade");
+    if values.clone().count() == 0 || !values.clone().all(|p| Protocol::parse(p).is_some()) {
+        return Err(HeaderError::Invalid("Upgrade"));
+    }
+
+    Ok(Some(Protocols(values)))
+}
+
+/// The expectation of an `Expect` header
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Expectation<'a> {
+    /// `100-continue`
+    Continue,
+    /// An expectation that isn't `100-continue`, which a server can't
+    /// meet. The first one found is given.
+    Other(&'a [u8]),
+}
+
+/// Decodes `Expect`
+///
+/// # Return Value
+/// The expectation, or [`HeaderError::Invalid`] if the header has no
+/// expectations or one isn't of the form `token[=value]`, optionally
+/// followed by `;` parameters.
+///
+/// # Examples
+///
+/// ```
+/// use twister_http::Header;
+/// use twister_http::headers::{self, Expectation};
+///
+/// let expectation = headers::expect(&[Header(b"Expect", b"100-Continue")]).unwrap();
+/// assert_eq!(Some(Expectation::Continue), expectation);
+/// ```
+///
+/// [`HeaderError::Invalid`]: enum.HeaderError.html#variant.Invalid
+pub fn expect<'a>(headers: &'a [Header<'a>]) -> Result<Option<Expectation<'a>>, HeaderError> {
+    if get(headers, b"Expect").is_none() {
+        return Ok(None);
+    }
+
+    let mut expectation = None;
+    for value in get_list(headers, b"Expect") {
+        let mut parts = value.split(|byte| *byte == b';').map(trim_whitespace);
+        let first = parts.next().unwrap();
+        if !(is_token_string(first) || is_parameter(first)) || !parts.all(|p| p.is_empty() || is_parameter(p)) {
+            return Err(HeaderError::Invalid("Expect"));
+        }
+
+        expectation = match expectation {
+            Some(Expectation::Other(other)) => Some(Expectation::Other(other)),
+            _ if value.eq_ignore_ascii_case(b"100-continue") => Some(Expectation::Continue),
+            _ => Some(Expectation::Other(value)),
+        };
+    }
+
+    expectation.map(Some).ok_or(HeaderError::Invalid("Expect"))
+}
+
+/// An intermediary recorded by `Via` - E.g. `1.1 proxy.example (twister)`
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ViaEntry<'a> {
+    /// `None` if the protocol is HTTP
+    pub protocol: Option<&'a [u8]>,
+    pub version: &'a [u8],
+    /// The intermediary's `host[:port]` or pseudonym
+    pub received_by: &'a [u8],
+    /// The comment, including its parentheses
+    pub comment: Option<&'a [u8]>,
+}
+
+impl<'a> ViaEntry<'a> {
+    fn parse(data: &'a [u8]) -> Option<ViaEntry<'a>> {
+        let (received_protocol, rest) = split_rws(data)?;
+        let (received_by, comment) = match split_rws(rest) {
+            Some((received_by, comment)) => (received_by, Some(comment)),
+            None => (rest, None),
+        };
+
+        let (protocol, version) = match received_protocol.iter().position(|byte| *byte == b'/') {
+            Some(p) => (Some(&received_protocol[..p]), &received_protocol[p + 1..]),
+            None => (None, received_protocol),
+        };
+
+        let valid = protocol.map(is_token_string).unwrap_or(true) &&
+            is_token_string(version) &&
+            (is_token_string(received_by) || Authority::parse(received_by, 0).is_ok()) &&
+            comment.map(|c| c.len() >= 2 && c[0] == b'(' && c[c.len() - 1] == b')').unwrap_or(true);
+
+        if !valid {
+            return None;
+        }
+
+        Some(ViaEntry {
+            protocol: protocol,
+            version: version,
+            received_by: received_by,
+            comment: comment,
+        })
+    }
+}
+
+/// An iterator over the entries of `Via`, starting with the
+/// intermediary nearest the sender. Created by [`via`].
+///
+/// [`via`]: fn.via.html
+#[derive(Debug, Clone)]
+pub struct ViaEntries<'a>(ListValues<'a, 'static>);
+
+impl<'a> Iterator for ViaEntries<'a> {
+    type Item = ViaEntry<'a>;
+
+    fn next(&mut self) -> Option<ViaEntry<'a>> {
+        self.0.next().and_then(ViaEntry::parse)
+    }
+}
+
+/// Decodes `Via`. A message without the header has no entries.
+///
+/// # Examples
+///
+/// ```
+/// use twister_http::Header;
+/// use twister_http::headers::{self, ViaEntry};
+///
+/// let mut entries = headers::via(&[Header(b"Via", b"1.0 fred, 1.1 p.example.net:8080 (twister)")]).unwrap();
+/// assert_eq!(Some(ViaEntry { protocol: None, version: b"1.0", received_by: b"fred", comment: None }), entries.next());
+/// assert_eq!(Some(&b"p.example.net:8080"[..]), entries.next().map(|entry| entry.received_by));
+/// ```
+pub fn via<'a>(headers: &'a [Header<'a>]) -> Result<ViaEntries<'a>, HeaderError> {
+    let values = get_list(headers, b"Via");
+    if !values.clone().all(|entry| ViaEntry::parse(entry).is_some()) {
+        return Err(HeaderError::Invalid("Via"));
+    }
+
+    Ok(ViaEntries(values))
+}
+
+#[cfg(test)]
+mod headers_should {
+    use super::*;
+
+    const HEADERS: &[Header] = &[
+        Header(b"Host", b"example.com"),
+        Header(b"connection", b"keep-alive, Upgrade"),
+        Header(b"Via", b"1.1 a"),
+        Header(b"Connection", b" ,te"),
+        Header(b"VIA", b"1.0 b"),
+    ];
+
+    #[test]
+    fn find_headers_ignoring_case() {
+        assert_eq!(Some(&b"example.com"[..]), get(HEADERS, b"HOST"));
+        assert_eq!(Some(&b"1.1 a"[..]), get(HEADERS, b"via"));
+        assert_eq!(None, get(HEADERS, b"Content-Length"));
+    }
+
+    #[test]
+    fn find_every_value_in_order() {
+        assert_eq!(vec![&b"1.1 a"[..], b"1.0 b"], get_all(HEADERS, b"Via").collect::<Vec<_>>());
+        assert_eq!(0, get_all(HEADERS, b"Upgrade").count());
+    }
+
+    #[test]
+    fn split_lists_across_headers() {
+        assert_eq!(vec![&b"keep-alive"[..], b"Upgrade", b"te"], get_list(HEADERS, b"Connection").collect::<Vec<_>>());
+        assert_eq!(0, get_list(&[Header(b"Connection", b" , ,")], b"Connection").count());
+    }
+
+    #[test]
+    fn keep_quoted_commas() {
+        let headers = [Header(b"Forwarded", b"for=a;by=\"x,\\\"y\", for=b")];
+        assert_eq!(vec![&b"for=a;by=\"x,\\\"y\""[..], b"for=b"], get_list(&headers, b"Forwarded").collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn decode_host() {
+        use target::Host;
+
+        let authority = Authority { host: Host::Ipv6(b"::1"), port: Some(8080) };
+        assert_eq!(Ok(Some(authority)), host(&[Header(b"host", b" [::1]:8080 ")]));
+        assert_eq!(Ok(None), host(&[Header(b"Host", b"")]));
+        assert_eq!(Ok(None), host(&[]));
+        assert_eq!(Err(HeaderError::Invalid("Host")), host(&[Header(b"Host", b"user@example.com")]));
+        assert_eq!(Err(HeaderError::Duplicate("Host")), host(&[Header(b"Host", b"a"), Header(b"Host", b"a")]));
+    }
+
+    #[test]
+    fn decode_content_length() {
+        assert_eq!(Ok(Some(5)), content_length(&[Header(b"Content-Length", b"5, 5"), Header(b"content-length", b"5")]));
+        assert_eq!(Ok(None), content_length(&[]));
+        assert_eq!(Err(HeaderError::Conflicting("Content-Length")),
+                   content_length(&[Header(b"Content-Length", b"5"), Header(b"Content-Length", b"6")]));
+        assert_eq!(Err(HeaderError::Invalid("Content-Length")), content_length(&[Header(b"Content-Length", b"5,")]));
+        assert_eq!(Err(HeaderError::Invalid("Content-Length")), content_length(&[Header(b"Content-Length", b"-1")]));
+        assert_eq!(Err(HeaderError::Invalid("Content-Length")),
+                   content_length(&[Header(b"Content-Length", b"99999999999999999999")]));
+    }
+
+    #[test]
+    fn decode_transfer_encoding() {
+        let headers = [Header(b"Transfer-Encoding", b"gzip;q=\"1\""), Header(b"Transfer-Encoding", b"Chunked")];
+        let codings = transfer_encoding(&headers).unwrap().unwrap();
+        assert!(codings.is_chunked());
+        assert_eq!(vec![&b"gzip"[..], b"Chunked"], codings.codings().collect::<Vec<_>>());
+
+        assert!(!transfer_encoding(&[Header(b"Transfer-Encoding", b"gzip")]).unwrap().unwrap().is_chunked());
+        assert!(transfer_encoding(&[]).unwrap().is_none());
+
+        for value in &[&b""[..], b" , ", b"chunked, gzip", b"chunked, chunked", b"g zip", b"gzip;q"] {
+            let headers = [Header(b"Transfer-Encoding", value)];
+            assert_eq!(Err(HeaderError::Invalid("Transfer-Encoding")), transfer_encoding(&headers).map(|_| ()));
+        }
+    }
+
+    #[test]
+    fn decode_connection_options() {
+        let options = connection(HEADERS).unwrap();
+        assert!(options.is_keep_alive());
+        assert!(options.contains(b"TE"));
+        assert!(!options.is_close());
+
+        let headers = [Header(b"Proxy-Connection", b"close")];
+        assert!(proxy_connection(&headers).unwrap().is_close());
+        assert!(!connection(&headers).unwrap().is_close());
+
+        assert_eq!(Err(HeaderError::Invalid("Connection")), connection(&[Header(b"Connection", b"keep alive")]).map(|_| ()));
+    }
+
+    #[test]
+    fn decode_proxy_authorization() {
+        let credentials = Credentials { scheme: b"Basic", params: b"dXNlcjpwYXNz" };
+        assert_eq!(Ok(Some(credentials)), proxy_authorization(&[Header(b"Proxy-Authorization", b"Basic  dXNlcjpwYXNz")]));
+
+        let credentials = Credentials { scheme: b"Digest", params: b"username=\"u\", nonce=abc" };
+        assert_eq!(Ok(Some(credentials)),
+                   proxy_authorization(&[Header(b"Proxy-Authorization", b"Digest username=\"u\", nonce=abc")]));
+
+        assert_eq!(Err(HeaderError::Invalid("Proxy-Authorization")),
+                   proxy_authorization(&[Header(b"Proxy-Authorization", b"Basic a b")]));
+        assert_eq!(Err(HeaderError::Duplicate("Proxy-Authorization")),
+                   proxy_authorization(&[Header(b"Proxy-Authorization", b"Basic a"), Header(b"Proxy-Authorization", b"Basic a")]));
+    }
+
+    #[test]
+    fn decode_upgrade() {
+        let protocols = upgrade(&[Header(b"Upgrade", b"HTTP/2.0, websocket")]).unwrap().unwrap();
+        assert_eq!(vec![Protocol { name: b"HTTP", version: Some(b"2.0") }, Protocol { name: b"websocket", version: None }],
+                   protocols.collect::<Vec<_>>());
+
+        assert_eq!(Err(HeaderError::Invalid("Upgrade")), upgrade(&[Header(b"Upgrade", b"")]).map(|_| ()));
+        assert_eq!(Err(HeaderError::Invalid("Upgrade")), upgrade(&[Header(b"Upgrade", b"h2/")]).map(|_| ()));
+    }
+
+    #[test]
+    fn decode_expect() {
+        assert_eq!(Ok(None), expect(&[]));
+        assert_eq!(Ok(Some(Expectation::Continue)), expect(&[Header(b"Expect", b"100-continue")]));
+        assert_eq!(Ok(Some(Expectation::Other(b"foo=\"a, b\""))),
+                   expect(&[Header(b"Expect", b"100-continue, foo=\"a, b\"")]));
+        assert_eq!(Err(HeaderError::Invalid("Expect")), expect(&[Header(b"Expect", b"")]));
+        assert_eq!(Err(HeaderError::Invalid("Expect")), expect(&[Header(b"Expect", b"100 continue")]));
+    }
+
+    #[test]
+    fn decode_via() {
+        let headers = [Header(b"Via", b"HTTP/1.1 [::1]:3128 (a, (nested) comment)"), Header(b"Via", b"1.0 fred")];
+        let entries = via(&headers).unwrap().collect::<Vec<_>>();
+        assert_eq!(vec![
+            ViaEntry { protocol: Some(b"HTTP"), version: b"1.1", received_by: b"[::1]:3128", comment: Some(b"(a, (nested) comment)") },
+            ViaEntry { protocol: None, version: b"1.0", received_by: b"fred", comment: None },
+        ], entries);
+
+        assert_eq!(0, via(&[]).unwrap().count());
+
+        for value in &[&b"1.1"[..], b"1.1 fred comment", b"/1.1 fred", b"1.1 fr\"ed"] {
+            assert_eq!(Err(HeaderError::Invalid("Via")), via(&[Header(b"Via", value)]).map(|_| ()));
+        }
+    }
+}
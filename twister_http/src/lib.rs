@@ -2,14 +2,28 @@
 
 #[cfg(test)]
 extern crate core;
+#[cfg(feature = "alloc")]
+extern crate alloc;
 
+pub mod body;
+pub mod headers;
+#[cfg(feature = "alloc")]
+pub mod owned;
 pub mod parser;
+pub mod serialize;
+pub mod target;
 
 trait FromBytes : Sized {
     fn from_bytes(bytes: &[u8]) -> Option<Self>;
 }
 
-#[derive(Debug, PartialEq)]
+use core::convert::TryFrom;
+use core::fmt;
+
+use headers::{GetAll, ListValues};
+use parser::{check_version, is_token, ParseError};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum HttpMethod<'a> {
     Connect,
     Get,
@@ -18,55 +32,290 @@ pub enum HttpMethod<'a> {
     Delete,
     Patch,
     Head,
+    Options,
+    Trace,
     Other(&'a [u8]),
 }
 
-fn to_lower(v: u8) -> u8 {
-    match v {
-        b'A'...b'Z' => v + (b'a' - b'A'),
-        o => o
+/// How method names are compared with the standard methods' names
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MethodCase {
+    /// `get` is `GET`
+    Insensitive,
+    /// Method names are case-sensitive, as RFC 9110 requires. A name
+    /// that only matches a standard method when case is ignored, such
+    /// as `get`, is rejected.
+    Sensitive,
+}
+
+/// The standard methods, in their canonical case
+const METHODS: &[(&[u8], HttpMethod<'static>)] = &[
+    (b"CONNECT", HttpMethod::Connect),
+    (b"GET", HttpMethod::Get),
+    (b"POST", HttpMethod::Post),
+    (b"PUT", HttpMethod::Put),
+    (b"DELETE", HttpMethod::Delete),
+    (b"PATCH", HttpMethod::Patch),
+    (b"HEAD", HttpMethod::Head),
+    (b"OPTIONS", HttpMethod::Options),
+    (b"TRACE", HttpMethod::Trace),
+];
+
+/// Converts a method name without validating it. Names are compared
+/// case-insensitively. See [`HttpMethod::parse`] to validate names.
+///
+/// [`HttpMethod::parse`]: enum.HttpMethod.html#method.parse
+impl<'a> From<&'a [u8]> for HttpMethod<'a> {
+    fn from(bytes: &'a [u8]) -> HttpMethod<'a> {
+        METHODS.iter()
+            .find(|&&(name, _)| name.eq_ignore_ascii_case(bytes))
+            .map(|&(_, method)| method)
+            .unwrap_or(HttpMethod::Other(bytes))
     }
 }
 
-fn which_of(to_find: &[u8], in_set: &[&[u8]]) -> Option<usize> {
-    for (i, el) in in_set.iter().enumerate() {
-        let eq = el.iter().map(|byte| to_lower(*byte))
-            .eq(to_find.iter().map(|byte| to_lower(*byte)));
+impl<'a> HttpMethod<'a> {
+    /// Converts a method name, validating that it's a token.
+    ///
+    /// # Return Value
+    /// The method, or [`ParseError::InvalidMethod`] with the offset of the
+    /// first invalid byte if `bytes` is empty, contains a non-token
+    /// character, or doesn't match a standard method's case when `case`
+    /// is [`MethodCase::Sensitive`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use twister_http::{HttpMethod, MethodCase};
+    /// use twister_http::parser::ParseError;
+    ///
+    /// assert_eq!(Ok(HttpMethod::Get), HttpMethod::parse(b"get", MethodCase::Insensitive));
+    /// assert_eq!(Err(ParseError::InvalidMethod(0)), HttpMethod::parse(b"get", MethodCase::Sensitive));
+    /// assert_eq!(Ok(HttpMethod::Other(b"PURGE")), HttpMethod::parse(b"PURGE", MethodCase::Sensitive));
+    /// assert_eq!(Err(ParseError::InvalidMethod(3)), HttpMethod::parse(b"GET\0", MethodCase::Insensitive));
+    /// ```
+    ///
+    /// [`ParseError::InvalidMethod`]: parser/enum.ParseError.html#variant.InvalidMethod
+    /// [`MethodCase::Sensitive`]: enum.MethodCase.html#variant.Sensitive
+    pub fn parse(bytes: &'a [u8], case: MethodCase) -> Result<HttpMethod<'a>, ParseError> {
+        if let Some(p) = bytes.iter().position(|byte| !is_token(*byte)) {
+            return Err(ParseError::InvalidMethod(p));
+        }
+
+        if bytes.is_empty() {
+            return Err(ParseError::InvalidMethod(0));
+        }
+
+        let method = HttpMethod::from(bytes);
+        match method {
+            HttpMethod::Other(_) => {},
+            ref known if case == MethodCase::Sensitive && known.as_bytes() != bytes =>
+                return Err(ParseError::InvalidMethod(0)),
+            _ => {},
+        }
+
+        Ok(method)
+    }
 
-        if eq {
-            return Some(i);
+    /// The method's name as it appears in a request line - E.g. `GET`
+    pub fn as_bytes(&self) -> &'a [u8] {
+        match *self {
+            HttpMethod::Connect => b"CONNECT",
+            HttpMethod::Get => b"GET",
+            HttpMethod::Post => b"POST",
+            HttpMethod::Put => b"PUT",
+            HttpMethod::Delete => b"DELETE",
+            HttpMethod::Patch => b"PATCH",
+            HttpMethod::Head => b"HEAD",
+            HttpMethod::Options => b"OPTIONS",
+            HttpMethod::Trace => b"TRACE",
+            HttpMethod::Other(bytes) => bytes,
         }
     }
+}
 
-    None
+/// A HTTP version. Only `HTTP/1.0` and `HTTP/1.1` are supported.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum Version {
+    Http10,
+    Http11,
 }
 
-impl<'a> From<&'a [u8]> for HttpMethod<'a> {
-    fn from(bytes: &'a [u8]) -> HttpMethod<'a> {
-        let valid: &[&[u8]] = &[
-            b"connect",
-            b"Get",
-            b"Post",
-            b"Put",
-            b"Delete",
-            b"Patch",
-            b"Head",
-        ];
-
-        if let Some(n) = which_of(bytes, valid) {
-            return match n {
-                0 => HttpMethod::Connect,
-                1 => HttpMethod::Get,
-                2 => HttpMethod::Post,
-                3 => HttpMethod::Put,
-                4 => HttpMethod::Delete,
-                5 => HttpMethod::Patch,
-                6 => HttpMethod::Head,
-                _ => unreachable!(),
-            }
+impl Version {
+    /// Parses a version - E.g. `HTTP/1.1`
+    ///
+    /// # Return Value
+    /// The version, or [`ParseError::InvalidVersion`] with the offset of
+    /// the first invalid byte if `bytes` isn't of the form `HTTP/x.y`, or
+    /// is a version other than `1.0` or `1.1`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use twister_http::Version;
+    /// use twister_http::parser::ParseError;
+    ///
+    /// assert_eq!(Ok(Version::Http10), Version::parse(b"HTTP/1.0"));
+    /// assert_eq!(Err(ParseError::InvalidVersion(5)), Version::parse(b"HTTP/2.0"));
+    /// ```
+    ///
+    /// [`ParseError::InvalidVersion`]: parser/enum.ParseError.html#variant.InvalidVersion
+    pub fn parse(bytes: &[u8]) -> Result<Version, ParseError> {
+        check_version(bytes).map_err(ParseError::InvalidVersion)?;
+
+        match bytes[7] {
+            b'0' => Ok(Version::Http10),
+            _ => Ok(Version::Http11),
         }
+    }
+
+    /// The version as it appears in a start line - E.g. `HTTP/1.1`
+    pub fn as_bytes(&self) -> &'static [u8] {
+        match *self {
+            Version::Http10 => b"HTTP/1.0",
+            Version::Http11 => b"HTTP/1.1",
+        }
+    }
+
+    /// Returns `true` if connections using this version stay open after
+    /// a message unless `Connection: close` is sent. `HTTP/1.0`
+    /// connections close unless `Connection: keep-alive` is sent.
+    pub fn is_persistent_by_default(&self) -> bool {
+        *self == Version::Http11
+    }
+}
 
-        HttpMethod::Other(bytes)
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Version::Http10 => write!(f, "HTTP/1.0"),
+            Version::Http11 => write!(f, "HTTP/1.1"),
+        }
+    }
+}
+
+/// A response's status code - E.g. `StatusCode(404)`
+///
+/// # Examples
+///
+/// ```
+/// use twister_http::StatusCode;
+///
+/// let status = StatusCode::parse(b"407").unwrap();
+/// assert_eq!(Some("Proxy Authentication Required"), status.reason());
+/// assert!(status.is_client_error());
+/// assert_eq!(None, StatusCode(299).reason());
+/// ```
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct StatusCode(pub u16);
+
+/// The reason phrases of the status codes registered by RFC 9110
+const REASONS: &[(u16, &str)] = &[
+    (100, "Continue"),
+    (101, "Switching Protocols"),
+    (200, "OK"),
+    (201, "Created"),
+    (202, "Accepted"),
+    (203, "Non-Authoritative Information"),
+    (204, "No Content"),
+    (205, "Reset Content"),
+    (206, "Partial Content"),
+    (300, "Multiple Choices"),
+    (301, "Moved Permanently"),
+    (302, "Found"),
+    (303, "See Other"),
+    (304, "Not Modified"),
+    (305, "Use Proxy"),
+    (307, "Temporary Redirect"),
+    (308, "Permanent Redirect"),
+    (400, "Bad Request"),
+    (401, "Unauthorized"),
+    (402, "Payment Required"),
+    (403, "Forbidden"),
+    (404, "Not Found"),
+    (405, "Method Not Allowed"),
+    (406, "Not Acceptable"),
+    (407, "Proxy Authentication Required"),
+    (408, "Request Timeout"),
+    (409, "Conflict"),
+    (410, "Gone"),
+    (411, "Length Required"),
+    (412, "Precondition Failed"),
+    (413, "Content Too Large"),
+    (414, "URI Too Long"),
+    (415, "Unsupported Media Type"),
+    (416, "Range Not Satisfiable"),
+    (417, "Expectation Failed"),
+    (421, "Misdirected Request"),
+    (422, "Unprocessable Content"),
+    (426, "Upgrade Required"),
+    (431, "Request Header Fields Too Large"),
+    (500, "Internal Server Error"),
+    (501, "Not Implemented"),
+    (502, "Bad Gateway"),
+    (503, "Service Unavailable"),
+    (504, "Gateway Timeout"),
+    (505, "HTTP Version Not Supported"),
+];
+
+impl StatusCode {
+    /// Parses a status code, which must be three digits - E.g. `404`
+    ///
+    /// # Return Value
+    /// The status code, or [`ParseError::InvalidStatus`] with the offset
+    /// of the first invalid byte.
+    ///
+    /// [`ParseError::InvalidStatus`]: parser/enum.ParseError.html#variant.InvalidStatus
+    pub fn parse(bytes: &[u8]) -> Result<StatusCode, ParseError> {
+        if let Some(p) = bytes.iter().take(3).position(|byte| !byte.is_ascii_digit()) {
+            return Err(ParseError::InvalidStatus(p));
+        }
+
+        if bytes.len() != 3 {
+            return Err(ParseError::InvalidStatus(bytes.len().min(3)));
+        }
+
+        let code = bytes.iter().fold(0, |code, byte| code * 10 + u16::from(byte - b'0'));
+        Ok(StatusCode(code))
+    }
+
+    /// The status code's registered reason phrase - E.g. `Not Found` for
+    /// `404` - or `None` if it isn't registered
+    pub fn reason(&self) -> Option<&'static str> {
+        REASONS.iter()
+            .find(|&&(code, _)| code == self.0)
+            .map(|&(_, reason)| reason)
+    }
+
+    /// Returns `true` for `1xx` status codes
+    pub fn is_informational(&self) -> bool {
+        self.0 >= 100 && self.0 < 200
+    }
+
+    /// Returns `true` for `2xx` status codes
+    pub fn is_success(&self) -> bool {
+        self.0 >= 200 && self.0 < 300
+    }
+
+    /// Returns `true` for `3xx` status codes
+    pub fn is_redirection(&self) -> bool {
+        self.0 >= 300 && self.0 < 400
+    }
+
+    /// Returns `true` for `4xx` status codes
+    pub fn is_client_error(&self) -> bool {
+        self.0 >= 400 && self.0 < 500
+    }
+
+    /// Returns `true` for `5xx` status codes
+    pub fn is_server_error(&self) -> bool {
+        self.0 >= 500 && self.0 < 600
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
     }
 }
 
@@ -79,39 +328,84 @@ impl<'a> From<&'a [u8]> for HttpMethod<'a> {
 pub struct Header<'a>(pub &'a [u8], pub &'a [u8]);
 
 /// A type to represent a HTTP request object
+#[derive(Debug, PartialEq)]
 pub struct Request<'a> {
     /// The object's method - E.g. `GET`, `POST`. See [`HttpMethod`]
     /// [`HttpMethod`]: ../enum.HttpMethod.html
     pub method: HttpMethod<'a>,
     /// The path value
     pub path: &'a [u8],
-    /// The version string - E.g. `HTTP/1.1`
-    pub version: &'a [u8],
+    /// The version - E.g. `HTTP/1.1`
+    pub version: Version,
     /// The headers contained in the object
     pub headers: &'a [Header<'a>],
     /// The body of the request
     pub body: &'a [u8],
 }
 
-impl<'a> From<(&'a [u8], &'a [u8], &'a [u8], &'a [Header<'a>], &'a [u8])> for Request<'a> {
-    fn from(parts: (&'a [u8], &'a [u8], &'a [u8], &'a [Header<'a>], &'a [u8])) -> Request<'a> {
+impl<'a> Request<'a> {
+    /// The value of the first header named `name`, which is compared
+    /// ASCII case-insensitively
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use twister_http::{Header, Request};
+    /// use twister_http::parser::HttpObjectParser;
+    ///
+    /// let mut headers = [Header::default(); 4];
+    /// let request = HttpObjectParser::new(&mut headers)
+    ///     .parse::<Request>(b"GET / HTTP/1.1\r\nHost: docs.rs\r\nConnection: keep-alive, TE\r\n\r\n")
+    ///     .unwrap()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(Some(&b"docs.rs"[..]), request.get(b"host"));
+    /// assert_eq!(vec![&b"keep-alive"[..], b"TE"], request.get_list(b"Connection").collect::<Vec<_>>());
+    /// ```
+    pub fn get(&self, name: &[u8]) -> Option<&'a [u8]> {
+        headers::get(self.headers, name)
+    }
+
+    /// The values of every header named `name`, in the order they appear
+    pub fn get_all<'n>(&self, name: &'n [u8]) -> GetAll<'a, 'n> {
+        headers::get_all(self.headers, name)
+    }
+
+    /// The elements of every header named `name`, treating each value as
+    /// a comma-separated list - E.g. `Connection` or `Transfer-Encoding`.
+    /// See [`ListValues`].
+    ///
+    /// [`ListValues`]: headers/struct.ListValues.html
+    pub fn get_list<'n>(&self, name: &'n [u8]) -> ListValues<'a, 'n> {
+        headers::get_list(self.headers, name)
+    }
+}
+
+/// Builds a request from the parts of its start line, its headers and its
+/// body. Errors are offset from the start of the line, as if its parts
+/// were separated by single spaces.
+impl<'a> TryFrom<(&'a [u8], &'a [u8], &'a [u8], &'a [Header<'a>], &'a [u8])> for Request<'a> {
+    type Error = ParseError;
+
+    fn try_from(parts: (&'a [u8], &'a [u8], &'a [u8], &'a [Header<'a>], &'a [u8])) -> Result<Request<'a>, ParseError> {
         let (method, path, version, headers, body) = parts;
-        Request {
+        Ok(Request {
             method: method.into(),
             path: path,
-            version: version,
+            version: Version::parse(version).map_err(|e| e.advance(method.len() + path.len() + 2))?,
             headers: headers,
             body: body,
-        }
+        })
     }
 }
 
 /// A type respresenting a HTTP response object
+#[derive(Debug, PartialEq)]
 pub struct Response<'a> {
-    /// The version string - E.g. `HTTP/1.1`
-    pub version: &'a [u8],
+    /// The version - E.g. `HTTP/1.1`
+    pub version: Version,
     /// The status code - E.g. `200`, `404`, etc.
-    pub status_code: &'a [u8],
+    pub status_code: StatusCode,
     /// The status text - E.g. `OK`, `Not Found`, etc.
     pub status_text: &'a [u8],
     /// The headers contained in the object
@@ -120,16 +414,44 @@ pub struct Response<'a> {
     pub body: &'a [u8],
 }
 
-impl<'a> From<(&'a [u8], &'a [u8], &'a [u8], &'a [Header<'a>], &'a [u8])> for Response<'a> {
-    fn from(parts: (&'a [u8], &'a [u8], &'a [u8], &'a [Header<'a>], &'a [u8])) -> Response<'a> {
+impl<'a> Response<'a> {
+    /// See [`Request::get`]
+    ///
+    /// [`Request::get`]: struct.Request.html#method.get
+    pub fn get(&self, name: &[u8]) -> Option<&'a [u8]> {
+        headers::get(self.headers, name)
+    }
+
+    /// See [`Request::get_all`]
+    ///
+    /// [`Request::get_all`]: struct.Request.html#method.get_all
+    pub fn get_all<'n>(&self, name: &'n [u8]) -> GetAll<'a, 'n> {
+        headers::get_all(self.headers, name)
+    }
+
+    /// See [`Request::get_list`]
+    ///
+    /// [`Request::get_list`]: struct.Request.html#method.get_list
+    pub fn get_list<'n>(&self, name: &'n [u8]) -> ListValues<'a, 'n> {
+        headers::get_list(self.headers, name)
+    }
+}
+
+/// Builds a response from the parts of its start line, its headers and its
+/// body. Errors are offset from the start of the line, as if its parts
+/// were separated by single spaces.
+impl<'a> TryFrom<(&'a [u8], &'a [u8], &'a [u8], &'a [Header<'a>], &'a [u8])> for Response<'a> {
+    type Error = ParseError;
+
+    fn try_from(parts: (&'a [u8], &'a [u8], &'a [u8], &'a [Header<'a>], &'a [u8])) -> Result<Response<'a>, ParseError> {
         let (version, status, text, headers, body) = parts;
-        Response {
-            version: version,
-            status_code: status,
+        Ok(Response {
+            version: Version::parse(version)?,
+            status_code: StatusCode::parse(status).map_err(|e| e.advance(version.len() + 1))?,
             status_text: text,
             headers: headers,
             body: body,
-        }
+        })
     }
 }
 
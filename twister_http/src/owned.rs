@@ -0,0 +1,397 @@
+use alloc::vec::Vec;
+
+use {Header, HttpMethod, Request, Response, StatusCode, Version, METHODS};
+use serialize::{serialize_message, status_digits, Serialize, Sink};
+
+/// A header that owns its name and value. See [`Header`].
+///
+/// [`Header`]: ../struct.Header.html
+#[derive(Debug, PartialEq, Clone)]
+pub struct OwnedHeader(pub Vec<u8>, pub Vec<u8>);
+
+impl OwnedHeader {
+    /// Borrows the header's name and value as a [`Header`]
+    ///
+    /// [`Header`]: ../struct.Header.html
+    pub fn as_header(&self) -> Header<'_> {
+        Header(&self.0, &self.1)
+    }
+}
+
+impl<'a> From<&'a Header<'a>> for OwnedHeader {
+    fn from(header: &'a Header<'a>) -> OwnedHeader {
+        OwnedHeader(header.0.to_vec(), header.1.to_vec())
+    }
+}
+
+/// A method that owns its name if it isn't a standard one. See
+/// [`HttpMethod`].
+///
+/// [`HttpMethod`]: ../enum.HttpMethod.html
+#[derive(Debug, PartialEq, Clone)]
+pub enum OwnedMethod {
+    /// A standard method - E.g. `HttpMethod::Get`. Never
+    /// `HttpMethod::Other`.
+    Standard(HttpMethod<'static>),
+    /// Any other method's name - E.g. `PURGE`
+    Other(Vec<u8>),
+}
+
+impl OwnedMethod {
+    /// Borrows the method as a [`HttpMethod`]
+    ///
+    /// [`HttpMethod`]: ../enum.HttpMethod.html
+    pub fn as_method(&self) -> HttpMethod<'_> {
+        match *self {
+            OwnedMethod::Standard(method) => method,
+            OwnedMethod::Other(ref name) => HttpMethod::Other(name),
+        }
+    }
+}
+
+impl<'a> From<HttpMethod<'a>> for OwnedMethod {
+    fn from(method: HttpMethod<'a>) -> OwnedMethod {
+        METHODS.iter()
+            .find(|&&(_, standard)| standard == method)
+            .map(|&(_, standard)| OwnedMethod::Standard(standard))
+            .unwrap_or_else(|| OwnedMethod::Other(method.as_bytes().to_vec()))
+    }
+}
+
+/// A [`Request`] that owns its data, so it can be kept after the buffer
+/// it was parsed from is reused
+///
+/// [`Request`]: ../struct.Request.html
+#[derive(Debug, PartialEq, Clone)]
+pub struct OwnedRequest {
+    /// The request's method - E.g. `GET`. See [`OwnedMethod`]
+    ///
+    /// [`OwnedMethod`]: enum.OwnedMethod.html
+    pub method: OwnedMethod,
+    /// The request target - E.g. `/index.html`
+    pub path: Vec<u8>,
+    /// The version - E.g. `HTTP/1.1`
+    pub version: Version,
+    /// The headers, in the order they were sent
+    pub headers: Vec<OwnedHeader>,
+    /// The body, or as much of it as was read with the head
+    pub body: Vec<u8>,
+}
+
+impl OwnedRequest {
+    /// Borrows the request as a [`Request`], whose headers are stored in
+    /// `headers`
+    ///
+    /// # Panics
+    /// This function will `panic` if `headers` has less room than the
+    /// request has headers.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")] {
+    /// use twister_http::{Header, HttpMethod};
+    /// use twister_http::owned::RequestBuilder;
+    ///
+    /// let owned = RequestBuilder::new(HttpMethod::Get, "/").header("Host", "docs.rs").build();
+    ///
+    /// let mut headers = [Header::default(); 4];
+    /// let request = owned.as_request(&mut headers);
+    /// assert_eq!(Some(&b"docs.rs"[..]), request.get(b"Host"));
+    /// # }
+    /// ```
+    pub fn as_request<'a>(&'a self, headers: &'a mut [Header<'a>]) -> Request<'a> {
+        let headers = copy_headers(&self.headers, headers);
+        Request {
+            method: self.method.as_method(),
+            path: &self.path,
+            version: self.version,
+            headers: headers,
+            body: &self.body,
+        }
+    }
+}
+
+/// A [`Response`] that owns its data, so it can be kept after the buffer
+/// it was parsed from is reused
+///
+/// [`Response`]: ../struct.Response.html
+#[derive(Debug, PartialEq, Clone)]
+pub struct OwnedResponse {
+    /// The version - E.g. `HTTP/1.1`
+    pub version: Version,
+    /// The status code - E.g. `404`
+    pub status_code: StatusCode,
+    /// The status text - E.g. `Not Found`
+    pub status_text: Vec<u8>,
+    /// The headers, in the order they were sent
+    pub headers: Vec<OwnedHeader>,
+    /// The body, or as much of it as was read with the head
+    pub body: Vec<u8>,
+}
+
+impl OwnedResponse {
+    /// Borrows the response as a [`Response`], whose headers are stored
+    /// in `headers`. See [`OwnedRequest::as_request`].
+    ///
+    /// # Panics
+    /// This function will `panic` if `headers` has less room than the
+    /// response has headers.
+    ///
+    /// [`Response`]: ../struct.Response.html
+    /// [`OwnedRequest::as_request`]: struct.OwnedRequest.html#method.as_request
+    pub fn as_response<'a>(&'a self, headers: &'a mut [Header<'a>]) -> Response<'a> {
+        let headers = copy_headers(&self.headers, headers);
+        Response {
+            version: self.version,
+            status_code: self.status_code,
+            status_text: &self.status_text,
+            headers: headers,
+            body: &self.body,
+        }
+    }
+}
+
+/// Borrows each of `owned` into the start of `headers`, returning the
+/// part of `headers` that was filled
+fn copy_headers<'a>(owned: &'a [OwnedHeader], headers: &'a mut [Header<'a>]) -> &'a [Header<'a>] {
+    let headers = &mut headers[..owned.len()];
+    for (header, owned) in headers.iter_mut().zip(owned) {
+        *header = owned.as_header();
+    }
+
+    headers
+}
+
+impl<'a> Request<'a> {
+    /// Copies the request into an [`OwnedRequest`]
+    ///
+    /// [`OwnedRequest`]: owned/struct.OwnedRequest.html
+    pub fn to_owned_request(&self) -> OwnedRequest {
+        OwnedRequest {
+            method: OwnedMethod::from(self.method),
+            path: self.path.to_vec(),
+            version: self.version,
+            headers: self.headers.iter().map(OwnedHeader::from).collect(),
+            body: self.body.to_vec(),
+        }
+    }
+}
+
+impl<'a> Response<'a> {
+    /// Copies the response into an [`OwnedResponse`]
+    ///
+    /// [`OwnedResponse`]: owned/struct.OwnedResponse.html
+    pub fn to_owned_response(&self) -> OwnedResponse {
+        OwnedResponse {
+            version: self.version,
+            status_code: self.status_code,
+            status_text: self.status_text.to_vec(),
+            headers: self.headers.iter().map(OwnedHeader::from).collect(),
+            body: self.body.to_vec(),
+        }
+    }
+}
+
+impl Serialize for OwnedRequest {
+    fn serialize<S: Sink>(&self, sink: &mut S) -> Result<(), S::Error> {
+        let start = [self.method.as_method().as_bytes(), &*self.path, self.version.as_bytes()];
+        serialize_message(start, self.headers.iter().map(OwnedHeader::as_header), &self.body, sink)
+    }
+}
+
+impl Serialize for OwnedResponse {
+    fn serialize<S: Sink>(&self, sink: &mut S) -> Result<(), S::Error> {
+        let mut digits = [0; 5];
+        let start = [self.version.as_bytes(), status_digits(self.status_code, &mut digits), &*self.status_text];
+        serialize_message(start, self.headers.iter().map(OwnedHeader::as_header), &self.body, sink)
+    }
+}
+
+/// Constructs an [`OwnedRequest`]. The version defaults to `HTTP/1.1`.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "alloc")] {
+/// use twister_http::HttpMethod;
+/// use twister_http::owned::RequestBuilder;
+///
+/// let request = RequestBuilder::new(HttpMethod::Post, "/submit")
+///     .header("Host", "example.com")
+///     .header("Content-Length", "5")
+///     .body("hello")
+///     .build();
+///
+/// assert_eq!(HttpMethod::Post, request.method.as_method());
+/// # }
+/// ```
+///
+/// [`OwnedRequest`]: struct.OwnedRequest.html
+pub struct RequestBuilder(OwnedRequest);
+
+impl RequestBuilder {
+    /// Starts a request with `method` for `path`, without headers or a
+    /// body
+    pub fn new<P: AsRef<[u8]>>(method: HttpMethod, path: P) -> RequestBuilder {
+        RequestBuilder(OwnedRequest {
+            method: OwnedMethod::from(method),
+            path: path.as_ref().to_vec(),
+            version: Version::Http11,
+            headers: Vec::new(),
+            body: Vec::new(),
+        })
+    }
+
+    /// Replaces the default version
+    pub fn version(mut self, version: Version) -> RequestBuilder {
+        self.0.version = version;
+        self
+    }
+
+    /// Adds a header after those already added
+    pub fn header<N, V>(mut self, name: N, value: V) -> RequestBuilder
+        where N: AsRef<[u8]>,
+              V: AsRef<[u8]>,
+    {
+        self.0.headers.push(OwnedHeader(name.as_ref().to_vec(), value.as_ref().to_vec()));
+        self
+    }
+
+    /// Replaces the body. Framing headers aren't added.
+    pub fn body<B: AsRef<[u8]>>(mut self, body: B) -> RequestBuilder {
+        self.0.body = body.as_ref().to_vec();
+        self
+    }
+
+    /// Returns the request
+    pub fn build(self) -> OwnedRequest {
+        self.0
+    }
+}
+
+/// Constructs an [`OwnedResponse`]. The version defaults to `HTTP/1.1`,
+/// and the status text to the status code's reason phrase.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "alloc")] {
+/// use twister_http::StatusCode;
+/// use twister_http::owned::ResponseBuilder;
+///
+/// let response = ResponseBuilder::new(StatusCode(404)).build();
+/// assert_eq!(b"Not Found", &*response.status_text);
+/// # }
+/// ```
+///
+/// [`OwnedResponse`]: struct.OwnedResponse.html
+pub struct ResponseBuilder(OwnedResponse);
+
+impl ResponseBuilder {
+    /// Starts a response with `status_code`, without headers or a body
+    pub fn new(status_code: StatusCode) -> ResponseBuilder {
+        ResponseBuilder(OwnedResponse {
+            version: Version::Http11,
+            status_code: status_code,
+            status_text: status_code.reason().unwrap_or("").as_bytes().to_vec(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        })
+    }
+
+    /// Replaces the default version
+    pub fn version(mut self, version: Version) -> ResponseBuilder {
+        self.0.version = version;
+        self
+    }
+
+    /// Replaces the default status text
+    pub fn status_text<T: AsRef<[u8]>>(mut self, status_text: T) -> ResponseBuilder {
+        self.0.status_text = status_text.as_ref().to_vec();
+        self
+    }
+
+    /// Adds a header after those already added
+    pub fn header<N, V>(mut self, name: N, value: V) -> ResponseBuilder
+        where N: AsRef<[u8]>,
+              V: AsRef<[u8]>,
+    {
+        self.0.headers.push(OwnedHeader(name.as_ref().to_vec(), value.as_ref().to_vec()));
+        self
+    }
+
+    /// Replaces the body. Framing headers aren't added.
+    pub fn body<B: AsRef<[u8]>>(mut self, body: B) -> ResponseBuilder {
+        self.0.body = body.as_ref().to_vec();
+        self
+    }
+
+    /// Returns the response
+    pub fn build(self) -> OwnedResponse {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod owned_should {
+    use super::*;
+    use parser::HttpObjectParser;
+
+    #[test]
+    fn outlive_the_parsed_buffer() {
+        let mut buffer = b"GET /index.html HTTP/1.1\r\nHost: docs.rs\r\n\r\n".to_vec();
+        let owned = {
+            let mut headers = [Header::default(); 4];
+            HttpObjectParser::new(&mut headers).parse::<Request>(&buffer).unwrap().unwrap().to_owned_request()
+        };
+        buffer.clear();
+
+        let expected = RequestBuilder::new(HttpMethod::Get, "/index.html").header("Host", "docs.rs").build();
+        assert_eq!(expected, owned);
+        assert_eq!(HttpMethod::Get, owned.method.as_method());
+    }
+
+    #[test]
+    fn serialize_like_borrowed_objects() {
+        let response = ResponseBuilder::new(StatusCode(200))
+            .version(Version::Http10)
+            .header("Content-Length", "2")
+            .body("Hi")
+            .build();
+
+        let mut buffer = [0; 64];
+        let len = response.write_to(&mut buffer).unwrap();
+        assert_eq!(&b"HTTP/1.0 200 OK\r\nContent-Length: 2\r\n\r\nHi"[..], &buffer[..len]);
+
+        let mut headers = [Header::default(); 4];
+        let parsed = HttpObjectParser::new(&mut headers).parse::<Response>(&buffer[..len]).unwrap().unwrap();
+        assert_eq!(response, parsed.to_owned_response());
+    }
+
+    #[test]
+    fn keep_methods_that_arent_standard() {
+        let request = RequestBuilder::new(HttpMethod::Other(b"PURGE"), "/").build();
+        assert_eq!(OwnedMethod::Other(b"PURGE".to_vec()), request.method);
+        assert_eq!(HttpMethod::Other(b"PURGE"), request.method.as_method());
+        assert_eq!(OwnedMethod::Standard(HttpMethod::Get), OwnedMethod::from(HttpMethod::Get));
+    }
+
+    #[test]
+    fn convert_back_to_borrowed_objects() {
+        let mut buffer = b"GET /index.html HTTP/1.1\r\nHost: docs.rs\r\n\r\n".to_vec();
+        let mut headers = [Header::default(); 4];
+        let owned = HttpObjectParser::new(&mut headers).parse::<Request>(&buffer).unwrap().unwrap().to_owned_request();
+        buffer.clear();
+
+        let mut headers = [Header::default(); 4];
+        let request = owned.as_request(&mut headers);
+        assert_eq!(HttpMethod::Get, request.method);
+        assert_eq!(&[Header(b"Host", b"docs.rs")][..], request.headers);
+        assert_eq!(owned, request.to_owned_request());
+
+        let owned = ResponseBuilder::new(StatusCode(204)).header("Server", "twister").build();
+        let mut headers = [Header::default(); 1];
+        assert_eq!(owned, owned.as_response(&mut headers).to_owned_response());
+    }
+}
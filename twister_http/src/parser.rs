@@ -1,54 +1,344 @@
+use core::cmp;
+use core::convert::TryFrom;
+use core::fmt;
 use core::mem;
-use Header;
+use {Header, HttpMethod, MethodCase, Request, Response, StatusCode, Version};
 
-fn skip_newline(data: &[u8]) -> &[u8] {
-    data.iter()
-        .position(|b| *b != b'\r' && *b != b'\n')
-        .map(|p| {
-            let (_, tail) = data.split_at(p);
-            tail
-        })
-        .unwrap_or_else(|| &[])
+/// The outcome of a parse that didn't fail.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Status<T> {
+    /// Parsing finished and produced a `T`
+    Complete(T),
+    /// The data ended before the item being parsed did. Parsing
+    /// should be retried once more data is available.
+    Partial,
 }
 
-fn skip_whitespace(data: &[u8]) -> &[u8] {
-    data.iter()
-        .position(|byte| *byte != b' ' && *byte != b'\t')
-        .map(|p| {
-            let (_, tail) = data.split_at(p);
-            tail
-        })
-        .unwrap_or_else(|| &[])
+impl<T> Status<T> {
+    /// Returns `true` if the status is [`Status::Complete`]
+    ///
+    /// [`Status::Complete`]: enum.Status.html#variant.Complete
+    pub fn is_complete(&self) -> bool {
+        match *self {
+            Status::Complete(_) => true,
+            Status::Partial => false,
+        }
+    }
+
+    /// Returns `true` if the status is [`Status::Partial`]
+    ///
+    /// [`Status::Partial`]: enum.Status.html#variant.Partial
+    pub fn is_partial(&self) -> bool {
+        !self.is_complete()
+    }
+
+    /// Converts the status into a `Result`, treating a partial item
+    /// as [`ParseError::Incomplete`]. This is useful once the caller
+    /// knows no more data will arrive - E.g. at EOF. `len` is the
+    /// amount of data that was parsed, and is reported as the
+    /// error's offset.
+    ///
+    /// [`ParseError::Incomplete`]: enum.ParseError.html#variant.Incomplete
+    pub fn into_complete(self, len: usize) -> Result<T, ParseError> {
+        match self {
+            Status::Complete(val) => Ok(val),
+            Status::Partial => Err(ParseError::Incomplete(len)),
+        }
+    }
+
+    /// Returns the completed value.
+    ///
+    /// # Panics
+    /// This function will `panic` if the status is [`Status::Partial`]
+    ///
+    /// [`Status::Partial`]: enum.Status.html#variant.Partial
+    pub fn unwrap(self) -> T {
+        match self {
+            Status::Complete(val) => val,
+            Status::Partial => panic!("unwrap called on a partial status"),
+        }
+    }
 }
 
-fn skip_header_separator(data: &[u8]) -> &[u8] {
-    data.iter()
-        .position(|byte| *byte != b'\t' && *byte != b' ' && *byte != b':')
-        .map(|p| {
-            let (_, tail) = data.split_at(p);
-            tail
-        })
-        .unwrap_or_else(|| &[])
+/// The errors that can occur when parsing a HTTP object. Each
+/// variant carries the byte offset, relative to the start of the
+/// data given to the parser, at which parsing failed.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ParseError {
+    /// The data ended before a complete object was found. Parsers
+    /// report this as [`Status::Partial`]; it is only produced by
+    /// [`Status::into_complete`].
+    ///
+    /// [`Status::Partial`]: enum.Status.html#variant.Partial
+    /// [`Status::into_complete`]: enum.Status.html#method.into_complete
+    Incomplete(usize),
+    /// The request method contains a non-token character, is empty, or
+    /// is a standard method in the wrong case when methods are
+    /// case-sensitive
+    InvalidMethod(usize),
+    /// The request path contains a whitespace or control character,
+    /// or is empty
+    InvalidPath(usize),
+    /// The version isn't of the form `HTTP/x.y`, or isn't `HTTP/1.0` or
+    /// `HTTP/1.1`
+    InvalidVersion(usize),
+    /// The status code isn't three digits, or the status text
+    /// contains a control character
+    InvalidStatus(usize),
+    /// A header name contains a non-token character, or is empty
+    InvalidHeaderName(usize),
+    /// A header value contains a control character
+    InvalidHeaderValue(usize),
+    /// A `\r` was found that isn't followed by `\n`
+    InvalidNewline(usize),
+    /// There wasn't enough room to store all of the object's headers.
+    /// The first field is the offset of the first header that didn't
+    /// fit, the second is the total number of headers in the object. A
+    /// caller can use the latter to provide enough room and parse again.
+    TooManyHeaders(usize, usize),
 }
 
-fn split_as_first_newline(data: &[u8]) -> Option<(&[u8], &[u8])> {
-    data.iter()
-        .position(|byte| *byte == b'\r' || *byte == b'\n')
-        .map(|p| data.split_at(p))
+impl ParseError {
+    /// The byte offset at which parsing failed
+    pub fn offset(&self) -> usize {
+        use self::ParseError::*;
+
+        match *self {
+            Incomplete(n) |
+            InvalidMethod(n) |
+            InvalidPath(n) |
+            InvalidVersion(n) |
+            InvalidStatus(n) |
+            InvalidHeaderName(n) |
+            InvalidHeaderValue(n) |
+            InvalidNewline(n) |
+            TooManyHeaders(n, _) => n,
+        }
+    }
+
+    /// Moves the error's offset `by` bytes further into the data. Used
+    /// to translate offsets from a sub-parser into those of its caller.
+    pub(crate) fn advance(self, by: usize) -> ParseError {
+        use self::ParseError::*;
+
+        match self {
+            Incomplete(n) => Incomplete(n + by),
+            InvalidMethod(n) => InvalidMethod(n + by),
+            InvalidPath(n) => InvalidPath(n + by),
+            InvalidVersion(n) => InvalidVersion(n + by),
+            InvalidStatus(n) => InvalidStatus(n + by),
+            InvalidHeaderName(n) => InvalidHeaderName(n + by),
+            InvalidHeaderValue(n) => InvalidHeaderValue(n + by),
+            InvalidNewline(n) => InvalidNewline(n + by),
+            TooManyHeaders(n, count) => TooManyHeaders(n + by, count),
+        }
+    }
 }
 
-fn split_at_first_whitespace(data: &[u8]) -> Option<(&[u8], &[u8])> {
-    data.iter()
-        .position(|byte| *byte == b' ' || *byte == b'\t')
-        .map(|p| data.split_at(p))
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ParseError::*;
+
+        let description = match *self {
+            Incomplete(_) => "incomplete object",
+            InvalidMethod(_) => "invalid method",
+            InvalidPath(_) => "invalid path",
+            InvalidVersion(_) => "invalid version",
+            InvalidStatus(_) => "invalid status",
+            InvalidHeaderName(_) => "invalid header name",
+            InvalidHeaderValue(_) => "invalid header value",
+            InvalidNewline(_) => "invalid newline",
+            TooManyHeaders(..) => "too many headers",
+        };
+
+        write!(f, "{} at byte {}", description, self.offset())
+    }
+}
+
+/// The kind of *start line* a HTTP object begins with.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum StartLine {
+    /// A request line - E.g. `GET /index.html HTTP/1.1`
+    Request,
+    /// A response's status line - E.g. `HTTP/1.1 200 OK`
+    Status,
+}
+
+/// A start line parsed by [`ProtocolParser`]. A request's method is
+/// left as bytes, because whether it's recognised depends on the
+/// [`MethodCase`] it's compared in.
+///
+/// [`ProtocolParser`]: enum.ProtocolParser.html
+/// [`MethodCase`]: ../enum.MethodCase.html
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Line<'a> {
+    /// A request line - E.g. `GET /index.html HTTP/1.1`
+    Request {
+        method: &'a [u8],
+        path: &'a [u8],
+        version: Version,
+    },
+    /// A status line - E.g. `HTTP/1.1 200 OK`
+    Status {
+        version: Version,
+        status_code: StatusCode,
+        status_text: &'a [u8],
+    },
+}
+
+impl<'a> Line<'a> {
+    /// Builds a start line of `kind` from the parts found by
+    /// [`ProtocolParser`], which are separated by single spaces
+    ///
+    /// [`ProtocolParser`]: enum.ProtocolParser.html
+    fn new(kind: StartLine, parts: (&'a [u8], &'a [u8], &'a [u8])) -> Result<Line<'a>, ParseError> {
+        let (part1, part2, part3) = parts;
+        match kind {
+            StartLine::Request => Ok(Line::Request {
+                method: part1,
+                path: part2,
+                version: Version::parse(part3).map_err(|e| e.advance(part1.len() + part2.len() + 2))?,
+            }),
+            StartLine::Status => Ok(Line::Status {
+                version: Version::parse(part1)?,
+                status_code: StatusCode::parse(part2).map_err(|e| e.advance(part1.len() + 1))?,
+                status_text: part3,
+            }),
+        }
+    }
 }
 
-fn split_at_first_header_separator(data: &[u8]) -> Option<(&[u8], &[u8])> {
+/// The raw parts of a start line, and the data after it
+type Parts<'a> = (&'a [u8], &'a [u8], &'a [u8], &'a [u8]);
+
+/// Implemented by the HTTP object types that [`HttpObjectParser`]
+/// can produce.
+///
+/// [`HttpObjectParser`]: enum.HttpObjectParser.html
+pub trait HttpObject<'a>: TryFrom<(&'a [u8], &'a [u8], &'a [u8], &'a [Header<'a>], &'a [u8]), Error = ParseError> {
+    /// The kind of start line the object begins with
+    const START_LINE: StartLine;
+}
+
+impl<'a> HttpObject<'a> for Request<'a> {
+    const START_LINE: StartLine = StartLine::Request;
+}
+
+impl<'a> HttpObject<'a> for Response<'a> {
+    const START_LINE: StartLine = StartLine::Status;
+}
+
+type ParseResult<T> = Result<Status<T>, ParseError>;
+
+/// `tchar` as defined by RFC 9110, section 5.6.2
+pub(crate) fn is_token(byte: u8) -> bool {
+    matches!(byte,
+        b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' |
+        b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' |
+        b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~')
+}
+
+fn is_visible(byte: u8) -> bool {
+    byte > b' ' && byte < 0x7f
+}
+
+fn is_digit(byte: u8) -> bool {
+    byte.is_ascii_digit()
+}
+
+/// Bytes allowed in header values and status text. Includes
+/// whitespace and `obs-text`, but no other control characters.
+fn is_text(byte: u8) -> bool {
+    byte == b'\t' || (byte >= b' ' && byte != 0x7f)
+}
+
+pub(crate) fn is_whitespace(byte: u8) -> bool {
+    byte == b' ' || byte == b'\t'
+}
+
+/// Control characters that can't appear anywhere in a HTTP object's
+/// head, so finding one means the object is invalid.
+fn is_forbidden(byte: u8) -> bool {
+    (byte < b' ' && byte != b'\t' && byte != b'\r' && byte != b'\n') || byte == 0x7f
+}
+
+/// Splits `data` at the first byte that doesn't satisfy `pred`. If
+/// every byte satisfies `pred` then the end of the item can't be known
+/// yet, so `None` is returned.
+fn split_while<F>(data: &[u8], pred: F) -> Option<(&[u8], &[u8])>
+    where F: Fn(u8) -> bool
+{
     data.iter()
-        .position(|byte| *byte == b':')
+        .position(|byte| !pred(*byte))
         .map(|p| data.split_at(p))
 }
 
+/// Consumes the line ending at the start of `data`. Either `\r\n`,
+/// or a bare `\n`, is accepted.
+fn skip_newline(data: &[u8]) -> ParseResult<&[u8]> {
+    match data.first() {
+        None => Ok(Status::Partial),
+        Some(&b'\n') => Ok(Status::Complete(&data[1..])),
+        Some(&b'\r') => match data.get(1) {
+            None => Ok(Status::Partial),
+            Some(&b'\n') => Ok(Status::Complete(&data[2..])),
+            Some(_) => Err(ParseError::InvalidNewline(1)),
+        },
+        Some(_) => Err(ParseError::InvalidNewline(0)),
+    }
+}
+
+fn is_newline(byte: u8) -> bool {
+    byte == b'\r' || byte == b'\n'
+}
+
+pub(crate) fn trim_whitespace(data: &[u8]) -> &[u8] {
+    let start = data.iter()
+        .position(|byte| !is_whitespace(*byte))
+        .unwrap_or(data.len());
+    let end = data.iter()
+        .rposition(|byte| !is_whitespace(*byte))
+        .map(|p| p + 1)
+        .unwrap_or(start);
+
+    &data[start..end]
+}
+
+/// Checks `version` is `HTTP/1.0` or `HTTP/1.1`, returning the offset
+/// of the first offending byte if it isn't.
+pub(crate) fn check_version(version: &[u8]) -> Result<(), usize> {
+    const PREFIX: &[u8] = b"HTTP/";
+
+    if let Some(p) = PREFIX.iter().zip(version).position(|(a, b)| a != b) {
+        return Err(p);
+    }
+
+    match version.len() {
+        8 => {},
+        n if n < 8 => return Err(n),
+        _ => return Err(8),
+    }
+
+    if !is_digit(version[5]) {
+        Err(5)
+    }
+    else if version[6] != b'.' {
+        Err(6)
+    }
+    else if !is_digit(version[7]) {
+        Err(7)
+    }
+    else if version[5] != b'1' {
+        Err(5)
+    }
+    else if version[7] != b'0' && version[7] != b'1' {
+        Err(7)
+    }
+    else {
+        Ok(())
+    }
+}
+
 /// A type to parse the *protocol line* of a HTTP request.
 /// E.g.
 ///
@@ -56,15 +346,21 @@ fn split_at_first_header_separator(data: &[u8]) -> Option<(&[u8], &[u8])> {
 /// CONNECT docs.rs:443 HTTP/1.1
 /// ```
 ///
+/// It can also parse the *status line* of a HTTP response. E.g.
+///
+/// ```no_compile
+/// HTTP/1.1 200 OK
+/// ```
+///
 /// `ProtocolParser` is non-allocating and works purely
 /// on borrowed data, hence the lifetime parameter.
 pub enum ProtocolParser<'a> {
     #[doc(hidden)]
-    Method(&'a [u8]),
+    Method(StartLine, &'a [u8]),
     #[doc(hidden)]
-    Path(&'a [u8], &'a [u8]),
+    Path(StartLine, &'a [u8], usize, &'a [u8]),
     #[doc(hidden)]
-    Version(&'a [u8], &'a [u8], &'a [u8]),
+    Version(StartLine, &'a [u8], &'a [u8], usize, &'a [u8]),
     #[doc(hidden)]
     Done,
 }
@@ -82,7 +378,7 @@ pub enum HeaderParser<'a> {
     #[doc(hidden)]
     Name(&'a [u8]),
     #[doc(hidden)]
-    Value(&'a [u8], &'a [u8]),
+    Value(&'a [u8], usize, &'a [u8]),
     #[doc(hidden)]
     Done,
 }
@@ -91,10 +387,17 @@ impl<'a> ProtocolParser<'a> {
     /// Creates a new instance. `bytes` must be at the start
     /// of the *protocol line* for any parsing to be successful.
     pub fn new(bytes: &'a [u8]) -> ProtocolParser<'a> {
-        ProtocolParser::Method(bytes)
+        ProtocolParser::Method(StartLine::Request, bytes)
+    }
+
+    /// Creates a new instance to parse a response's *status line*.
+    /// `bytes` must be at the start of the line for any parsing
+    /// to be successful.
+    pub fn new_status(bytes: &'a [u8]) -> ProtocolParser<'a> {
+        ProtocolParser::Method(StartLine::Status, bytes)
     }
 
-    /// Parses the protocol line contained at the start of 
+    /// Parses the protocol line contained at the start of
     /// the data provided to [`ProtocolParser::new`]
     ///
     /// Parse requires `&mut self` because it is internally
@@ -102,66 +405,163 @@ impl<'a> ProtocolParser<'a> {
     /// itself in the process of parsing.
     ///
     /// # Return Value
-    /// If parsing is successful, a tuple is returned consisting
-    /// of `(method: HttpMethod, path: &[u8], version: &[u8], 
-    /// remaining: &[u8])`. `remaining` is any remaining data found 
-    /// after the protocol line. The parser consumes the trailing `\r\n` 
-    /// bytes of the protocol line so, assuming a well-formed request, 
-    /// `remaining` is at the very start of the first header line.
+    /// If parsing is successful, a tuple is returned consisting of
+    /// `(line: Line, remaining: &[u8])`. `remaining` is any remaining
+    /// data found after the protocol line. The parser consumes the
+    /// trailing `\r\n` bytes of the protocol line so, assuming a
+    /// well-formed request, `remaining` is at the very start of the
+    /// first header line. The version and status code are parsed into a
+    /// [`Version`] and a [`StatusCode`].
     ///
-    /// If parsing can't be completed because either the data is
-    /// incomplete, or it is invalid, then this function returns
-    /// `None`.
+    /// If the data ends before the protocol line does then
+    /// [`Status::Partial`] is returned. If the line is invalid then
+    /// a [`ParseError`] describing where is returned.
     ///
     /// # Examples
     ///
     /// ```
-    /// use twister_http::parser::ProtocolParser;
-    /// use twister_http::HttpMethod;
+    /// use twister_http::parser::{Line, ProtocolParser};
+    /// use twister_http::Version;
     ///
-    /// const HTTP: &'static [u8] = b"GET /index.html HTTP/1.1\r\n";
+    /// const HTTP: &[u8] = b"GET /index.html HTTP/1.1\r\n";
     ///
     /// let mut parser = ProtocolParser::new(HTTP);
-    /// let (method, path, version, tail) = parser.parse().unwrap();
+    /// let (line, tail) = parser.parse().unwrap().unwrap();
     ///
-    /// assert_eq!(HttpMethod::Get, method.into());
-    /// assert_eq!(b"/index.html", path);
-    /// assert_eq!(b"HTTP/1.1", version);
+    /// assert_eq!(Line::Request { method: b"GET", path: b"/index.html", version: Version::Http11 }, line);
     /// assert_eq!(0, tail.len());
     /// ```
     ///
     /// [`ProtocolParser::new`]: enum.ProtocolParser.html#method.new
-    pub fn parse(&mut self) -> Option<(&'a [u8], &'a [u8], &'a [u8], &'a [u8])> {
+    /// [`Version`]: ../enum.Version.html
+    /// [`StatusCode`]: ../struct.StatusCode.html
+    /// [`Status::Partial`]: enum.Status.html#variant.Partial
+    /// [`ParseError`]: enum.ParseError.html
+    pub fn parse(&mut self) -> ParseResult<(Line<'a>, &'a [u8])> {
+        let kind = match *self {
+            ProtocolParser::Method(kind, _) | ProtocolParser::Path(kind, ..) | ProtocolParser::Version(kind, ..) => kind,
+            ProtocolParser::Done => panic!("parse called after done"),
+        };
+
+        match self.parse_parts()? {
+            Status::Complete((part1, part2, part3, tail)) =>
+                Line::new(kind, (part1, part2, part3)).map(|line| Status::Complete((line, tail))),
+            Status::Partial => Ok(Status::Partial),
+        }
+    }
+
+    /// Parses the protocol line into its raw parts. The version and
+    /// status code are checked but left as bytes.
+    fn parse_parts(&mut self) -> ParseResult<Parts<'a>> {
         use self::ProtocolParser::*;
         loop {
             let next = match mem::replace(self, Done) {
-                Method(data) => {
-                    split_at_first_whitespace(data)
-                        .map(|(val, tail)| {
-                            Path(val, skip_whitespace(tail))
-                        })
+                Method(StartLine::Request, data) => {
+                    let (val, tail) = match split_while(data, is_token) {
+                        Some(parts) => parts,
+                        None => return Ok(Status::Partial),
+                    };
+
+                    if val.is_empty() || tail[0] != b' ' {
+                        return Err(ParseError::InvalidMethod(val.len()));
+                    }
+
+                    Path(StartLine::Request, val, val.len() + 1, &tail[1..])
                 },
-                Path(method, data) => {
-                    split_at_first_whitespace(data)
-                        .map(|(val, tail)| {
-                            Version(method, val, skip_whitespace(tail))
+                Method(StartLine::Status, data) => {
+                    let (val, tail) = match split_while(data, is_visible) {
+                        Some(parts) => parts,
+                        None => return Ok(Status::Partial),
+                    };
+
+                    if let Err(p) = check_version(val) {
+                        return Err(ParseError::InvalidVersion(p));
+                    }
+
+                    if tail[0] != b' ' {
+                        return Err(ParseError::InvalidVersion(val.len()));
+                    }
+
+                    Path(StartLine::Status, val, val.len() + 1, &tail[1..])
+                },
+                Path(StartLine::Request, method, offset, data) => {
+                    let (val, tail) = match split_while(data, is_visible) {
+                        Some(parts) => parts,
+                        None => return Ok(Status::Partial),
+                    };
+
+                    if val.is_empty() {
+                        return Err(ParseError::InvalidPath(offset));
+                    }
+
+                    match tail[0] {
+                        b' ' => {},
+                        b if is_newline(b) => return Err(ParseError::InvalidVersion(offset + val.len())),
+                        _ => return Err(ParseError::InvalidPath(offset + val.len())),
+                    }
+
+                    Version(StartLine::Request, method, val, offset + val.len() + 1, &tail[1..])
+                },
+                Path(StartLine::Status, version, offset, data) => {
+                    let (val, tail) = match split_while(data, is_digit) {
+                        Some(parts) => parts,
+                        None if data.len() <= 3 => return Ok(Status::Partial),
+                        None => return Err(ParseError::InvalidStatus(offset + 3)),
+                    };
+
+                    if val.len() != 3 {
+                        return Err(ParseError::InvalidStatus(offset + cmp::min(val.len(), 3)));
+                    }
+
+                    // The status text may be omitted entirely
+                    match tail[0] {
+                        b' ' => Version(StartLine::Status, version, val, offset + 4, &tail[1..]),
+                        b if is_newline(b) => Version(StartLine::Status, version, val, offset + 3, tail),
+                        _ => return Err(ParseError::InvalidStatus(offset + 3)),
+                    }
+                },
+                Version(StartLine::Request, method, path, offset, data) => {
+                    let (val, tail) = match split_while(data, is_visible) {
+                        Some(parts) => parts,
+                        None => return Ok(Status::Partial),
+                    };
+
+                    if let Err(p) = check_version(val) {
+                        return Err(ParseError::InvalidVersion(offset + p));
+                    }
+
+                    if !is_newline(tail[0]) {
+                        return Err(ParseError::InvalidVersion(offset + val.len()));
+                    }
+
+                    return skip_newline(tail)
+                        .map(|status| match status {
+                            Status::Complete(tail) => Status::Complete((method, path, val, tail)),
+                            Status::Partial => Status::Partial,
                         })
+                        .map_err(|e| e.advance(offset + val.len()));
                 },
-                Version(method, url, data) => {
-                    return split_as_first_newline(data)
-                        .map(|(val, tail)| {
-                            (method, url, val, skip_newline(tail))
-                        });
+                Version(StartLine::Status, version, status, offset, data) => {
+                    let (val, tail) = match split_while(data, is_text) {
+                        Some(parts) => parts,
+                        None => return Ok(Status::Partial),
+                    };
+
+                    if !is_newline(tail[0]) {
+                        return Err(ParseError::InvalidStatus(offset + val.len()));
+                    }
+
+                    return skip_newline(tail)
+                        .map(|status_line| match status_line {
+                            Status::Complete(tail) => Status::Complete((version, status, val, tail)),
+                            Status::Partial => Status::Partial,
+                        })
+                        .map_err(|e| e.advance(offset + val.len()));
                 },
                 Done => panic!("parse called after done"),
             };
 
-            if let Some(next) = next {
-                *self = next;
-            }
-            else {
-                return None
-            }
+            *self = next;
         }
     }
 }
@@ -173,7 +573,7 @@ impl<'a> HeaderParser<'a> {
         HeaderParser::Name(bytes)
     }
 
-    /// Parses a single HTTP header contained at the start of 
+    /// Parses a single HTTP header contained at the start of
     /// the data provided to [`HeaderParser::new`]
     ///
     /// Parsing requires `&mut self` because it is internally
@@ -182,15 +582,20 @@ impl<'a> HeaderParser<'a> {
     ///
     /// # Return Value
     /// If parsing is successful, a tuple is returned consisting
-    /// of `(header: Header, remaining: &[u8])`. `remaining` is 
-    /// any remaining data found after the protocol line. The parser 
-    /// consumes the trailing `\r\n` bytes of the protocol line so, 
-    /// assuming a well-formed request, `remaining` is at the very start 
-    /// of the next header line.
+    /// of `(header: Header, remaining: &[u8])`. `remaining` is
+    /// any remaining data found after the header line. The parser
+    /// consumes the trailing `\r\n` bytes of the header line so,
+    /// assuming a well-formed request, `remaining` is at the very start
+    /// of the next header line. Whitespace surrounding the header's
+    /// value is not included in the value.
+    ///
+    /// If `bytes` starts with the empty line that ends a HTTP object's
+    /// headers then the returned header's name and value are both empty,
+    /// and `remaining` is at the very start of the object's body.
     ///
-    /// If parsing can't be completed because either the data is
-    /// incomplete, or it is invalid, then this function returns
-    /// `None`.
+    /// If the data ends before the header line does then
+    /// [`Status::Partial`] is returned. If the header is invalid then
+    /// a [`ParseError`] describing where is returned.
     ///
     /// # Examples
     ///
@@ -201,7 +606,7 @@ impl<'a> HeaderParser<'a> {
     /// const HTTP: &'static [u8] = b"Content-Type: text/xml; charset=utf8\r\n";
     ///
     /// let mut parser = HeaderParser::new(HTTP);
-    /// let (Header (name, value), remaining) = parser.parse().unwrap();
+    /// let (Header (name, value), remaining) = parser.parse().unwrap().unwrap();
     ///
     /// assert_eq!(b"Content-Type", name);
     /// assert_eq!(b"text/xml; charset=utf8", value);
@@ -209,60 +614,140 @@ impl<'a> HeaderParser<'a> {
     /// ```
     ///
     /// [`HeaderParser::new`]: enum.HeaderParser.html#method.new
-    pub fn parse(&mut self) -> Option<(Header<'a>, &'a [u8])> {
+    /// [`Status::Partial`]: enum.Status.html#variant.Partial
+    /// [`ParseError`]: enum.ParseError.html
+    pub fn parse(&mut self) -> ParseResult<(Header<'a>, &'a [u8])> {
         use self::HeaderParser::*;
 
         loop {
             let next = match mem::replace(self, Done) {
                 Name(data) => {
-                    if let Some(state) = split_at_first_header_separator(data)
-                        .map(|(val, tail)| {
-                            Value(val, skip_header_separator(tail))
-                        })
-                    {
-                        Some(state)
+                    if data.first().map(|b| is_newline(*b)).unwrap_or(false) {
+                        return skip_newline(data)
+                            .map(|status| match status {
+                                Status::Complete(tail) => Status::Complete((Header(&[], &[]), tail)),
+                                Status::Partial => Status::Partial,
+                            });
                     }
-                    else {
-                        return Some((Header(&[], &[]), skip_newline(data)));
+
+                    let (val, tail) = match split_while(data, is_token) {
+                        Some(parts) => parts,
+                        None => return Ok(Status::Partial),
+                    };
+
+                    if val.is_empty() || tail[0] != b':' {
+                        return Err(ParseError::InvalidHeaderName(val.len()));
                     }
+
+                    Value(val, val.len() + 1, &tail[1..])
                 },
-                Value(name, data) => {
-                    return split_as_first_newline(data)
-                        .map(|(val, tail)| {
-                            (Header(name, val), skip_newline(tail))
-                        });
+                Value(name, offset, data) => {
+                    let (val, tail) = match split_while(data, is_text) {
+                        Some(parts) => parts,
+                        None => return Ok(Status::Partial),
+                    };
+
+                    if !is_newline(tail[0]) {
+                        return Err(ParseError::InvalidHeaderValue(offset + val.len()));
+                    }
+
+                    return skip_newline(tail)
+                        .map(|status| match status {
+                            Status::Complete(tail) => Status::Complete((Header(name, trim_whitespace(val)), tail)),
+                            Status::Partial => Status::Partial,
+                        })
+                        .map_err(|e| e.advance(offset + val.len()));
                 },
                 Done => panic!("parse called on finished result"),
             };
 
-            if let Some(next) = next {
-                *self = next;
-            }
-            else {
-                return None;
-            }
+            *self = next;
         }
     }
 }
 
+/// Records how far [`HttpObjectParser::resume`] got through a partial
+/// HTTP object so that, once more data has arrived, parsing can carry on
+/// without examining the same bytes again. Once the head is complete the
+/// token holds where its parts are, so the object can be produced
+/// again - E.g. with more room for headers - without parsing it again.
+///
+/// A token is only meaningful for the data it was produced from. That
+/// data may be extended between calls, but not otherwise modified.
+///
+/// [`HttpObjectParser::resume`]: enum.HttpObjectParser.html#method.resume
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct ResumeToken {
+    line: usize,
+    scanned: usize,
+    start_line: [(usize, usize); 3],
+    headers: usize,
+    complete: bool,
+}
+
+impl ResumeToken {
+    /// Creates a token for an object that hasn't been examined yet
+    pub fn new() -> ResumeToken {
+        ResumeToken::default()
+    }
+
+    /// The number of bytes of the object already examined
+    pub fn offset(&self) -> usize {
+        self.scanned
+    }
+}
+
+/// The position of `part`, which was sliced from `data`, within `data`
+fn span(data: &[u8], part: &[u8]) -> (usize, usize) {
+    let start = part.as_ptr() as usize - data.as_ptr() as usize;
+    (start, start + part.len())
+}
+
+/// Validates a single complete line of a HTTP object's head, or the
+/// partial line at the end of it. `first` indicates whether `line` is
+/// the object's start line.
+fn validate_line(kind: StartLine, case: MethodCase, first: bool, line: &[u8]) -> Result<(), ParseError> {
+    if first {
+        match ProtocolParser::Method(kind, line).parse_parts()? {
+            Status::Complete((method, ..)) => check_method(kind, case, method),
+            Status::Partial => Ok(()),
+        }
+    }
+    else {
+        HeaderParser::new(line).parse().map(|_| ())
+    }
+}
+
+/// Checks a request's method is valid in the `case` the parser was
+/// configured with. The start line parser has already checked it's a
+/// token.
+fn check_method(kind: StartLine, case: MethodCase, method: &[u8]) -> Result<(), ParseError> {
+    match kind {
+        StartLine::Request => HttpMethod::parse(method, case).map(|_| ()),
+        StartLine::Status => Ok(()),
+    }
+}
+
 /// A non-allocating HTTP object parser
 pub enum HttpObjectParser<'a> {
     #[doc(hidden)]
-    NotStarted(&'a mut [Header<'a>]),
+    NotStarted(&'a mut [Header<'a>], MethodCase),
     #[doc(hidden)]
-    Protocol(&'a mut [Header<'a>], ProtocolParser<'a>),
+    Protocol(&'a mut [Header<'a>], MethodCase, ProtocolParser<'a>),
     #[doc(hidden)]
-    Headers(&'a [u8], &'a [u8], &'a [u8], &'a mut [Header<'a>], HeaderParser<'a>),
+    Headers(&'a [u8], &'a [u8], &'a [u8], &'a mut [Header<'a>], &'a [u8]),
     #[doc(hidden)]
     Done
 }
 
-impl<'a> HttpObjectParser<'a> 
+impl<'a> HttpObjectParser<'a>
 {
     /// Creates a new instance. `headers` will be used to store all
     /// the headers found in the HTTP object when [`parse`] is called. It
     /// is important to provide enough space in `headers`, otherwise [`parse`]
-    /// will `panic`.
+    /// will fail with [`ParseError::TooManyHeaders`]. The error reports how
+    /// many headers the object has, so the caller can parse it again with
+    /// enough space.
     ///
     /// # Examples
     /// ```
@@ -272,20 +757,50 @@ impl<'a> HttpObjectParser<'a>
     /// let mut headers = vec![Header::default(); 16];
     /// let mut parser = HttpObjectParser::new(&mut headers);
     /// ```
-    /// [`parse`]: enum.ResponseParser.html#method.parse
+    /// [`parse`]: enum.HttpObjectParser.html#method.parse
+    /// [`ParseError::TooManyHeaders`]: enum.ParseError.html#variant.TooManyHeaders
     pub fn new(headers: &'a mut [Header<'a>]) -> HttpObjectParser<'a> {
-        HttpObjectParser::NotStarted(headers)
+        HttpObjectParser::NotStarted(headers, MethodCase::Insensitive)
+    }
+
+    /// Sets how a request's method is compared with the standard
+    /// methods. Methods are case-insensitive by default. With
+    /// [`MethodCase::Sensitive`], a method such as `get` fails with
+    /// [`ParseError::InvalidMethod`].
+    ///
+    /// # Panics
+    /// This function will `panic` if the parser has already been used.
+    ///
+    /// # Examples
+    /// ```
+    /// use twister_http::{Header, MethodCase, Request};
+    /// use twister_http::parser::{HttpObjectParser, ParseError};
+    ///
+    /// let mut headers = [Header::default(); 16];
+    /// let result = HttpObjectParser::new(&mut headers)
+    ///     .method_case(MethodCase::Sensitive)
+    ///     .parse::<Request>(b"get / HTTP/1.1\r\n\r\n");
+    ///
+    /// assert_eq!(Err(ParseError::InvalidMethod(0)), result.map(|_| ()));
+    /// ```
+    ///
+    /// [`MethodCase::Sensitive`]: ../enum.MethodCase.html#variant.Sensitive
+    /// [`ParseError::InvalidMethod`]: enum.ParseError.html#variant.InvalidMethod
+    pub fn method_case(self, case: MethodCase) -> HttpObjectParser<'a> {
+        match self {
+            HttpObjectParser::NotStarted(headers, _) => HttpObjectParser::NotStarted(headers, case),
+            _ => panic!("method_case called on a used parser"),
+        }
     }
 
     /// Parses a HTTP object.
     ///
     /// # Return Value
-    /// If parsing succeeds, a `T` is returned. If parsing fails
-    /// due to an incomplete, or invalid object then `None` is returned.
-    ///
-    /// # Panics
-    /// This function will `panic` if there is not enough storage for all
-    /// the headers found in the HTTP object.
+    /// If parsing succeeds, a `T` is returned. If `data` ends before
+    /// the object's headers do then [`Status::Partial`] is returned. If
+    /// the object is invalid, or there isn't enough storage for all the
+    /// headers found in the object, then a [`ParseError`] describing
+    /// where is returned.
     ///
     /// # Examples
     ///
@@ -293,10 +808,10 @@ impl<'a> HttpObjectParser<'a>
     ///
     /// ```
     /// use std::str;
-    /// use twister_http::{Header, Response};
+    /// use twister_http::{Header, Response, StatusCode, Version};
     /// use twister_http::parser::HttpObjectParser;
     ///
-    /// const HTTP: &'static [u8] = 
+    /// const HTTP: &'static [u8] =
     ///     b"HTTP/1.1 200 OK\r\n\
     ///       Content-Type: text/plain\r\n\
     ///       Content-Length: 13\r\n\
@@ -305,10 +820,10 @@ impl<'a> HttpObjectParser<'a>
     ///
     /// let mut headers = [Header::default(); 16];
     /// let mut parser = HttpObjectParser::new(&mut headers);
-    /// let http_object = parser.parse::<Response>(HTTP).unwrap();
+    /// let http_object = parser.parse::<Response>(HTTP).unwrap().unwrap();
     ///
-    /// assert_eq!("HTTP/1.1", str::from_utf8(http_object.version).unwrap());
-    /// assert_eq!("200", str::from_utf8(http_object.status_code).unwrap());
+    /// assert_eq!(Version::Http11, http_object.version);
+    /// assert_eq!(StatusCode(200), http_object.status_code);
     /// assert_eq!("OK", str::from_utf8(http_object.status_text).unwrap());
     /// assert_eq!(2, http_object.headers.len());
     ///
@@ -326,7 +841,7 @@ impl<'a> HttpObjectParser<'a>
     /// use twister_http::{Header, HttpMethod, Request};
     /// use twister_http::parser::HttpObjectParser;
     ///
-    /// const HTTP: &'static [u8] = 
+    /// const HTTP: &'static [u8] =
     ///     b"POST /api/resource HTTP/1.1\r\n\
     ///       Host: docs.rs\r\n\
     ///       Content-Type: text/plain\r\n\
@@ -336,7 +851,7 @@ impl<'a> HttpObjectParser<'a>
     ///
     /// let mut headers = [Header::default(); 16];
     /// let mut parser = HttpObjectParser::new(&mut headers);
-    /// let http_object = parser.parse::<Request>(HTTP).unwrap();
+    /// let http_object = parser.parse::<Request>(HTTP).unwrap().unwrap();
     ///
     /// assert_eq!(HttpMethod::Post, http_object.method);
     /// assert_eq!("/api/resource", str::from_utf8(http_object.path).unwrap());
@@ -349,68 +864,313 @@ impl<'a> HttpObjectParser<'a>
     ///
     /// assert_eq!("Hello, World!", str::from_utf8(http_object.body).unwrap());
     /// ```
-    pub fn parse<T>(&mut self, data: &'a [u8]) -> Option<T>
-        where T: From<(&'a [u8], &'a [u8], &'a [u8], &'a [Header<'a>], &'a [u8])>
+    ///
+    /// Providing enough room for the headers
+    ///
+    /// ```
+    /// use twister_http::{Header, Request};
+    /// use twister_http::parser::{HttpObjectParser, ParseError};
+    ///
+    /// const HTTP: &'static [u8] =
+    ///     b"GET / HTTP/1.1\r\n\
+    ///       Host: docs.rs\r\n\
+    ///       Accept: */*\r\n\
+    ///       \r\n";
+    ///
+    /// let mut headers = [Header::default(); 1];
+    /// let count = match HttpObjectParser::new(&mut headers).parse::<Request>(HTTP) {
+    ///     Err(ParseError::TooManyHeaders(_, count)) => count,
+    ///     _ => unreachable!(),
+    /// };
+    ///
+    /// let mut headers = vec![Header::default(); count];
+    /// let http_object = HttpObjectParser::new(&mut headers)
+    ///     .parse::<Request>(HTTP)
+    ///     .unwrap()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(2, http_object.headers.len());
+    /// ```
+    ///
+    /// Handling an invalid object
+    ///
+    /// ```
+    /// use twister_http::{Header, Request};
+    /// use twister_http::parser::{HttpObjectParser, ParseError};
+    ///
+    /// let mut headers = [Header::default(); 16];
+    /// let mut parser = HttpObjectParser::new(&mut headers);
+    /// let result = parser.parse::<Request>(b"GET /index.html HTTP/1.1\r\nBad Header: 1\r\n");
+    ///
+    /// assert_eq!(Err(ParseError::InvalidHeaderName(29)), result.map(|_| ()));
+    /// ```
+    ///
+    /// [`Status::Partial`]: enum.Status.html#variant.Partial
+    /// [`ParseError`]: enum.ParseError.html
+    pub fn parse<T>(&mut self, data: &'a [u8]) -> Result<Status<T>, ParseError>
+        where T: HttpObject<'a>
     {
         use self::HttpObjectParser::*;
 
         loop {
             let next = match mem::replace(self, Done) {
-                NotStarted(headers) => Some(Protocol(headers, ProtocolParser::new(data))),
-                Protocol(headers, mut parser) => {
-                    parser.parse()
-                        .map(move |(part1, part2, part3, tail)| {
-                            Headers(part1, part2, part3, headers, HeaderParser::new(tail))
-                        })
+                NotStarted(headers, case) => Protocol(headers, case, ProtocolParser::Method(T::START_LINE, data)),
+                Protocol(headers, case, mut parser) => {
+                    match parser.parse_parts()? {
+                        Status::Complete((part1, part2, part3, tail)) => {
+                            check_method(T::START_LINE, case, part1)?;
+                            Headers(part1, part2, part3, headers, tail)
+                        },
+                        Status::Partial => return Ok(Status::Partial),
+                    }
                 },
-                Headers(part1, part2, part3, headers, mut parser) => {
+                Headers(part1, part2, part3, headers, mut tail) => {
                     let mut header_pos = 0;
-                    while let Some((Header(name, val), tail)) = parser.parse() {
+                    let mut overflow = None;
+                    loop {
+                        let offset = data.len() - tail.len();
+                        let parsed = HeaderParser::new(tail).parse()
+                            .map_err(|e| e.advance(offset))?;
 
-                        if name.len() == 0 {
-                            let parts = (part1, part2, part3, &headers[..header_pos], tail);
-                            return Some(parts.into());
-                        }
+                        match parsed {
+                            Status::Complete((Header(&[], _), body)) => {
+                                if let Some(overflow) = overflow {
+                                    return Err(ParseError::TooManyHeaders(overflow, header_pos));
+                                }
 
-                        if header_pos >= headers.len() {
-                            panic!("Not enough room for headers");
-                        }
+                                let parts = (part1, part2, part3, &headers[..header_pos], body);
+                                return T::try_from(parts).map(Status::Complete);
+                            },
+                            Status::Complete((header, remaining)) => {
+                                // Keep counting once `headers` is full so the
+                                // caller knows how much room is required
+                                if header_pos < headers.len() {
+                                    headers[header_pos] = header;
+                                }
+                                else if overflow.is_none() {
+                                    overflow = Some(offset);
+                                }
 
-                        headers[header_pos] = Header(name, val);
-                        parser = HeaderParser::new(tail);
-                        header_pos += 1;
+                                header_pos += 1;
+                                tail = remaining;
+                            },
+                            Status::Partial => return Ok(Status::Partial),
+                        }
                     }
-                    
-                    Some(Done)
                 },
                 Done => panic!("parse called on finished result"),
             };
 
-            if let Some(next) = next {
-                *self = next;
+            *self = next;
+        }
+    }
+
+    /// Parses a HTTP object that may arrive over several calls. Unlike
+    /// [`parse`], which examines all of `data` every time it's called,
+    /// `resume` records its progress in `token` when `data` ends before
+    /// the object's headers do. Calling `resume` again, with the same
+    /// token and the same data extended by newly arrived bytes, only
+    /// examines the new bytes until the headers are complete. The object
+    /// is then produced from the positions recorded in the token, and it
+    /// can be produced again by resuming with the same token - E.g. with
+    /// more room after [`ParseError::TooManyHeaders`].
+    ///
+    /// A new parser is required for each call, because the object it
+    /// produces borrows from `data`.
+    ///
+    /// # Return Value
+    /// The same as [`parse`]. Errors are reported as soon as the bytes
+    /// containing them arrive, rather than once the headers are complete.
+    ///
+    /// # Panics
+    /// This function will `panic` if the parser has already been used.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use twister_http::{Header, Request};
+    /// use twister_http::parser::{HttpObjectParser, ResumeToken, Status};
+    ///
+    /// const HTTP: &'static [u8] =
+    ///     b"GET /index.html HTTP/1.1\r\n\
+    ///       Host: docs.rs\r\n\
+    ///       \r\n";
+    ///
+    /// let mut token = ResumeToken::new();
+    /// let mut headers = [Header::default(); 16];
+    /// let status = HttpObjectParser::new(&mut headers)
+    ///     .resume::<Request>(&HTTP[..20], &mut token)
+    ///     .unwrap();
+    ///
+    /// assert_eq!(true, status.is_partial());
+    /// assert_eq!(20, token.offset());
+    ///
+    /// let mut headers = [Header::default(); 16];
+    /// let http_object = HttpObjectParser::new(&mut headers)
+    ///     .resume::<Request>(HTTP, &mut token)
+    ///     .unwrap()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(b"/index.html", http_object.path);
+    /// ```
+    ///
+    /// [`parse`]: enum.HttpObjectParser.html#method.parse
+    /// [`ParseError::TooManyHeaders`]: enum.ParseError.html#variant.TooManyHeaders
+    pub fn resume<T>(&mut self, data: &'a [u8], token: &mut ResumeToken) -> Result<Status<T>, ParseError>
+        where T: HttpObject<'a>
+    {
+        let case = match *self {
+            HttpObjectParser::NotStarted(_, case) => case,
+            _ => panic!("resume called on a used parser"),
+        };
+
+        if token.complete {
+            return self.complete(data, token);
+        }
+
+        loop {
+            let end = match data[token.scanned..].iter()
+                .position(|byte| *byte == b'\n' || is_forbidden(*byte))
+            {
+                Some(p) => token.scanned + p,
+                None => {
+                    token.scanned = data.len();
+                    return Ok(Status::Partial);
+                },
+            };
+
+            let first = token.line == 0;
+
+            if data[end] != b'\n' {
+                // The line is invalid, so validating what there is of it
+                // reports exactly where
+                validate_line(T::START_LINE, case, first, &data[token.line..])
+                    .map_err(|e| e.advance(token.line))?;
+                return Err(ParseError::InvalidHeaderValue(end));
+            }
+
+            let line = &data[token.line..end + 1];
+
+            if first {
+                let parsed = ProtocolParser::Method(T::START_LINE, line).parse_parts()?;
+                if let Status::Complete((part1, part2, part3, _)) = parsed {
+                    check_method(T::START_LINE, case, part1)?;
+                    token.start_line = [span(data, part1), span(data, part2), span(data, part3)];
+                }
+            }
+            else if line == b"\n" || line == b"\r\n" {
+                token.line = end + 1;
+                token.scanned = end + 1;
+                token.complete = true;
+                return self.complete(data, token);
             }
             else {
-                return None;
+                HeaderParser::new(line).parse().map_err(|e| e.advance(token.line))?;
+                token.headers += 1;
             }
+
+            token.line = end + 1;
+            token.scanned = end + 1;
         }
     }
+
+    /// Produces the object whose head `token` found to be complete. Its
+    /// lines were validated as they arrived, so the headers only need
+    /// splitting into names and values.
+    fn complete<T>(&mut self, data: &'a [u8], token: &ResumeToken) -> Result<Status<T>, ParseError>
+        where T: HttpObject<'a>
+    {
+        let headers = match mem::replace(self, HttpObjectParser::Done) {
+            HttpObjectParser::NotStarted(headers, _) => headers,
+            _ => panic!("resume called on a used parser"),
+        };
+
+        let mut line = token.start_line[2].1;
+        line += data[line..].iter().position(|byte| *byte == b'\n').unwrap() + 1;
+
+        for pos in 0..token.headers {
+            if pos == headers.len() {
+                return Err(ParseError::TooManyHeaders(line, token.headers));
+            }
+
+            let end = line + data[line..].iter().position(|byte| *byte == b'\n').unwrap();
+            let colon = line + data[line..end].iter().position(|byte| *byte == b':').unwrap();
+            let value = &data[colon + 1..end];
+            let value = value.strip_suffix(b"\r").unwrap_or(value);
+
+            headers[pos] = Header(&data[line..colon], trim_whitespace(value));
+            line = end + 1;
+        }
+
+        let part = |(start, end): (usize, usize)| &data[start..end];
+        let parts = (
+            part(token.start_line[0]),
+            part(token.start_line[1]),
+            part(token.start_line[2]),
+            &headers[..token.headers],
+            &data[token.scanned..],
+        );
+
+        T::try_from(parts).map(Status::Complete)
+    }
 }
 
 #[cfg(test)]
 mod protocol_parser_should {
     use super::*;
-    use std::str;
-    use HttpMethod;
 
     #[test]
     fn parse_protocol_header() {
         let proxy_connect = include_bytes!("../tests/proxy_connect.txt");
         let mut p = ProtocolParser::new(proxy_connect);
-        let (method, url, version, _) = p.parse().unwrap();
+        let (line, _) = p.parse().unwrap().unwrap();
 
-        assert_eq!(HttpMethod::Connect, method.into());
-        assert_eq!("docs.rs:443", str::from_utf8(url).unwrap());
-        assert_eq!("HTTP/1.1", str::from_utf8(version).unwrap());
+        assert_eq!(Line::Request { method: b"CONNECT", path: b"docs.rs:443", version: Version::Http11 }, line);
+    }
+
+    #[test]
+    fn parse_status_line() {
+        let mut p = ProtocolParser::new_status(b"HTTP/1.1 404 Not Found\r\n");
+        let (line, tail) = p.parse().unwrap().unwrap();
+
+        assert_eq!(Line::Status { version: Version::Http11, status_code: StatusCode(404), status_text: b"Not Found" }, line);
+        assert_eq!(0, tail.len());
+    }
+
+    #[test]
+    fn report_partial_line() {
+        assert_eq!(Ok(Status::Partial), ProtocolParser::new(b"GET /ind").parse());
+        assert_eq!(Ok(Status::Partial), ProtocolParser::new(b"GET / HTTP/1.1\r").parse());
+        assert_eq!(Ok(Status::Partial), ProtocolParser::new_status(b"HTTP/1.1 20").parse());
+    }
+
+    #[test]
+    fn reject_invalid_lines() {
+        assert_eq!(Err(ParseError::InvalidMethod(1)), ProtocolParser::new(b"G\0T / HTTP/1.1\r\n").parse());
+        assert_eq!(Err(ParseError::InvalidMethod(0)), ProtocolParser::new(b" GET / HTTP/1.1\r\n").parse());
+        assert_eq!(Err(ParseError::InvalidPath(4)), ProtocolParser::new(b"GET  / HTTP/1.1\r\n").parse());
+        assert_eq!(Err(ParseError::InvalidVersion(5)), ProtocolParser::new(b"GET /\r\n").parse());
+        assert_eq!(Err(ParseError::InvalidVersion(11)), ProtocolParser::new(b"GET / HTTP/x.1\r\n").parse());
+        assert_eq!(Err(ParseError::InvalidNewline(15)), ProtocolParser::new(b"GET / HTTP/1.1\rX").parse());
+        assert_eq!(Err(ParseError::InvalidVersion(3)), ProtocolParser::new_status(b"HTTX/1.1 200 OK\r\n").parse());
+        assert_eq!(Err(ParseError::InvalidStatus(11)), ProtocolParser::new_status(b"HTTP/1.1 20 OK\r\n").parse());
+    }
+
+    #[test]
+    fn offset_errors_from_the_start_of_the_line() {
+        let request = Request::try_from((&b"GET"[..], &b"/"[..], &b"HTTP/2.0"[..], &[][..], &[][..]));
+        assert_eq!(Err(ParseError::InvalidVersion(11)), request);
+
+        let response = Response::try_from((&b"HTTP/1.1"[..], &b"2x0"[..], &b"OK"[..], &[][..], &[][..]));
+        assert_eq!(Err(ParseError::InvalidStatus(10)), response);
+    }
+
+    #[test]
+    fn reject_unknown_versions() {
+        assert!(ProtocolParser::new(b"GET / HTTP/1.0\r\n").parse().is_ok());
+        assert_eq!(Err(ParseError::InvalidVersion(11)), ProtocolParser::new(b"GET / HTTP/2.0\r\n").parse());
+        assert_eq!(Err(ParseError::InvalidVersion(13)), ProtocolParser::new(b"GET / HTTP/1.2\r\n").parse());
+        assert_eq!(Err(ParseError::InvalidVersion(5)), ProtocolParser::new_status(b"HTTP/0.9 200 OK\r\n").parse());
     }
 }
 
@@ -418,15 +1178,19 @@ mod protocol_parser_should {
 mod header_parser_should {
     use super::*;
     use std::str;
-   
+
+    fn headers(data: &[u8]) -> &[u8] {
+        let (.., tail) = ProtocolParser::new(data).parse().unwrap().unwrap();
+        tail
+    }
+
     #[test]
     fn parse_multiple_headers() {
         let proxy_connect = include_bytes!("../tests/proxy_connect.txt");
-        let (_, headers) = split_as_first_newline(proxy_connect).unwrap();
-        let headers = skip_newline(headers);
+        let headers = headers(proxy_connect);
 
         let mut p = HeaderParser::new(headers);
-        let (Header(name, val), tail) = p.parse().unwrap();
+        let (Header(name, val), tail) = p.parse().unwrap().unwrap();
 
         assert_eq!("User-Agent", str::from_utf8(name).unwrap());
         assert_eq!(
@@ -434,27 +1198,27 @@ mod header_parser_should {
             Gecko/20100101 Firefox/59.0", str::from_utf8(val).unwrap());
 
         let mut p = HeaderParser::new(tail);
-        let (Header(name, val), tail) = p.parse().unwrap();
+        let (Header(name, val), tail) = p.parse().unwrap().unwrap();
 
         assert_eq!("Proxy-Connection", str::from_utf8(name).unwrap());
         assert_eq!(
             "keep-alive", str::from_utf8(val).unwrap());
 
         let mut p = HeaderParser::new(tail);
-        let (Header(name, val), tail) = p.parse().unwrap();
+        let (Header(name, val), tail) = p.parse().unwrap().unwrap();
 
         assert_eq!("Connection", str::from_utf8(name).unwrap());
         assert_eq!(
             "keep-alive", str::from_utf8(val).unwrap());
 
         let mut p = HeaderParser::new(tail);
-        let (Header(name, val), tail) = p.parse().unwrap();
+        let (Header(name, val), tail) = p.parse().unwrap().unwrap();
 
         assert_eq!("Host", str::from_utf8(name).unwrap());
         assert_eq!(
             "docs.rs:443", str::from_utf8(val).unwrap());
 
-        let (Header(_, _), tail) = HeaderParser::new(tail).parse().unwrap();
+        let (Header(_, _), tail) = HeaderParser::new(tail).parse().unwrap().unwrap();
         assert_eq!("Hello, World!\r\n", str::from_utf8(tail).unwrap());
 
     }
@@ -462,44 +1226,265 @@ mod header_parser_should {
     #[test]
     fn parse_a_header() {
         let proxy_connect = include_bytes!("../tests/proxy_connect.txt");
-        let (_, headers) = split_as_first_newline(proxy_connect).unwrap();
-        let headers = skip_newline(headers);
+        let headers = headers(proxy_connect);
 
         let mut p = HeaderParser::new(headers);
-        let (Header(name, val), _) = p.parse().unwrap();
+        let (Header(name, val), _) = p.parse().unwrap().unwrap();
 
         assert_eq!("User-Agent", str::from_utf8(name).unwrap());
         assert_eq!(
             "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:59.0) \
             Gecko/20100101 Firefox/59.0", str::from_utf8(val).unwrap());
     }
+
+    #[test]
+    fn trim_value_whitespace() {
+        let (Header(_, val), _) = HeaderParser::new(b"Host: \t docs.rs \r\n").parse().unwrap().unwrap();
+
+        assert_eq!(b"docs.rs", val);
+    }
+
+    #[test]
+    fn reject_invalid_headers() {
+        assert_eq!(Err(ParseError::InvalidHeaderName(4)), HeaderParser::new(b"Host : docs.rs\r\n").parse());
+        assert_eq!(Err(ParseError::InvalidHeaderName(0)), HeaderParser::new(b" folded\r\n").parse());
+        assert_eq!(Err(ParseError::InvalidHeaderName(0)), HeaderParser::new(b": empty\r\n").parse());
+        assert_eq!(Err(ParseError::InvalidHeaderValue(9)), HeaderParser::new(b"Host: doc\0s.rs\r\n").parse());
+        assert_eq!(Err(ParseError::InvalidNewline(14)), HeaderParser::new(b"Host: docs.rs\r\r\n").parse());
+    }
 }
 
 #[cfg(test)]
 mod request_parser_should {
     use super::*;
     use std::str;
-    use {Request, HttpMethod};
+    use {Request, Response, HttpMethod, MethodCase, StatusCode, Version};
 
     #[test]
     fn parse_a_request() {
-        use std::mem;
+        let proxy_connect = include_bytes!("../tests/proxy_connect.txt");
+        let mut headers = vec![Header::default(); 16];
+        let r = HttpObjectParser::new(&mut headers)
+            .parse::<Request>(proxy_connect)
+            .unwrap()
+            .unwrap();
 
+        assert_eq!(HttpMethod::Connect, r.method);
+        assert_eq!("docs.rs:443", str::from_utf8(r.path).unwrap());
+        assert_eq!(Version::Http11, r.version);
+        assert_eq!(4, r.headers.len());
+        assert_eq!("Hello, World!\r\n", str::from_utf8(r.body).unwrap());
+    }
+
+    #[test]
+    fn parse_a_request_after_running_out_of_headers() {
         let proxy_connect = include_bytes!("../tests/proxy_connect.txt");
-        let mut header_size = 16;
-        loop {
-            let mut headers = vec![Header::default(); header_size];
-            if let Some(r) = HttpObjectParser::new(&mut headers).parse::<Request>(proxy_connect) {
+        let mut headers = vec![Header::default(); 1];
+        let count = match HttpObjectParser::new(&mut headers).parse::<Request>(proxy_connect) {
+            Err(ParseError::TooManyHeaders(_, count)) => count,
+            other => panic!("Unexpected result: {:?}", other.map(|_| ())),
+        };
+
+        assert_eq!(4, count);
 
+        let mut headers = vec![Header::default(); count];
+        let r = HttpObjectParser::new(&mut headers)
+            .parse::<Request>(proxy_connect)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(4, r.headers.len());
+        assert_eq!(Header(b"Host", b"docs.rs:443"), r.headers[3]);
+    }
+
+    #[test]
+    fn report_partial_request() {
+        let proxy_connect = include_bytes!("../tests/proxy_connect.txt");
+
+        for len in 0..proxy_connect.len() - "Hello, World!\r\n".len() {
+            let mut headers = [Header::default(); 16];
+            let result = HttpObjectParser::new(&mut headers)
+                .parse::<Request>(&proxy_connect[..len]);
+
+            assert_eq!(Ok(true), result.map(|s| s.is_partial()), "at length {}", len);
+        }
+    }
+
+    #[test]
+    fn resume_a_request() {
+        let proxy_connect = include_bytes!("../tests/proxy_connect.txt");
+        let mut token = ResumeToken::new();
+
+        for len in 0..proxy_connect.len() {
+            let mut headers = [Header::default(); 16];
+            let status = HttpObjectParser::new(&mut headers)
+                .resume::<Request>(&proxy_connect[..len], &mut token)
+                .unwrap();
+
+            if let Status::Complete(r) = status {
                 assert_eq!(HttpMethod::Connect, r.method);
-                assert_eq!("docs.rs:443", str::from_utf8(r.path).unwrap());
                 assert_eq!(4, r.headers.len());
-                assert_eq!("Hello, World!\r\n", str::from_utf8(r.body).unwrap());
-                break;
+                assert_eq!(b"", r.body);
+                return;
             }
 
-            header_size *= 2;
+            assert_eq!(len, token.offset());
+        }
+
+        panic!("Request never completed");
+    }
+
+    #[test]
+    fn resume_a_complete_request_with_more_room_for_headers() {
+        let request = b"HEAD / HTTP/1.0\nHost: docs.rs\r\nAccept:  */* \r\nX-Empty:\n\r\nbody";
+        let mut token = ResumeToken::new();
+
+        let mut headers = [Header::default(); 2];
+        let status = HttpObjectParser::new(&mut headers)
+            .resume::<Request>(request, &mut token);
+
+        assert_eq!(Err(ParseError::TooManyHeaders(46, 3)), status.map(|_| ()));
+
+        let mut headers = [Header::default(); 3];
+        let r = HttpObjectParser::new(&mut headers)
+            .resume::<Request>(request, &mut token)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(HttpMethod::Head, r.method);
+        assert_eq!(b"/", r.path);
+        assert_eq!(Version::Http10, r.version);
+        assert_eq!(&[Header(b"Host", b"docs.rs"), Header(b"Accept", b"*/*"), Header(b"X-Empty", b"")], r.headers);
+        assert_eq!(b"body", r.body);
+    }
+
+    #[test]
+    fn resume_a_response() {
+        let response = b"HTTP/1.1 204\r\nVia: 1.1 gw\r\n\r\n";
+        let mut token = ResumeToken::new();
+
+        let mut headers = [Header::default(); 1];
+        let status = HttpObjectParser::new(&mut headers)
+            .resume::<Response>(&response[..14], &mut token);
+        assert_eq!(Ok(true), status.map(|s| s.is_partial()));
+
+        let mut headers = [Header::default(); 1];
+        let r = HttpObjectParser::new(&mut headers)
+            .resume::<Response>(response, &mut token)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(StatusCode(204), r.status_code);
+        assert_eq!(b"", r.status_text);
+        assert_eq!(&[Header(b"Via", b"1.1 gw")], r.headers);
+    }
+
+    #[test]
+    fn report_errors_when_resuming() {
+        let request = b"GET / HTTP/1.1\r\nHost docs.rs\r\n";
+        let mut token = ResumeToken::new();
+
+        let mut headers = [Header::default(); 16];
+        let status = HttpObjectParser::new(&mut headers)
+            .resume::<Request>(&request[..20], &mut token);
+
+        assert_eq!(Ok(true), status.map(|s| s.is_partial()));
+
+        let mut headers = [Header::default(); 16];
+        let status = HttpObjectParser::new(&mut headers)
+            .resume::<Request>(request, &mut token);
+
+        assert_eq!(Err(ParseError::InvalidHeaderName(20)), status.map(|_| ()));
+    }
+
+    #[test]
+    fn report_control_characters_before_line_ends() {
+        let mut token = ResumeToken::new();
+        let mut headers = [Header::default(); 16];
+        let status = HttpObjectParser::new(&mut headers)
+            .resume::<Request>(b"GET / HTTP/1.1\r\nHost: docs\0", &mut token);
+
+        assert_eq!(Err(ParseError::InvalidHeaderValue(26)), status.map(|_| ()));
+    }
+
+    #[test]
+    fn report_error_offset() {
+        let mut headers = [Header::default(); 16];
+        let result = HttpObjectParser::new(&mut headers)
+            .parse::<Request>(b"GET / HTTP/1.1\r\nHost: docs.rs\r\nX\x01: 1\r\n\r\n");
+
+        assert_eq!(Err(ParseError::InvalidHeaderName(32)), result.map(|_| ()));
+    }
+
+    #[test]
+    fn report_too_many_headers() {
+        let mut headers = [Header::default(); 1];
+        let result = HttpObjectParser::new(&mut headers)
+            .parse::<Request>(b"GET / HTTP/1.1\r\nHost: docs.rs\r\nAccept: */*\r\nUser-Agent: test\r\n\r\n");
+
+        assert_eq!(Err(ParseError::TooManyHeaders(31, 3)), result.map(|_| ()));
+    }
+
+    #[test]
+    fn report_partial_request_when_out_of_headers() {
+        let mut headers = [Header::default(); 1];
+        let result = HttpObjectParser::new(&mut headers)
+            .parse::<Request>(b"GET / HTTP/1.1\r\nHost: docs.rs\r\nAccept: */*\r\n");
+
+        assert_eq!(Ok(true), result.map(|s| s.is_partial()));
+    }
+
+    #[test]
+    fn parse_every_standard_method() {
+        for name in &["CONNECT", "GET", "POST", "PUT", "DELETE", "PATCH", "HEAD", "OPTIONS", "TRACE"] {
+            let request = format!("{} / HTTP/1.1\r\n\r\n", name);
+            let mut headers = [Header::default(); 1];
+            let r = HttpObjectParser::new(&mut headers)
+                .method_case(MethodCase::Sensitive)
+                .parse::<Request>(request.as_bytes())
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(name.as_bytes(), r.method.as_bytes());
+            assert_ne!(HttpMethod::Other(name.as_bytes()), r.method);
         }
+    }
+
+    #[test]
+    fn reject_methods_in_the_wrong_case_when_case_sensitive() {
+        let mut headers = [Header::default(); 1];
+        let r = HttpObjectParser::new(&mut headers)
+            .parse::<Request>(b"options * HTTP/1.1\r\n\r\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(HttpMethod::Options, r.method);
+
+        let mut headers = [Header::default(); 1];
+        let result = HttpObjectParser::new(&mut headers)
+            .method_case(MethodCase::Sensitive)
+            .parse::<Request>(b"Get / HTTP/1.1\r\n\r\n");
+        assert_eq!(Err(ParseError::InvalidMethod(0)), result.map(|_| ()));
+
+        let mut token = ResumeToken::new();
+        let mut headers = [Header::default(); 1];
+        let result = HttpObjectParser::new(&mut headers)
+            .method_case(MethodCase::Sensitive)
+            .resume::<Request>(b"trace / HTTP/1.1\r\nHost: docs.rs", &mut token);
+        assert_eq!(Err(ParseError::InvalidMethod(0)), result.map(|_| ()));
+    }
+
+    #[test]
+    fn accept_extension_methods_made_of_tokens() {
+        let mut headers = [Header::default(); 1];
+        let r = HttpObjectParser::new(&mut headers)
+            .method_case(MethodCase::Sensitive)
+            .parse::<Request>(b"M-SEARCH * HTTP/1.1\r\n\r\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(HttpMethod::Other(b"M-SEARCH"), r.method);
 
+        let mut headers = [Header::default(); 1];
+        let result = HttpObjectParser::new(&mut headers).parse::<Request>(b"M(SEARCH * HTTP/1.1\r\n\r\n");
+        assert_eq!(Err(ParseError::InvalidMethod(1)), result.map(|_| ()));
     }
 }
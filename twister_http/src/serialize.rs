@@ -0,0 +1,261 @@
+use core::fmt;
+use core::str;
+use {Header, Request, Response, StatusCode};
+
+/// A destination for serialized HTTP objects
+pub trait Sink {
+    type Error;
+
+    /// Writes all of `bytes`, or fails
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// The error produced when a [`SliceSink`] runs out of room
+///
+/// [`SliceSink`]: struct.SliceSink.html
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct BufferTooSmall;
+
+impl fmt::Display for BufferTooSmall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "buffer too small")
+    }
+}
+
+/// A [`Sink`] that fills a caller-supplied buffer
+///
+/// [`Sink`]: trait.Sink.html
+pub struct SliceSink<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> SliceSink<'a> {
+    pub fn new(buffer: &'a mut [u8]) -> SliceSink<'a> {
+        SliceSink {
+            buffer: buffer,
+            len: 0,
+        }
+    }
+
+    /// The bytes written so far
+    pub fn written(&self) -> &[u8] {
+        &self.buffer[..self.len]
+    }
+}
+
+impl<'a> Sink for SliceSink<'a> {
+    type Error = BufferTooSmall;
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), BufferTooSmall> {
+        let end = self.len + bytes.len();
+        if end > self.buffer.len() {
+            return Err(BufferTooSmall);
+        }
+
+        self.buffer[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// A [`Sink`] that writes to a `core::fmt::Write`, such as a `String`.
+/// Objects containing bytes that aren't UTF-8 fail with `fmt::Error`.
+///
+/// [`Sink`]: trait.Sink.html
+pub struct FmtSink<W>(pub W);
+
+impl<W: fmt::Write> Sink for FmtSink<W> {
+    type Error = fmt::Error;
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), fmt::Error> {
+        self.0.write_str(str::from_utf8(bytes).map_err(|_| fmt::Error)?)
+    }
+}
+
+/// Counts the bytes written to it
+struct Counter(usize);
+
+impl Sink for Counter {
+    type Error = ();
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        self.0 += bytes.len();
+        Ok(())
+    }
+}
+
+/// Formats `status` as decimal digits in `buffer`, returning them
+pub(crate) fn status_digits(status: StatusCode, buffer: &mut [u8; 5]) -> &[u8] {
+    let mut code = status.0;
+    let mut start = buffer.len();
+    loop {
+        start -= 1;
+        buffer[start] = b'0' + (code % 10) as u8;
+        code /= 10;
+        if code == 0 {
+            break;
+        }
+    }
+
+    &buffer[start..]
+}
+
+pub(crate) fn serialize_message<'h, S, H>(start: [&[u8]; 3], headers: H, body: &[u8], sink: &mut S) -> Result<(), S::Error>
+    where S: Sink,
+          H: IntoIterator<Item = Header<'h>>,
+{
+    sink.write_bytes(start[0])?;
+    sink.write_bytes(b" ")?;
+    sink.write_bytes(start[1])?;
+    sink.write_bytes(b" ")?;
+    sink.write_bytes(start[2])?;
+    sink.write_bytes(b"\r\n")?;
+
+    for Header(name, value) in headers {
+        sink.write_bytes(name)?;
+        sink.write_bytes(b": ")?;
+        sink.write_bytes(value)?;
+        sink.write_bytes(b"\r\n")?;
+    }
+
+    sink.write_bytes(b"\r\n")?;
+    sink.write_bytes(body)
+}
+
+/// HTTP objects that can be written in their wire format. The object's
+/// fields are written as they are, so its headers must already describe
+/// how its body is framed.
+pub trait Serialize {
+    /// Writes the object to `sink`
+    fn serialize<S: Sink>(&self, sink: &mut S) -> Result<(), S::Error>;
+
+    /// The number of bytes [`serialize`] writes
+    ///
+    /// [`serialize`]: #tymethod.serialize
+    fn serialized_len(&self) -> usize {
+        let mut counter = Counter(0);
+        let _ = self.serialize(&mut counter);
+        counter.0
+    }
+
+    /// Writes the object to the start of `buffer`.
+    ///
+    /// # Return Value
+    /// The number of bytes written, or [`BufferTooSmall`] if the object
+    /// doesn't fit. See [`serialized_len`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use twister_http::{Header, Response, StatusCode, Version};
+    /// use twister_http::serialize::Serialize;
+    ///
+    /// let response = Response {
+    ///     version: Version::Http11,
+    ///     status_code: StatusCode(200),
+    ///     status_text: b"OK",
+    ///     headers: &[Header(b"Content-Length", b"2")],
+    ///     body: b"Hi",
+    /// };
+    ///
+    /// let mut buffer = [0; 64];
+    /// let len = response.write_to(&mut buffer).unwrap();
+    /// assert_eq!(&b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nHi"[..], &buffer[..len]);
+    /// ```
+    ///
+    /// [`BufferTooSmall`]: struct.BufferTooSmall.html
+    /// [`serialized_len`]: #method.serialized_len
+    fn write_to(&self, buffer: &mut [u8]) -> Result<usize, BufferTooSmall> {
+        let mut sink = SliceSink::new(buffer);
+        self.serialize(&mut sink)?;
+        Ok(sink.len)
+    }
+}
+
+impl<'a> Serialize for Request<'a> {
+    fn serialize<S: Sink>(&self, sink: &mut S) -> Result<(), S::Error> {
+        serialize_message([self.method.as_bytes(), self.path, self.version.as_bytes()], self.headers.iter().cloned(), self.body, sink)
+    }
+}
+
+impl<'a> Serialize for Response<'a> {
+    fn serialize<S: Sink>(&self, sink: &mut S) -> Result<(), S::Error> {
+        let mut digits = [0; 5];
+        let start = [self.version.as_bytes(), status_digits(self.status_code, &mut digits), self.status_text];
+        serialize_message(start, self.headers.iter().cloned(), self.body, sink)
+    }
+}
+
+#[cfg(test)]
+mod serialize_should {
+    use super::*;
+    use parser::HttpObjectParser;
+    use {HttpMethod, Version};
+
+    #[test]
+    fn round_trip_requests() {
+        let request = Request {
+            method: HttpMethod::Post,
+            path: b"/submit?a=1",
+            version: Version::Http11,
+            headers: &[Header(b"Host", b"example.com"), Header(b"Content-Length", b"5")],
+            body: b"hello",
+        };
+
+        let mut buffer = [0; 128];
+        let len = request.write_to(&mut buffer).unwrap();
+        assert_eq!(request.serialized_len(), len);
+
+        let mut headers = [Header::default(); 4];
+        let parsed = HttpObjectParser::new(&mut headers).parse::<Request>(&buffer[..len]).unwrap().unwrap();
+        assert_eq!(request, parsed);
+    }
+
+    #[test]
+    fn round_trip_responses() {
+        let response = Response {
+            version: Version::Http10,
+            status_code: StatusCode(404),
+            status_text: b"Not Found",
+            headers: &[],
+            body: b"",
+        };
+
+        let mut sink = FmtSink(String::new());
+        response.serialize(&mut sink).unwrap();
+        assert_eq!("HTTP/1.0 404 Not Found\r\n\r\n", sink.0);
+
+        let mut headers = [Header::default(); 4];
+        let parsed = HttpObjectParser::new(&mut headers).parse::<Response>(sink.0.as_bytes()).unwrap().unwrap();
+        assert_eq!(response, parsed);
+    }
+
+    #[test]
+    fn report_buffers_that_are_too_small() {
+        let response = Response {
+            version: Version::Http11,
+            status_code: StatusCode(200),
+            status_text: b"OK",
+            headers: &[],
+            body: b"",
+        };
+
+        let mut buffer = [0; 18];
+        assert_eq!(Err(BufferTooSmall), response.write_to(&mut buffer));
+        assert_eq!(Ok(19), response.write_to(&mut [0; 19]));
+    }
+
+    #[test]
+    fn reject_bytes_a_fmt_sink_cant_hold() {
+        let response = Response {
+            version: Version::Http11,
+            status_code: StatusCode(200),
+            status_text: b"\xff",
+            headers: &[],
+            body: b"",
+        };
+
+        assert_eq!(Err(fmt::Error), response.serialize(&mut FmtSink(String::new())));
+    }
+}
@@ -0,0 +1,340 @@
+use core::fmt;
+use core::net::Ipv6Addr;
+use core::str;
+use HttpMethod;
+use parser::ParseError;
+
+/// The host of an [`Authority`]
+///
+/// [`Authority`]: struct.Authority.html
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Host<'a> {
+    /// A registered name or an IPv4 address - E.g. `docs.rs`
+    Name(&'a [u8]),
+    /// An IPv6 address, without the brackets that surround it in a
+    /// target - E.g. `::1` from `[::1]:443`
+    Ipv6(&'a [u8]),
+}
+
+/// The `host[:port]` part of a request target
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Authority<'a> {
+    pub host: Host<'a>,
+    /// `None` if the port is absent or empty
+    pub port: Option<u16>,
+}
+
+/// A request target, in one of the four forms of RFC 9112, section 3.2
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RequestTarget<'a> {
+    /// `/index.html?q=1`
+    Origin {
+        path: &'a [u8],
+        query: Option<&'a [u8]>,
+    },
+    /// `http://docs.rs/index.html?q=1`, as sent to a proxy. The path is
+    /// empty if the target doesn't have one.
+    Absolute {
+        scheme: &'a [u8],
+        authority: Authority<'a>,
+        path: &'a [u8],
+        query: Option<&'a [u8]>,
+    },
+    /// `docs.rs:443`, as sent with `CONNECT`. The port is always present.
+    Authority(Authority<'a>),
+    /// `*`, as sent with `OPTIONS`
+    Asterisk,
+}
+
+fn is_unreserved(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"-._~".contains(&byte)
+}
+
+fn is_sub_delim(byte: u8) -> bool {
+    b"!$&'()*+,;=".contains(&byte)
+}
+
+fn is_pchar(byte: u8) -> bool {
+    is_unreserved(byte) || is_sub_delim(byte) || byte == b':' || byte == b'@'
+}
+
+/// Returns the offset of the first byte in `data` that isn't allowed by
+/// `allowed` or part of a percent-encoded octet
+fn find_invalid<F: Fn(u8) -> bool>(data: &[u8], allowed: F) -> Option<usize> {
+    let mut i = 0;
+    while i < data.len() {
+        if data[i] == b'%' {
+            if data.len() < i + 3 || !data[i + 1].is_ascii_hexdigit() || !data[i + 2].is_ascii_hexdigit() {
+                return Some(i);
+            }
+            i += 3;
+        }
+        else if allowed(data[i]) {
+            i += 1;
+        }
+        else {
+            return Some(i);
+        }
+    }
+
+    None
+}
+
+fn check(data: &[u8], offset: usize, allowed: fn(u8) -> bool) -> Result<(), ParseError> {
+    match find_invalid(data, allowed) {
+        Some(p) => Err(ParseError::InvalidPath(offset + p)),
+        None => Ok(()),
+    }
+}
+
+/// Splits `data`, which starts at `offset` in the target, into a path
+/// and an optional query, checking both
+fn path_and_query(data: &[u8], offset: usize) -> Result<(&[u8], Option<&[u8]>), ParseError> {
+    let (path, query) = match data.iter().position(|byte| *byte == b'?') {
+        Some(p) => (&data[..p], Some(&data[p + 1..])),
+        None => (data, None),
+    };
+
+    check(path, offset, |byte| is_pchar(byte) || byte == b'/')?;
+    if let Some(query) = query {
+        check(query, offset + path.len() + 1, |byte| is_pchar(byte) || byte == b'/' || byte == b'?')?;
+    }
+
+    Ok((path, query))
+}
+
+impl<'a> Authority<'a> {
+    /// Parses `host[:port]`. `offset` is where `data` starts in the
+    /// target, for error reporting.
+    pub(crate) fn parse(data: &'a [u8], offset: usize) -> Result<Authority<'a>, ParseError> {
+        let error = |p: usize| ParseError::InvalidPath(offset + p);
+
+        let (host, rest) = if data.first() == Some(&b'[') {
+            let end = data.iter().position(|byte| *byte == b']').ok_or(error(data.len()))?;
+            let address = &data[1..end];
+            str::from_utf8(address).ok()
+                .and_then(|address| address.parse::<Ipv6Addr>().ok())
+                .ok_or(error(1))?;
+
+            (Host::Ipv6(address), &data[end + 1..])
+        }
+        else {
+            let end = data.iter().position(|byte| *byte == b':').unwrap_or(data.len());
+            let name = &data[..end];
+            if name.is_empty() {
+                return Err(error(0));
+            }
+            check(name, offset, |byte| is_unreserved(byte) || is_sub_delim(byte))?;
+
+            (Host::Name(name), &data[end..])
+        };
+
+        let port_offset = data.len() - rest.len();
+        let port = match rest.split_first() {
+            None => None,
+            Some((&b':', [])) => None,
+            Some((&b':', port)) => {
+                if let Some(p) = port.iter().position(|byte| !byte.is_ascii_digit()) {
+                    return Err(error(port_offset + 1 + p));
+                }
+                let port = str::from_utf8(port).ok()
+                    .and_then(|port| port.parse::<u16>().ok())
+                    .ok_or(error(port_offset + 1))?;
+                Some(port)
+            },
+            Some(_) => return Err(error(port_offset)),
+        };
+
+        Ok(Authority {
+            host: host,
+            port: port,
+        })
+    }
+}
+
+/// Writes `host[:port]`, with IPv6 hosts in brackets - E.g. `[::1]:443`
+impl<'a> fmt::Display for Authority<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Hosts are checked to be ASCII when they're parsed
+        match self.host {
+            Host::Name(name) => f.write_str(str::from_utf8(name).map_err(|_| fmt::Error)?)?,
+            Host::Ipv6(address) => write!(f, "[{}]", str::from_utf8(address).map_err(|_| fmt::Error)?)?,
+        }
+
+        match self.port {
+            Some(port) => write!(f, ":{}", port),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a> RequestTarget<'a> {
+    /// Parses and classifies the target of a request with `method`.
+    /// `CONNECT` requires authority-form, with a port. Asterisk-form is
+    /// only allowed with `OPTIONS`. Other methods use origin-form or
+    /// absolute-form, and absolute-form targets must have an authority
+    /// without user information.
+    ///
+    /// # Return Value
+    /// The target, or [`ParseError::InvalidPath`] with the offset of the
+    /// first invalid byte.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use twister_http::HttpMethod;
+    /// use twister_http::target::{Authority, Host, RequestTarget};
+    ///
+    /// let target = RequestTarget::parse(b"[::1]:443", &HttpMethod::Connect).unwrap();
+    /// assert_eq!(RequestTarget::Authority(Authority { host: Host::Ipv6(b"::1"), port: Some(443) }), target);
+    ///
+    /// let target = RequestTarget::parse(b"/search?q=twister", &HttpMethod::Get).unwrap();
+    /// assert_eq!(RequestTarget::Origin { path: b"/search", query: Some(b"q=twister") }, target);
+    /// ```
+    ///
+    /// [`ParseError::InvalidPath`]: ../parser/enum.ParseError.html#variant.InvalidPath
+    pub fn parse(target: &'a [u8], method: &HttpMethod) -> Result<RequestTarget<'a>, ParseError> {
+        if target.is_empty() {
+            return Err(ParseError::InvalidPath(0));
+        }
+
+        match *method {
+            HttpMethod::Connect => {
+                let authority = Authority::parse(target, 0)?;
+                if authority.port.is_none() {
+                    return Err(ParseError::InvalidPath(target.len()));
+                }
+                return Ok(RequestTarget::Authority(authority));
+            },
+            HttpMethod::Options if target == b"*" => return Ok(RequestTarget::Asterisk),
+            _ => {},
+        }
+
+        if target[0] == b'/' {
+            let (path, query) = path_and_query(target, 0)?;
+            return Ok(RequestTarget::Origin {
+                path: path,
+                query: query,
+            });
+        }
+
+        let scheme_end = target.iter().position(|byte| *byte == b':').unwrap_or(target.len());
+        let scheme = &target[..scheme_end];
+        match scheme.iter().position(|byte| !(byte.is_ascii_alphanumeric() || b"+-.".contains(byte))) {
+            Some(p) => return Err(ParseError::InvalidPath(p)),
+            None if scheme.is_empty() || !scheme[0].is_ascii_alphabetic() => return Err(ParseError::InvalidPath(0)),
+            None => {},
+        }
+
+        let authority_start = scheme_end + 3;
+        if !target[scheme_end..].starts_with(b"://") {
+            return Err(ParseError::InvalidPath(scheme_end));
+        }
+
+        let rest = &target[authority_start..];
+        let authority_end = rest.iter()
+            .position(|byte| *byte == b'/' || *byte == b'?')
+            .unwrap_or(rest.len());
+        let authority = Authority::parse(&rest[..authority_end], authority_start)?;
+        let (path, query) = path_and_query(&rest[authority_end..], authority_start + authority_end)?;
+
+        Ok(RequestTarget::Absolute {
+            scheme: scheme,
+            authority: authority,
+            path: path,
+            query: query,
+        })
+    }
+}
+
+#[cfg(test)]
+mod target_should {
+    use super::*;
+
+    fn parse(target: &[u8]) -> Result<RequestTarget<'_>, ParseError> {
+        RequestTarget::parse(target, &HttpMethod::Get)
+    }
+
+    fn authority(host: Host, port: Option<u16>) -> Authority {
+        Authority {
+            host: host,
+            port: port,
+        }
+    }
+
+    #[test]
+    fn parse_origin_form() {
+        assert_eq!(Ok(RequestTarget::Origin { path: b"/", query: None }), parse(b"/"));
+        assert_eq!(Ok(RequestTarget::Origin { path: b"/a%20b/c", query: Some(b"x=1&y=/?") }), parse(b"/a%20b/c?x=1&y=/?"));
+    }
+
+    #[test]
+    fn parse_absolute_form() {
+        assert_eq!(Ok(RequestTarget::Absolute {
+                       scheme: b"http",
+                       authority: authority(Host::Name(b"example.com"), Some(8080)),
+                       path: b"/index.html",
+                       query: Some(b"q=1"),
+                   }),
+                   parse(b"http://example.com:8080/index.html?q=1"));
+
+        assert_eq!(Ok(RequestTarget::Absolute {
+                       scheme: b"HTTPS",
+                       authority: authority(Host::Ipv6(b"fe80::1"), None),
+                       path: b"",
+                       query: None,
+                   }),
+                   parse(b"HTTPS://[fe80::1]:"));
+    }
+
+    #[test]
+    fn parse_authority_form() {
+        let connect = |target| RequestTarget::parse(target, &HttpMethod::Connect);
+
+        assert_eq!(Ok(RequestTarget::Authority(authority(Host::Name(b"docs.rs"), Some(443)))), connect(b"docs.rs:443"));
+        assert_eq!(Ok(RequestTarget::Authority(authority(Host::Name(b"10.0.0.1"), Some(22)))), connect(b"10.0.0.1:22"));
+        assert_eq!(Ok(RequestTarget::Authority(authority(Host::Ipv6(b"::1"), Some(443)))), connect(b"[::1]:443"));
+    }
+
+    #[test]
+    fn display_authorities() {
+        let connect = |target| match RequestTarget::parse(target, &HttpMethod::Connect) {
+            Ok(RequestTarget::Authority(authority)) => format!("{}", authority),
+            other => panic!("Expected authority-form, got {:?}", other),
+        };
+
+        assert_eq!("docs.rs:443", connect(b"docs.rs:443"));
+        assert_eq!("[::1]:8443", connect(b"[::1]:08443"));
+        assert_eq!("example.com", format!("{}", authority(Host::Name(b"example.com"), None)));
+    }
+
+    #[test]
+    fn parse_asterisk_form() {
+        assert_eq!(Ok(RequestTarget::Asterisk), RequestTarget::parse(b"*", &HttpMethod::Options));
+        assert_eq!(Err(ParseError::InvalidPath(0)), parse(b"*"));
+    }
+
+    #[test]
+    fn reject_invalid_targets() {
+        let connect = |target| RequestTarget::parse(target, &HttpMethod::Connect);
+
+        assert_eq!(Err(ParseError::InvalidPath(7)), connect(b"docs.rs"));
+        assert_eq!(Err(ParseError::InvalidPath(8)), connect(b"docs.rs:"));
+        assert_eq!(Err(ParseError::InvalidPath(8)), connect(b"docs.rs:99999"));
+        assert_eq!(Err(ParseError::InvalidPath(1)), connect(b"[::g]:443"));
+        assert_eq!(Err(ParseError::InvalidPath(5)), connect(b"[::1]443"));
+        assert_eq!(Err(ParseError::InvalidPath(4)), connect(b"[::1"));
+        assert_eq!(Err(ParseError::InvalidPath(0)), connect(b":443"));
+        assert_eq!(Err(ParseError::InvalidPath(0)), connect(b"/index.html"));
+
+        assert_eq!(Err(ParseError::InvalidPath(0)), parse(b""));
+        assert_eq!(Err(ParseError::InvalidPath(2)), parse(b"/a#b"));
+        assert_eq!(Err(ParseError::InvalidPath(1)), parse(b"/%2"));
+        assert_eq!(Err(ParseError::InvalidPath(3)), parse(b"/a?{"));
+        assert_eq!(Err(ParseError::InvalidPath(7)), parse(b"docs.rs"));
+        assert_eq!(Err(ParseError::InvalidPath(4)), parse(b"http:/docs.rs"));
+        assert_eq!(Err(ParseError::InvalidPath(11)), parse(b"http://user@docs.rs/"));
+        assert_eq!(Err(ParseError::InvalidPath(7)), parse(b"http:///"));
+        assert_eq!(Err(ParseError::InvalidPath(0)), parse(b"1http://docs.rs/"));
+    }
+}